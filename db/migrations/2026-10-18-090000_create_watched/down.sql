@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+drop table watched_txs;
+drop table watched;
\ No newline at end of file
@@ -0,0 +1,13 @@
+CREATE TABLE watched (
+  address TEXT NOT NULL PRIMARY KEY,
+  balance BIGINT NOT NULL DEFAULT 0,
+  created TIMESTAMP NOT NULL
+);
+
+CREATE TABLE watched_txs (
+  address TEXT NOT NULL,
+  tx_hash TEXT NOT NULL,
+  block_id INTEGER NOT NULL,
+  diff BIGINT NOT NULL,
+  PRIMARY KEY (address, tx_hash)
+);
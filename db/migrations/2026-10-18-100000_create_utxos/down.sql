@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+drop table utxo_undo;
+drop table utxos;
\ No newline at end of file
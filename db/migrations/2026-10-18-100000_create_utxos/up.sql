@@ -0,0 +1,24 @@
+CREATE TABLE utxos (
+  tx_hash TEXT NOT NULL,
+  idx INTEGER NOT NULL,
+  address TEXT NOT NULL,
+  amount BIGINT NOT NULL,
+  block_id INTEGER NOT NULL,
+  PRIMARY KEY (tx_hash, idx)
+);
+
+CREATE INDEX utxos_address ON utxos (address);
+CREATE INDEX utxos_block_id ON utxos (block_id);
+
+-- outputs spent by a block, so the block can be rolled back
+CREATE TABLE utxo_undo (
+  tx_hash TEXT NOT NULL,
+  idx INTEGER NOT NULL,
+  address TEXT NOT NULL,
+  amount BIGINT NOT NULL,
+  block_id INTEGER NOT NULL,
+  spent_block_id INTEGER NOT NULL,
+  PRIMARY KEY (tx_hash, idx)
+);
+
+CREATE INDEX utxo_undo_spent_block_id ON utxo_undo (spent_block_id);
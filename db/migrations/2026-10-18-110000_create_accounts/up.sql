@@ -0,0 +1,5 @@
+CREATE TABLE accounts (
+  address TEXT NOT NULL PRIMARY KEY,
+  balance BIGINT NOT NULL DEFAULT 0,
+  nonce BIGINT NOT NULL DEFAULT 0
+);
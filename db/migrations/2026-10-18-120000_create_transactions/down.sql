@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+drop table address_txs;
+drop table transactions;
\ No newline at end of file
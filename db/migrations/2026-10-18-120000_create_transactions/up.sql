@@ -0,0 +1,17 @@
+CREATE TABLE transactions (
+  hash TEXT NOT NULL PRIMARY KEY,
+  block_id INTEGER NOT NULL,
+  tx BLOB NOT NULL
+);
+
+CREATE INDEX transactions_block_id ON transactions (block_id);
+
+CREATE TABLE address_txs (
+  address TEXT NOT NULL,
+  tx_hash TEXT NOT NULL,
+  block_id INTEGER NOT NULL,
+  diff BIGINT NOT NULL,
+  PRIMARY KEY (address, tx_hash)
+);
+
+CREATE INDEX address_txs_block_id ON address_txs (address, block_id);
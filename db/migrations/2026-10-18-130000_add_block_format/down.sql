@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blocks DROP COLUMN format;
\ No newline at end of file
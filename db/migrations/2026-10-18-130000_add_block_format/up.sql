@@ -0,0 +1,2 @@
+-- 0: json, 1: bincode, existing rows are re-encoded on startup
+ALTER TABLE blocks ADD COLUMN format INTEGER NOT NULL DEFAULT 0;
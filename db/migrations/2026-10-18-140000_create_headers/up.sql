@@ -0,0 +1,7 @@
+CREATE TABLE headers (
+  id INTEGER NOT NULL PRIMARY KEY,
+  hash TEXT NOT NULL,
+  parent_hash TEXT,
+  timestamp BIGINT NOT NULL,
+  nonce TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE bans (
+  peer_id TEXT NOT NULL PRIMARY KEY,
+  until TIMESTAMP,
+  reason TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE peers (
+  peer_id TEXT NOT NULL PRIMARY KEY,
+  address TEXT NOT NULL,
+  last_seen TIMESTAMP NOT NULL,
+  score DOUBLE NOT NULL
+);
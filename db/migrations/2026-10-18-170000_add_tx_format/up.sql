@@ -0,0 +1,2 @@
+-- 0: json, 2: same layout as txs inside blocks, existing rows are re-encoded on startup
+ALTER TABLE transactions ADD COLUMN format INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+CREATE TABLE chain_params (
+  id INTEGER NOT NULL PRIMARY KEY,
+  ledger TEXT NOT NULL
+);
@@ -0,0 +1,336 @@
+use crate::store::ChainStore;
+use crate::stored::{
+  decode_block, encode_block, Relink, CURRENT_FORMAT, FORMAT_BINCODE_LEGACY_FLAG,
+  FORMAT_KEY_ORDER_LINKS,
+};
+use bchain_domain::block::Block;
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::{HashDigest, Hashable};
+use bchain_util::result::AppResult;
+use log::info;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 4] = b"BCHN";
+/// txs carry their legacy flag since 2, the block format is recorded since 3
+const VERSION: u8 = 3;
+const CHECKSUM_LENGTH: u64 = 32;
+
+/// archive layout, integers little endian:
+/// magic, version, block format (i32), chain id (u16 length + utf8), genesis hash,
+/// first block id (i64), block count (u64), blocks (u32 length + block in that format),
+/// sha256 of everything before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveHeader {
+  pub format: i32,
+  pub chain_id: String,
+  pub genesis: HashDigest,
+  pub from: i64,
+  pub count: u64,
+}
+
+/// writes blocks `from..=to` (latest block if `to` is not given) to `path`
+pub fn export(
+  store: &dyn ChainStore,
+  chain_id: &str,
+  from: i64,
+  to: Option<i64>,
+  path: &str,
+) -> AppResult<ArchiveHeader> {
+  let genesis = match store.get_block(0)? {
+    Some(genesis) => genesis.hash_digest(),
+    None => return Err(AppError::msg("Nothing to export, chain is empty")),
+  };
+  let latest_id = store.latest_block()?.map(|b| b.id).unwrap_or_default();
+  let to = to.unwrap_or(latest_id).min(latest_id);
+  if from < 0 || from > to {
+    let message = format!("Invalid block range {}..{}", from, to);
+    return Err(AppError::msg(message));
+  }
+  let header = ArchiveHeader {
+    format: CURRENT_FORMAT,
+    chain_id: chain_id.to_owned(),
+    genesis,
+    from,
+    count: (to - from + 1) as u64,
+  };
+
+  let mut writer = HashingWriter::new(BufWriter::new(File::create(path)?));
+  write_header(&mut writer, &header)?;
+  for id in from..=to {
+    let block = store
+      .get_block(id)?
+      .ok_or_else(|| AppError::msg(format!("Block {} is missing", id)))?;
+    let bytes = encode_block(&block)?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+  }
+  let checksum = writer.hasher.finalize();
+  writer.inner.write_all(&checksum)?;
+  writer.inner.flush()?;
+  Ok(header)
+}
+
+/// validates and commits blocks from the archive at `path`, blocks of older archives
+/// are relinked like stored ones, blocks the store already has are skipped,
+/// returns number of committed blocks
+pub fn import(store: &mut dyn ChainStore, chain_id: &str, path: &str) -> AppResult<usize> {
+  let len = verify_checksum(path)?;
+  let mut reader = BufReader::new(File::open(path)?).take(len);
+  let header = read_header(&mut reader)?;
+  if header.chain_id != chain_id {
+    let message = format!("Archive is for chain {}, not {}", header.chain_id, chain_id);
+    return Err(AppError::msg(message));
+  }
+  // older archives may name the genesis by a hash over its txs in map order
+  let is_genesis = |block: &Block| match header.format < FORMAT_KEY_ORDER_LINKS {
+    true => block.hashes_in_any_order().any(|h| h == header.genesis),
+    false => block.hash_digest() == header.genesis,
+  };
+  match store.get_block(0)? {
+    Some(genesis) if !is_genesis(&genesis) => {
+      return Err(AppError::msg("Archive has a different genesis block"));
+    }
+    None if header.from != 0 => {
+      return Err(AppError::msg(
+        "Archive has to start at genesis for an empty chain",
+      ));
+    }
+    _ => (),
+  }
+
+  let mut relink = match header.from {
+    0 => Relink::default(),
+    from => {
+      let parent = store.get_block(from - 1)?;
+      let hash = parent.as_ref().map(|parent| parent.hash_digest());
+      Relink::new(parent, hash)
+    }
+  };
+  let mut imported = 0;
+  let mut skipped = 0;
+  for _ in 0..header.count {
+    let mut block = read_block(&mut reader, header.format)?;
+    if block.id == 0 && !is_genesis(&block) {
+      return Err(AppError::msg(
+        "Archive genesis block doesn't match its header",
+      ));
+    }
+    if header.format < FORMAT_KEY_ORDER_LINKS {
+      relink.relink(&mut block);
+    }
+    let latest_id = store.latest_block()?.map(|b| b.id).unwrap_or(-1);
+    if block.id <= latest_id {
+      match store.get_block(block.id)? {
+        Some(local) if local.hash_digest() == block.hash_digest() => skipped += 1,
+        _ => {
+          let message = format!("Block {} differs from the local chain", block.id);
+          return Err(AppError::msg(message));
+        }
+      }
+      continue;
+    }
+    store.commit_block(&block)?;
+    imported += 1;
+  }
+  if reader.limit() > 0 {
+    return Err(AppError::msg("Archive has trailing data"));
+  }
+  info!("Imported {} blocks, {} already present", imported, skipped);
+  Ok(imported)
+}
+
+/// checks the trailing checksum, returns length of the archive without it
+fn verify_checksum(path: &str) -> AppResult<u64> {
+  let file = File::open(path)?;
+  let len = file.metadata()?.len();
+  if len < CHECKSUM_LENGTH {
+    return Err(AppError::msg("Archive is truncated"));
+  }
+  let len = len - CHECKSUM_LENGTH;
+  let mut reader = BufReader::new(file);
+  let mut hasher = Sha256::new();
+  let mut body = (&mut reader).take(len);
+  let mut buf = [0u8; 8192];
+  loop {
+    let n = body.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    hasher.update(&buf[..n]);
+  }
+  let mut checksum = [0u8; CHECKSUM_LENGTH as usize];
+  reader.read_exact(&mut checksum)?;
+  if hasher.finalize()[..] != checksum[..] {
+    return Err(AppError::msg("Archive checksum mismatch"));
+  }
+  Ok(len)
+}
+
+fn write_header<W: Write>(writer: &mut W, header: &ArchiveHeader) -> AppResult<()> {
+  writer.write_all(MAGIC)?;
+  writer.write_all(&[VERSION])?;
+  writer.write_all(&header.format.to_le_bytes())?;
+  writer.write_all(&(header.chain_id.len() as u16).to_le_bytes())?;
+  writer.write_all(header.chain_id.as_bytes())?;
+  writer.write_all(&header.genesis[..])?;
+  writer.write_all(&header.from.to_le_bytes())?;
+  writer.write_all(&header.count.to_le_bytes())?;
+  Ok(())
+}
+
+fn read_header<R: Read>(reader: &mut R) -> AppResult<ArchiveHeader> {
+  let mut magic = [0u8; 4];
+  reader.read_exact(&mut magic)?;
+  let mut version = [0u8; 1];
+  reader.read_exact(&mut version)?;
+  // archives written before the format was recorded hold format 2 blocks
+  let format = match (&magic, version[0]) {
+    (MAGIC, 2) => FORMAT_BINCODE_LEGACY_FLAG,
+    (MAGIC, VERSION) => i32::from_le_bytes(read_array(reader)?),
+    _ => {
+      return Err(AppError::msg(
+        "Not a bchain archive, or unsupported version",
+      ))
+    }
+  };
+  let mut chain_id = vec![0u8; u16::from_le_bytes(read_array(reader)?) as usize];
+  reader.read_exact(&mut chain_id)?;
+  let genesis: [u8; 32] = read_array(reader)?;
+  Ok(ArchiveHeader {
+    format,
+    chain_id: String::from_utf8(chain_id)?,
+    genesis: genesis.to_vec().into(),
+    from: i64::from_le_bytes(read_array(reader)?),
+    count: u64::from_le_bytes(read_array(reader)?),
+  })
+}
+
+fn read_block<R: Read>(reader: &mut R, format: i32) -> AppResult<Block> {
+  let len = u32::from_le_bytes(read_array(reader)?);
+  let mut bytes = vec![0u8; len as usize];
+  reader.read_exact(&mut bytes)?;
+  decode_block(format, &bytes).ok_or_else(|| AppError::msg("Archive holds an unreadable block"))
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> AppResult<[u8; N]> {
+  let mut buf = [0u8; N];
+  reader.read_exact(&mut buf)?;
+  Ok(buf)
+}
+
+struct HashingWriter<W: Write> {
+  inner: W,
+  hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+  fn new(inner: W) -> Self {
+    HashingWriter {
+      inner,
+      hasher: Sha256::new(),
+    }
+  }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    let n = self.inner.write(buf)?;
+    self.hasher.update(&buf[..n]);
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::memory_store::MemoryStore;
+  use bchain_domain::tx::Tx;
+  use bchain_domain::wallet::Wallet;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  fn archive_path(name: &str) -> String {
+    let file = format!("bchain-{}-{}.bchain", name, std::process::id());
+    std::env::temp_dir().join(file).to_string_lossy().into()
+  }
+
+  #[async_std::test]
+  async fn export_import_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut store = MemoryStore::default();
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    store.commit_as_genesis(&genesis)?;
+    let tx = Tx::new(&wallet, &wallet.address(), 1234)?;
+    let block1 = Block::from_previous(&genesis, Some([tx]));
+    store.commit_block(&block1)?;
+    store.commit_block(&Block::from_previous(&block1, None::<Vec<Tx>>))?;
+
+    let path = archive_path("roundtrip");
+    let header = export(&store, "main", 0, None, &path)?;
+    assert_eq!(header.count, 3);
+
+    let mut imported = MemoryStore::default();
+    assert!(import(&mut imported, "test", &path).is_err());
+    assert_eq!(import(&mut imported, "main", &path)?, 3);
+    assert_eq!(imported.blocks(0..3)?, store.blocks(0..3)?);
+    assert_eq!(import(&mut imported, "main", &path)?, 0);
+
+    let partial = archive_path("partial");
+    export(&store, "main", 1, Some(2), &partial)?;
+    assert!(import(&mut MemoryStore::default(), "main", &partial).is_err());
+    std::fs::remove_file(partial)?;
+
+    let mut bytes = std::fs::read(&path)?;
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 0xff;
+    std::fs::write(&path, bytes)?;
+    assert!(import(&mut MemoryStore::default(), "main", &path).is_err());
+    std::fs::remove_file(path)?;
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn import_map_order_links_test() -> AppResult<()> {
+    // an archive of blocks hashed over their txs in map order
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    let txs = (1..4)
+      .map(|amount| Tx::new(&wallet, &wallet.address(), amount))
+      .collect::<AppResult<Vec<_>>>()?;
+    let block1 = Block::from_previous(&genesis, Some(txs));
+    let mut block2 = Block::from_previous(&block1, None::<Vec<Tx>>);
+    block2.parent_hash = block1.hashes_in_any_order().last();
+
+    let path = archive_path("map-order");
+    let header = ArchiveHeader {
+      format: FORMAT_BINCODE_LEGACY_FLAG,
+      chain_id: "main".into(),
+      genesis: genesis.hash_digest(),
+      from: 0,
+      count: 3,
+    };
+    let mut writer = HashingWriter::new(BufWriter::new(File::create(&path)?));
+    write_header(&mut writer, &header)?;
+    for block in [&genesis, &block1, &block2] {
+      let bytes = encode_block(block)?;
+      writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+      writer.write_all(&bytes)?;
+    }
+    let checksum = writer.hasher.finalize();
+    writer.inner.write_all(&checksum)?;
+    writer.inner.flush()?;
+
+    let mut store = MemoryStore::default();
+    assert_eq!(import(&mut store, "main", &path)?, 3);
+    let latest = store.latest_block()?.unwrap();
+    assert_eq!(latest.parent_hash, Some(block1.hash_digest()));
+    std::fs::remove_file(path)?;
+    Ok(())
+  }
+}
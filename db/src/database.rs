@@ -1,21 +1,44 @@
+use crate::raw_account::{account_diffs, RawAccount};
+use crate::raw_ban::RawBan;
 use crate::raw_block::RawBlock;
-use crate::schema::blocks;
-use bchain_domain::block::Block;
+use crate::raw_header::RawHeader;
+use crate::raw_peer::RawPeer;
+use crate::raw_tx::{RawAddressTx, RawTx, TxInfo};
+use crate::raw_utxo::{RawUtxo, RawUtxoUndo};
+use crate::raw_watched::{RawWatched, RawWatchedTx, Watched};
+use crate::schema::{
+  accounts, address_txs, bans, blocks, chain_params, headers, peers, transactions, utxo_undo,
+  utxos, watched, watched_txs,
+};
+use crate::storage_error::StorageError;
+use crate::store::{replayed, verify_link, verify_next, BanList, ChainIndex, ChainStore, PeerBook};
+use crate::stored::{Relink, CURRENT_FORMAT};
+use bchain_domain::address::Address;
+use bchain_domain::block::{Block, BlockHeader};
+use bchain_domain::tx::Tx;
+use bchain_domain::utxo::{Ledger, Utxo};
 use bchain_util::error::AppError;
-use bchain_util::hash_digest::Hashable;
 use bchain_util::result::AppResult;
+use chrono::NaiveDateTime;
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
 use diesel::result::Error::NotFound;
 use diesel::SqliteConnection;
 use diesel_migrations::embed_migrations;
-use log::info;
-use std::cmp::max;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::convert::TryInto;
+use std::ops::Range;
 
 embed_migrations!();
 
+pub const HISTORY_PAGE_SIZE: i64 = 20;
+
 pub struct Db {
   connection: SqliteConnection,
+  ledger: Ledger,
+  prune: Option<i64>,
 }
 
 impl Db {
@@ -25,28 +48,469 @@ impl Db {
 
   pub fn new(path: &str) -> AppResult<Self> {
     let connection = SqliteConnection::establish(path)?;
-    Ok(Db { connection })
+    connection.batch_execute("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+    Ok(Db {
+      connection,
+      ledger: Ledger::default(),
+      prune: None,
+    })
+  }
+
+  /// ledger fixed by the genesis block, recorded apart from it so pruned chains keep it,
+  /// chains started without params use the account ledger
+  fn load_ledger(&mut self) -> AppResult<()> {
+    let recorded = chain_params::table
+      .select(chain_params::ledger)
+      .first::<String>(&self.connection)
+      .optional()?;
+    self.ledger = match recorded {
+      Some(ledger) => ledger.parse()?,
+      None => match self.get_block(0)? {
+        Some(genesis) => {
+          self.record_ledger(genesis.ledger())?;
+          genesis.ledger()
+        }
+        None => Ledger::default(),
+      },
+    };
+    Ok(())
+  }
+
+  fn record_ledger(&self, ledger: Ledger) -> AppResult<()> {
+    diesel::replace_into(chain_params::table)
+      .values((
+        chain_params::id.eq(0),
+        chain_params::ledger.eq(ledger.to_string()),
+      ))
+      .execute(&self.connection)?;
+    Ok(())
   }
 
-  pub fn commit_block(&mut self, block: &Block) -> AppResult<()> {
-    if let Some(latest) = self.latest_block()? {
-      assert_eq!(latest.id + 1, block.id);
-      assert_eq!(Some(latest.hash_digest()), block.parent_hash);
+  /// keeps only the latest `depth` full blocks, older ones are reduced to headers
+  pub fn set_prune(&mut self, depth: Option<i64>) {
+    self.prune = depth;
+  }
+
+  fn first_block_id(&self) -> AppResult<Option<i64>> {
+    let first = blocks::table
+      .select(diesel::dsl::min(blocks::id))
+      .first::<Option<i32>>(&self.connection)?;
+    Ok(first.map(|id| id as i64))
+  }
+
+  fn is_pruned(&self) -> AppResult<bool> {
+    Ok(self.first_block_id()?.unwrap_or_default() > 0)
+  }
+
+  /// drops bodies of blocks older than the prune depth along with their undo data,
+  /// headers, derived state and both tx indexes stay so lookups and history still work
+  fn prune_blocks(&self, latest_id: i64) -> AppResult<()> {
+    let depth = match self.prune {
+      Some(depth) => depth,
+      None => return Ok(()),
+    };
+    let horizon = (latest_id - depth) as i32;
+    diesel::delete(blocks::table.filter(blocks::id.le(horizon))).execute(&self.connection)?;
+    diesel::delete(utxo_undo::table.filter(utxo_undo::spent_block_id.le(horizon)))
+      .execute(&self.connection)?;
+    Ok(())
+  }
+
+  fn rebuild_headers(&self) -> AppResult<()> {
+    self.connection.transaction::<_, AppError, _>(|| {
+      let mut id = self.first_block_id()?.unwrap_or_default();
+      while let Some(block) = self.get_block(id)? {
+        diesel::insert_or_ignore_into(headers::table)
+          .values(RawHeader::from(&BlockHeader::from(&block)))
+          .execute(&self.connection)?;
+        id += 1;
+      }
+      Ok(())
+    })
+  }
+
+  /// re-encodes blocks stored in an older format and relinks those hashed in map order,
+  /// returns number of converted blocks
+  pub fn convert_blocks(&mut self) -> AppResult<usize> {
+    let outdated = blocks::table
+      .filter(blocks::format.ne(CURRENT_FORMAT))
+      .order(blocks::id.asc())
+      .load::<RawBlock>(&self.connection)?;
+    let mut relink = match outdated.first() {
+      Some(first) if first.id > 0 => {
+        let id = first.id as i64 - 1;
+        let hash = self.get_header(id)?.map(|header| header.hash);
+        Relink::new(self.get_block(id)?, hash)
+      }
+      _ => Relink::default(),
+    };
+    self.connection.transaction::<_, AppError, _>(|| {
+      for raw_block in &outdated {
+        let mut block: Block = raw_block.clone().try_into().map_err(|_| {
+          AppError::msg(format!(
+            "Block {} is stored in format {} which can't be read, not opening the database",
+            raw_block.id, raw_block.format
+          ))
+        })?;
+        let relinked = relink.relink(&mut block);
+        let converted: RawBlock = (&block).try_into()?;
+        diesel::update(blocks::table.find(raw_block.id))
+          .set((
+            blocks::block.eq(converted.block),
+            blocks::format.eq(converted.format),
+          ))
+          .execute(&self.connection)?;
+        if relinked {
+          let header = RawHeader::from(&BlockHeader::from(&block));
+          diesel::update(headers::table.find(raw_block.id))
+            .set((
+              headers::hash.eq(header.hash),
+              headers::parent_hash.eq(header.parent_hash),
+            ))
+            .execute(&self.connection)?;
+        }
+      }
+      Ok(())
+    })?;
+    Ok(outdated.len())
+  }
+
+  /// re-encodes indexed txs stored in an older format, returns number of converted txs
+  pub fn convert_txs(&mut self) -> AppResult<usize> {
+    let outdated = transactions::table
+      .filter(transactions::format.ne(CURRENT_FORMAT))
+      .load::<RawTx>(&self.connection)?;
+    self.connection.transaction::<_, AppError, _>(|| {
+      for raw_tx in &outdated {
+        let tx: Tx = raw_tx.clone().try_into()?;
+        let converted = RawTx::new(&raw_tx.hash, raw_tx.block_id as i64, &tx)?;
+        diesel::update(transactions::table.find(&raw_tx.hash))
+          .set((
+            transactions::tx.eq(converted.tx),
+            transactions::format.eq(converted.format),
+          ))
+          .execute(&self.connection)?;
+      }
+      Ok(())
+    })?;
+    Ok(outdated.len())
+  }
+
+  pub fn account(&self, address: &Address) -> AppResult<RawAccount> {
+    let key = address.to_string();
+    let account = accounts::table
+      .find(&key)
+      .first::<RawAccount>(&self.connection)
+      .optional()?;
+    Ok(account.unwrap_or_else(|| RawAccount::new(&key)))
+  }
+
+  fn insert_block(&self, block: &Block) -> AppResult<()> {
+    verify_next(self.latest_block()?.as_ref(), block)?;
+    for hash in block.txs.keys() {
+      if let Some(info) = self.get_tx(hash)? {
+        return Err(replayed(hash, info.block_id));
+      }
+    }
+    let result = self.connection.transaction::<_, AppError, _>(|| {
+      let raw_block: RawBlock = block.try_into()?;
+      let query = diesel::insert_into(blocks::table).values(raw_block);
+      query.execute(&self.connection)?;
+      diesel::insert_into(headers::table)
+        .values(RawHeader::from(&BlockHeader::from(block)))
+        .execute(&self.connection)?;
+      self.apply_utxos(block)?;
+      self.apply_accounts(block, 1)?;
+      self.index_txs(block)?;
+      for address in self.watched_addresses()? {
+        self.apply_watched(&address, block)?;
+      }
+      self.prune_blocks(block.id)
+    });
+    // sqlite failures are reported as such, anything else means the block was rejected
+    result.map_err(|e| match e.downcast::<diesel::result::Error>() {
+      Ok(e) => StorageError::Backend(e.to_string()).into(),
+      Err(e) => e,
+    })
+  }
+
+  /// indexes txs of all stored blocks, used for databases created before the index existed
+  fn rebuild_tx_index(&self) -> AppResult<()> {
+    self.connection.transaction::<_, AppError, _>(|| {
+      let mut id = 0;
+      while let Some(block) = self.get_block(id)? {
+        self.index_txs(&block)?;
+        id += 1;
+      }
+      Ok(())
+    })
+  }
+
+  /// walks the chain from genesis, truncating it at the first block that
+  /// can't be decoded or doesn't link to its parent, returns number of removed blocks
+  pub fn check_integrity(&mut self) -> AppResult<usize> {
+    let ids = blocks::table
+      .select(blocks::id)
+      .order(blocks::id.asc())
+      .load::<i32>(&self.connection)?;
+    // a pruned chain starts at the header of the last pruned block
+    let mut parent = match ids.first() {
+      Some(&first) if first > 0 => self.get_header(first as i64 - 1)?,
+      _ => None,
+    };
+    let mut valid = 0;
+    for &id in &ids {
+      let block = match self.get_block(id as i64) {
+        Ok(Some(block)) => block,
+        Ok(None) => break,
+        Err(e) if matches!(StorageError::of(&e), StorageError::Corrupt { .. }) => break,
+        Err(e) => return Err(e),
+      };
+      let linked = match &parent {
+        Some(parent) => verify_link(parent, &block).is_ok(),
+        None => id == 0 || valid == 0,
+      };
+      if !linked {
+        break;
+      }
+      parent = Some(BlockHeader::from(&block));
+      valid += 1;
+    }
+    let invalid = &ids[valid..];
+    if invalid.is_empty() {
+      return Ok(0);
+    }
+    // accounts of a pruned chain can't be rebuilt, so invalid blocks are reverted one by one
+    let pruned = self.is_pruned()?;
+    let mut corrupt = false;
+    self.connection.transaction::<_, AppError, _>(|| {
+      for &id in invalid.iter().rev() {
+        match self.get_block(id as i64) {
+          Ok(Some(block)) if pruned => self.apply_accounts(&block, -1)?,
+          Ok(_) => (),
+          Err(e) if matches!(StorageError::of(&e), StorageError::Corrupt { .. }) => corrupt = true,
+          Err(e) => return Err(e),
+        }
+        self.remove_block(id)?;
+      }
+      if !pruned {
+        self.rebuild_account_index()?;
+      }
+      Ok(())
+    })?;
+    if corrupt && pruned {
+      warn!("Accounts of a pruned chain can't be rebuilt, balances may be off");
+    }
+    Ok(invalid.len())
+  }
+
+  /// recomputes every account from the stored blocks,
+  /// returns how many accounts were off
+  fn rebuild_account_index(&self) -> AppResult<usize> {
+    let mut expected = BTreeMap::<String, (i64, i64)>::new();
+    let mut id = 0;
+    while let Some(block) = self.get_block(id)? {
+      for (address, (balance, nonce)) in account_diffs(&block) {
+        let entry = expected.entry(address).or_insert((0, 0));
+        entry.0 += balance;
+        entry.1 += nonce;
+      }
+      id += 1;
+    }
+    let mut actual: BTreeMap<_, _> = accounts::table
+      .load::<RawAccount>(&self.connection)?
+      .into_iter()
+      .map(|a| (a.address, (a.balance, a.nonce)))
+      .collect();
+    let mut mismatched = 0;
+    for (address, state) in &expected {
+      if actual.remove(address).unwrap_or_default() != *state {
+        mismatched += 1;
+      }
+    }
+    mismatched += actual.values().filter(|&&state| state != (0, 0)).count();
+
+    self.connection.transaction::<_, AppError, _>(|| {
+      diesel::delete(accounts::table).execute(&self.connection)?;
+      for (address, (balance, nonce)) in expected {
+        let account = RawAccount {
+          address,
+          balance,
+          nonce,
+        };
+        diesel::insert_into(accounts::table)
+          .values(account)
+          .execute(&self.connection)?;
+      }
+      Ok(())
+    })?;
+    Ok(mismatched)
+  }
+
+  /// deletes block `id` with everything indexed under it, except accounts,
+  /// which are keyed by address and have to be reverted by the caller
+  fn remove_block(&self, id: i32) -> AppResult<()> {
+    diesel::delete(utxos::table.filter(utxos::block_id.eq(id))).execute(&self.connection)?;
+    let spent = utxo_undo::table
+      .filter(utxo_undo::spent_block_id.eq(id))
+      .load::<RawUtxoUndo>(&self.connection)?;
+    for undo in spent {
+      let query = diesel::insert_into(utxos::table).values(RawUtxo::from(undo));
+      query.execute(&self.connection)?;
+    }
+    diesel::delete(utxo_undo::table.filter(utxo_undo::spent_block_id.eq(id)))
+      .execute(&self.connection)?;
+    diesel::delete(transactions::table.filter(transactions::block_id.eq(id)))
+      .execute(&self.connection)?;
+    diesel::delete(address_txs::table.filter(address_txs::block_id.eq(id)))
+      .execute(&self.connection)?;
+
+    let watched_diffs = watched_txs::table
+      .filter(watched_txs::block_id.eq(id))
+      .load::<RawWatchedTx>(&self.connection)?;
+    for w in watched_diffs {
+      diesel::update(watched::table.find(&w.address))
+        .set(watched::balance.eq(watched::balance - w.diff))
+        .execute(&self.connection)?;
+    }
+    diesel::delete(watched_txs::table.filter(watched_txs::block_id.eq(id)))
+      .execute(&self.connection)?;
+
+    diesel::delete(blocks::table.find(id)).execute(&self.connection)?;
+    diesel::delete(headers::table.find(id)).execute(&self.connection)?;
+    Ok(())
+  }
+
+  fn index_txs(&self, block: &Block) -> AppResult<()> {
+    for (tx_hash, tx) in &block.txs {
+      diesel::insert_or_ignore_into(transactions::table)
+        .values(RawTx::new(tx_hash, block.id, tx)?)
+        .execute(&self.connection)?;
+      for address in touched_addresses(tx) {
+        let raw = RawAddressTx {
+          address: address.to_string(),
+          tx_hash: tx_hash.clone(),
+          block_id: block.id as i32,
+          diff: tx.diff_for_address(address),
+        };
+        diesel::insert_or_ignore_into(address_txs::table)
+          .values(raw)
+          .execute(&self.connection)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// adds (`sign` = 1) or reverts (`sign` = -1) balance and nonce changes of the block
+  fn apply_accounts(&self, block: &Block, sign: i64) -> AppResult<()> {
+    for (address, (balance, nonce)) in account_diffs(block) {
+      diesel::insert_or_ignore_into(accounts::table)
+        .values(RawAccount::new(&address))
+        .execute(&self.connection)?;
+      diesel::update(accounts::table.find(&address))
+        .set((
+          accounts::balance.eq(accounts::balance + sign * balance),
+          accounts::nonce.eq(accounts::nonce + sign * nonce),
+        ))
+        .execute(&self.connection)?;
     }
-    let raw_block: RawBlock = block.try_into()?;
-    let query = diesel::insert_into(blocks::table).values(raw_block);
-    query.execute(&self.connection)?;
-    info!("Commited {}", block);
     Ok(())
   }
 
-  pub fn commit_as_genesis(&mut self, block: &Block) -> AppResult<()> {
-    let query = diesel::delete(blocks::table);
-    query.execute(&self.connection)?;
-    self.commit_block(block)
+  /// spends inputs and creates outputs of every tx in the block,
+  /// inputs have to come from earlier blocks, so tx order inside the block doesn't matter
+  fn apply_utxos(&self, block: &Block) -> AppResult<()> {
+    for (tx_hash, tx) in &block.txs {
+      if tx.inputs().is_empty() {
+        if self.ledger == Ledger::Utxo && !tx.is_coinbase() {
+          return Err(AppError::msg(format!("Tx {} spends no outputs", tx_hash)));
+        }
+        continue;
+      }
+      let mut spent = 0u64;
+      for input in tx.inputs() {
+        let key = (input.tx.to_string(), input.index as i32);
+        let utxo = utxos::table
+          .find(key.clone())
+          .first::<RawUtxo>(&self.connection)
+          .optional()?;
+        let utxo = match utxo {
+          Some(utxo) if utxo.address == tx.sender().to_string() => utxo,
+          Some(_) => {
+            let message = format!("Tx {} spends output {} it doesnt own", tx_hash, input);
+            return Err(AppError::msg(message));
+          }
+          None => {
+            let message = format!("Tx {} spends missing output {}", tx_hash, input);
+            return Err(AppError::msg(message));
+          }
+        };
+        spent = spent
+          .checked_add(utxo.amount as u64)
+          .ok_or_else(|| AppError::msg(format!("Tx {} inputs overflow", tx_hash)))?;
+        diesel::delete(utxos::table.find(key)).execute(&self.connection)?;
+        let undo = RawUtxoUndo::new(utxo, block.id as i32);
+        diesel::insert_into(utxo_undo::table)
+          .values(undo)
+          .execute(&self.connection)?;
+      }
+      if spent != tx.total()? {
+        let message = format!("Tx {} inputs dont match its outputs", tx_hash);
+        return Err(AppError::msg(message));
+      }
+    }
+    for (tx_hash, tx) in &block.txs {
+      for (idx, output) in tx.outputs().iter().enumerate() {
+        let utxo = RawUtxo {
+          tx_hash: tx_hash.clone(),
+          idx: idx as i32,
+          address: output.receiver.to_string(),
+          amount: output.amount as i64,
+          block_id: block.id as i32,
+        };
+        diesel::insert_into(utxos::table)
+          .values(utxo)
+          .execute(&self.connection)?;
+      }
+    }
+    Ok(())
   }
 
-  pub fn latest_block(&mut self) -> AppResult<Option<Block>> {
+  fn watched_addresses(&self) -> AppResult<Vec<Address>> {
+    let addresses = watched::table
+      .select(watched::address)
+      .load::<String>(&self.connection)?;
+    addresses.iter().map(|a| a.parse()).collect()
+  }
+
+  fn apply_watched(&self, address: &Address, block: &Block) -> AppResult<()> {
+    let key = address.to_string();
+    for (tx_hash, tx) in &block.txs {
+      let diff = tx.diff_for_address(address);
+      if diff == 0 {
+        continue;
+      }
+      let raw_tx = RawWatchedTx {
+        address: key.clone(),
+        tx_hash: tx_hash.clone(),
+        block_id: block.id as i32,
+        diff,
+      };
+      let inserted = diesel::insert_or_ignore_into(watched_txs::table)
+        .values(raw_tx)
+        .execute(&self.connection)?;
+      if inserted > 0 {
+        diesel::update(watched::table.find(&key))
+          .set(watched::balance.eq(watched::balance + diff))
+          .execute(&self.connection)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl ChainStore for Db {
+  fn latest_block(&self) -> AppResult<Option<Block>> {
     let query = blocks::table
       .select(blocks::all_columns)
       .order(blocks::id.desc())
@@ -55,28 +519,11 @@ impl Db {
     match query.first::<RawBlock>(&self.connection) {
       Ok(res) => Ok(Some(res.try_into()?)),
       Err(NotFound) => Ok(None),
-      Err(e) => Err(AppError::msg(format!("{:?}", e))),
+      Err(e) => Err(StorageError::Backend(e.to_string()).into()),
     }
   }
 
-  pub fn recent_blocks(&mut self, num_blocks: i64) -> AppResult<Vec<Block>> {
-    let mut blocks = vec![];
-    let latest = self.latest_block()?;
-    if let Some(block) = latest {
-      let latest_id = block.id;
-      blocks.push(block);
-      if latest_id > 0 {
-        let rest = ((max(0, latest_id - num_blocks))..latest_id).rev();
-        for id in rest {
-          blocks.push(self.get_block(id)?.unwrap());
-        }
-      }
-    }
-
-    Ok(blocks)
-  }
-
-  pub fn get_block(&mut self, id: i64) -> AppResult<Option<Block>> {
+  fn get_block(&self, id: i64) -> AppResult<Option<Block>> {
     let query = blocks::table
       .select(blocks::all_columns)
       .filter(blocks::id.eq(id as i32));
@@ -84,14 +531,713 @@ impl Db {
     match query.first::<RawBlock>(&self.connection) {
       Ok(res) => Ok(Some(res.try_into()?)),
       Err(NotFound) => Ok(None),
-      Err(e) => Err(AppError::msg(format!("{:?}", e))),
+      Err(e) => Err(StorageError::Backend(e.to_string()).into()),
     }
   }
+
+  fn commit_block(&mut self, block: &Block) -> AppResult<()> {
+    self.insert_block(block)?;
+    info!("Commited {}", block);
+    Ok(())
+  }
+
+  fn commit_as_genesis(&mut self, block: &Block) -> AppResult<()> {
+    let previous = std::mem::replace(&mut self.ledger, block.ledger());
+    let result = self.connection.transaction::<_, AppError, _>(|| {
+      diesel::delete(blocks::table).execute(&self.connection)?;
+      diesel::delete(headers::table).execute(&self.connection)?;
+      diesel::delete(utxos::table).execute(&self.connection)?;
+      diesel::delete(utxo_undo::table).execute(&self.connection)?;
+      diesel::delete(accounts::table).execute(&self.connection)?;
+      diesel::delete(transactions::table).execute(&self.connection)?;
+      diesel::delete(address_txs::table).execute(&self.connection)?;
+      diesel::delete(watched_txs::table).execute(&self.connection)?;
+      diesel::update(watched::table)
+        .set(watched::balance.eq(0))
+        .execute(&self.connection)?;
+      self.record_ledger(block.ledger())?;
+      self.insert_block(block)
+    });
+    if result.is_err() {
+      self.ledger = previous;
+    }
+    result?;
+    info!("Commited {}", block);
+    Ok(())
+  }
+
+  /// removes the latest block, restoring the outputs it spent
+  fn rollback_block(&mut self) -> AppResult<Option<Block>> {
+    let latest = match self.latest_block()? {
+      Some(latest) => latest,
+      None => return Ok(None),
+    };
+    if latest.id > 0 && self.get_block(latest.id - 1)?.is_none() {
+      let message = format!("Can't roll back {}, older blocks are pruned", latest);
+      return Err(AppError::msg(message));
+    }
+    self.connection.transaction::<_, AppError, _>(|| {
+      self.apply_accounts(&latest, -1)?;
+      self.remove_block(latest.id as i32)
+    })?;
+    info!("Rolled back {}", latest);
+    Ok(Some(latest))
+  }
+
+  fn ledger(&self) -> AppResult<Ledger> {
+    Ok(self.ledger)
+  }
+
+  fn balance(&self, address: &Address) -> AppResult<i64> {
+    Ok(self.account(address)?.balance)
+  }
+
+  fn get_tx(&self, hash: &str) -> AppResult<Option<TxInfo>> {
+    let raw_tx = transactions::table
+      .find(hash)
+      .first::<RawTx>(&self.connection)
+      .optional()?;
+    let raw_tx = match raw_tx {
+      Some(raw_tx) => raw_tx,
+      None => return Ok(None),
+    };
+    let latest_id = self.latest_block()?.map(|b| b.id).unwrap_or_default();
+    let block_id = raw_tx.block_id as i64;
+    Ok(Some(TxInfo {
+      tx: raw_tx.try_into()?,
+      block_id,
+      confirmations: latest_id - block_id + 1,
+    }))
+  }
+
+  /// txs touching `address`, newest first, `HISTORY_PAGE_SIZE` per page
+  fn history(&self, address: &Address, page: i64) -> AppResult<Vec<RawAddressTx>> {
+    let history = address_txs::table
+      .filter(address_txs::address.eq(address.to_string()))
+      .order((address_txs::block_id.desc(), address_txs::tx_hash.asc()))
+      .limit(HISTORY_PAGE_SIZE)
+      .offset(page * HISTORY_PAGE_SIZE)
+      .load::<RawAddressTx>(&self.connection)?;
+    Ok(history)
+  }
+
+  fn history_len(&self, address: &Address) -> AppResult<i64> {
+    let len = address_txs::table
+      .filter(address_txs::address.eq(address.to_string()))
+      .count()
+      .get_result(&self.connection)?;
+    Ok(len)
+  }
+
+  fn index(&self) -> Option<&dyn ChainIndex> {
+    Some(self)
+  }
+
+  fn index_mut(&mut self) -> Option<&mut dyn ChainIndex> {
+    Some(self)
+  }
+
+  fn peer_book(&mut self) -> Option<&mut dyn PeerBook> {
+    Some(self)
+  }
+
+  fn ban_list(&mut self) -> Option<&mut dyn BanList> {
+    Some(self)
+  }
+
+  fn get_header(&self, id: i64) -> AppResult<Option<BlockHeader>> {
+    let header = headers::table
+      .find(id as i32)
+      .first::<RawHeader>(&self.connection)
+      .optional()?;
+    header.map(|h| h.try_into()).transpose()
+  }
+
+  fn serving_range(&self) -> AppResult<Option<(i64, i64)>> {
+    let first = self.first_block_id()?;
+    let latest = self.latest_block()?;
+    Ok(first.zip(latest).map(|(first, latest)| (first, latest.id)))
+  }
+
+  fn blocks(&self, range: Range<i64>) -> AppResult<Vec<Block>> {
+    let raw_blocks = blocks::table
+      .filter(blocks::id.ge(range.start as i32))
+      .filter(blocks::id.lt(range.end as i32))
+      .order(blocks::id.asc())
+      .load::<RawBlock>(&self.connection)?;
+    raw_blocks.into_iter().map(|raw| raw.try_into()).collect()
+  }
+}
+
+impl ChainIndex for Db {
+  /// recomputes accounts from blocks, replacing the table,
+  /// returns number of accounts that were out of sync
+  fn rebuild_accounts(&mut self) -> AppResult<usize> {
+    if self.is_pruned()? {
+      return Err(AppError::msg("Accounts can't be rebuilt on a pruned chain"));
+    }
+    self.rebuild_account_index()
+  }
+
+  fn unspent(&self, address: &Address) -> AppResult<Vec<Utxo>> {
+    let unspent = utxos::table
+      .filter(utxos::address.eq(address.to_string()))
+      .order((
+        utxos::block_id.asc(),
+        utxos::tx_hash.asc(),
+        utxos::idx.asc(),
+      ))
+      .load::<RawUtxo>(&self.connection)?;
+    unspent.into_iter().map(|raw| raw.try_into()).collect()
+  }
+
+  fn utxo_balance(&self, address: &Address) -> AppResult<i64> {
+    let amounts = utxos::table
+      .select(utxos::amount)
+      .filter(utxos::address.eq(address.to_string()))
+      .load::<i64>(&self.connection)?;
+    Ok(amounts.iter().sum())
+  }
+
+  /// starts tracking `address`, backfilling its history from existing blocks
+  fn watch(&mut self, address: &Address) -> AppResult<()> {
+    self.connection.transaction::<_, AppError, _>(|| {
+      let query = diesel::insert_or_ignore_into(watched::table).values(RawWatched::from(address));
+      if query.execute(&self.connection)? > 0 {
+        if self.is_pruned()? {
+          return Err(AppError::msg(
+            "Watched history can't be backfilled on a pruned chain",
+          ));
+        }
+        let mut id = 0;
+        while let Some(block) = self.get_block(id)? {
+          self.apply_watched(address, &block)?;
+          id += 1;
+        }
+      }
+      Ok(())
+    })
+  }
+
+  fn unwatch(&mut self, address: &Address) -> AppResult<bool> {
+    let key = address.to_string();
+    diesel::delete(watched_txs::table.filter(watched_txs::address.eq(&key)))
+      .execute(&self.connection)?;
+    let deleted = diesel::delete(watched::table.find(&key)).execute(&self.connection)?;
+    Ok(deleted > 0)
+  }
+
+  fn watched(&self) -> AppResult<Vec<Watched>> {
+    let raw_watched = watched::table
+      .order(watched::created.asc())
+      .load::<RawWatched>(&self.connection)?;
+    let mut res = vec![];
+    for raw in raw_watched {
+      let txs = watched_txs::table
+        .filter(watched_txs::address.eq(&raw.address))
+        .count()
+        .get_result(&self.connection)?;
+      res.push(Watched {
+        address: raw.address.parse()?,
+        balance: raw.balance,
+        txs,
+      });
+    }
+    Ok(res)
+  }
+
+  fn watched_history(&self, address: &Address) -> AppResult<Vec<RawWatchedTx>> {
+    let history = watched_txs::table
+      .filter(watched_txs::address.eq(address.to_string()))
+      .order(watched_txs::block_id.desc())
+      .load::<RawWatchedTx>(&self.connection)?;
+    Ok(history)
+  }
+}
+
+impl BanList for Db {
+  fn ban(&mut self, ban: &RawBan) -> AppResult<()> {
+    diesel::replace_into(bans::table)
+      .values(ban)
+      .execute(&self.connection)?;
+    Ok(())
+  }
+
+  fn unban(&mut self, peer_id: &str) -> AppResult<bool> {
+    let deleted = diesel::delete(bans::table.find(peer_id)).execute(&self.connection)?;
+    Ok(deleted > 0)
+  }
+
+  fn bans(&self) -> AppResult<Vec<RawBan>> {
+    let bans = bans::table.load::<RawBan>(&self.connection)?;
+    Ok(bans.into_iter().filter(|ban| ban.is_active()).collect())
+  }
+}
+
+impl PeerBook for Db {
+  fn save_peer(&mut self, peer: &RawPeer) -> AppResult<()> {
+    diesel::replace_into(peers::table)
+      .values(peer)
+      .execute(&self.connection)?;
+    Ok(())
+  }
+
+  fn peers(&self) -> AppResult<Vec<RawPeer>> {
+    let peers = peers::table
+      .order(peers::last_seen.desc())
+      .load::<RawPeer>(&self.connection)?;
+    Ok(peers)
+  }
+
+  fn remove_stale_peers(&mut self, before: NaiveDateTime) -> AppResult<usize> {
+    let stale = peers::table.filter(peers::last_seen.lt(before));
+    Ok(diesel::delete(stale).execute(&self.connection)?)
+  }
 }
 
 pub fn create_db(path: &str) -> AppResult<Db> {
-  let db = Db::new(path)?;
+  let mut db = Db::new(path)?;
   info!("Using block chain database {}", path);
   embedded_migrations::run(db.raw_connection()?)?;
+  prepare_db(&mut db)?;
   Ok(db)
 }
+
+/// blocks are converted before the integrity check, so it never sees an older format
+/// and a database it can't read is refused instead of truncated
+fn prepare_db(db: &mut Db) -> AppResult<()> {
+  let converted = db.convert_blocks()?;
+  if converted > 0 {
+    info!(
+      "Converted {} blocks to format {}",
+      converted, CURRENT_FORMAT
+    );
+  }
+  let converted = db.convert_txs()?;
+  if converted > 0 {
+    info!("Converted {} txs to format {}", converted, CURRENT_FORMAT);
+  }
+  let removed = db.check_integrity()?;
+  if removed > 0 {
+    warn!(
+      "Removed {} invalid blocks from the end of the chain",
+      removed
+    );
+  }
+  db.load_ledger()?;
+  if db.latest_block()?.is_some() {
+    let accounts: i64 = accounts::table.count().get_result(&db.connection)?;
+    if accounts == 0 {
+      db.rebuild_accounts()?;
+      info!("Built account index from existing blocks");
+    }
+    let headers: i64 = headers::table.count().get_result(&db.connection)?;
+    if headers == 0 {
+      db.rebuild_headers()?;
+      info!("Built headers from existing blocks");
+    }
+    let txs: i64 = transactions::table.count().get_result(&db.connection)?;
+    if txs == 0 {
+      db.rebuild_tx_index()?;
+      info!("Built tx index from existing blocks");
+    }
+  }
+  Ok(())
+}
+
+pub(crate) fn touched_addresses(tx: &Tx) -> HashSet<&Address> {
+  let mut touched: HashSet<_> = tx.outputs().iter().map(|o| &o.receiver).collect();
+  touched.insert(tx.sender());
+  touched
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_domain::tx::{Tx, TxOutput};
+  use bchain_domain::utxo::OutPoint;
+  use bchain_domain::wallet::Wallet;
+  use bchain_util::hash_digest::Hashable;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn watched_balance_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut db = create_db(":memory:")?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    db.commit_as_genesis(&genesis)?;
+
+    db.watch(&wallet.address())?;
+    let watched = db.watched()?;
+    assert_eq!(watched[0].balance, 1_000_000);
+    assert_eq!(watched[0].txs, 1);
+
+    let tx = Tx::new(&wallet, &Address::default(), 1234)?;
+    db.commit_block(&Block::from_previous(&genesis, Some([tx])))?;
+    let watched = db.watched()?;
+    assert_eq!(watched[0].balance, 1_000_000 - 1234);
+    assert_eq!(watched[0].txs, 2);
+    assert_eq!(db.watched_history(&wallet.address())?[0].diff, -1234);
+
+    assert!(db.unwatch(&wallet.address())?);
+    assert!(db.watched()?.is_empty());
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn accounts_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let other = Address::default();
+    let mut db = create_db(":memory:")?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    db.commit_as_genesis(&genesis)?;
+    let tx = Tx::new(&wallet, &other, 1234)?;
+    let block1 = Block::from_previous(&genesis, Some([tx]));
+    db.commit_block(&block1)?;
+
+    let account = db.account(&wallet.address())?;
+    assert_eq!(account.balance, 1_000_000 - 1234);
+    assert_eq!(account.nonce, 1);
+    assert_eq!(db.rebuild_accounts()?, 0);
+
+    diesel::update(accounts::table)
+      .set(accounts::balance.eq(0))
+      .execute(&db.connection)?;
+    assert!(db.rebuild_accounts()? > 0);
+    assert_eq!(db.balance(&wallet.address())?, 1_000_000 - 1234);
+
+    db.rollback_block()?;
+    assert_eq!(db.account(&wallet.address())?.nonce, 0);
+    assert_eq!(db.balance(&wallet.address())?, 1_000_000);
+    assert_eq!(db.rebuild_accounts()?, 0);
+    Ok(())
+  }
+
+  #[test]
+  fn convert_baseline_blocks_test() -> AppResult<()> {
+    // rows as the first release wrote them, json without a format
+    let mut db = Db::new(":memory:")?;
+    embedded_migrations::run(db.raw_connection()?)?;
+    let fixture = include_str!("../fixtures/baseline_blocks.jsonl");
+    for (id, json) in fixture.lines().enumerate() {
+      diesel::insert_into(blocks::table)
+        .values((
+          blocks::id.eq(id as i32),
+          blocks::block.eq(json.as_bytes()),
+          blocks::created.eq(NaiveDateTime::default()),
+        ))
+        .execute(&db.connection)?;
+    }
+
+    prepare_db(&mut db)?;
+    let latest = db.latest_block()?.unwrap();
+    assert_eq!(latest.id, 1);
+    let tx = latest.txs.values().next().unwrap();
+    assert_eq!(db.balance(tx.sender())?, 1_000_000 - 1234);
+    assert_eq!(db.history_len(tx.sender())?, 2);
+    assert_eq!(db.get_header(1)?, Some(BlockHeader::from(&latest)));
+    assert_eq!(db.convert_blocks()?, 0);
+    assert_eq!(db.check_integrity()?, 0);
+
+    // a format that can't be read is refused, nothing is removed
+    diesel::update(blocks::table.find(1))
+      .set(blocks::format.eq(crate::stored::FORMAT_BINCODE))
+      .execute(&db.connection)?;
+    assert!(prepare_db(&mut db).is_err());
+    let rows: i64 = blocks::table.count().get_result(&db.connection)?;
+    assert_eq!(rows, 2);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn convert_blocks_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut db = create_db(":memory:")?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    db.commit_as_genesis(&genesis)?;
+    diesel::update(blocks::table)
+      .set((
+        blocks::block.eq(serde_json::to_vec(&genesis)?),
+        blocks::format.eq(crate::stored::FORMAT_JSON),
+      ))
+      .execute(&db.connection)?;
+
+    assert_eq!(db.convert_blocks()?, 1);
+    assert_eq!(db.convert_blocks()?, 0);
+    assert_eq!(db.get_block(0)?, Some(genesis));
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn relink_map_order_hashes_test() -> AppResult<()> {
+    // a chain written while blocks were hashed over txs in map order
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut db = create_db(":memory:")?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    db.commit_as_genesis(&genesis)?;
+    let txs = (1..4)
+      .map(|amount| Tx::new(&wallet, &Address::default(), amount * 1000))
+      .collect::<AppResult<Vec<_>>>()?;
+    let block1 = Block::from_previous(&genesis, Some(txs));
+    let mut block2 = Block::from_previous(&block1, Some([Tx::new(&wallet, &wallet.address(), 1)?]));
+    block2.parent_hash = block1.hashes_in_any_order().last();
+    assert_ne!(block2.parent_hash, Some(block1.hash_digest()));
+    let block3 = Block::from_previous(&block2, None::<Vec<Tx>>);
+    for block in [&block1, &block2, &block3] {
+      diesel::insert_into(blocks::table)
+        .values(RawBlock {
+          format: crate::stored::FORMAT_BINCODE_LEGACY_FLAG,
+          ..block.try_into()?
+        })
+        .execute(&db.connection)?;
+      diesel::insert_into(headers::table)
+        .values(RawHeader::from(&BlockHeader::from(block)))
+        .execute(&db.connection)?;
+    }
+
+    prepare_db(&mut db)?;
+    let relinked2 = db.get_block(2)?.unwrap();
+    let relinked3 = db.latest_block()?.unwrap();
+    assert_eq!(relinked3.id, 3);
+    assert_eq!(db.get_block(1)?, Some(block1.clone()));
+    assert_eq!(relinked2.parent_hash, Some(block1.hash_digest()));
+    assert_eq!(relinked3.parent_hash, Some(relinked2.hash_digest()));
+    assert_eq!(db.get_header(3)?, Some(BlockHeader::from(&relinked3)));
+    assert_eq!(db.convert_blocks()?, 0);
+    assert_eq!(db.check_integrity()?, 0);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn convert_txs_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut db = create_db(":memory:")?;
+    let coinbase = wallet.new_coinbase_tx(1_000_000)?;
+    db.commit_as_genesis(&Block::new(Some([coinbase.clone()])))?;
+    let hash = coinbase.hash_digest().to_string();
+    diesel::update(transactions::table.find(&hash))
+      .set((
+        transactions::tx.eq(serde_json::to_vec(&coinbase)?),
+        transactions::format.eq(crate::stored::FORMAT_JSON),
+      ))
+      .execute(&db.connection)?;
+    assert_eq!(
+      db.get_tx(&hash)?.map(|info| info.tx),
+      Some(coinbase.clone())
+    );
+
+    assert_eq!(db.convert_txs()?, 1);
+    assert_eq!(db.convert_txs()?, 0);
+    assert_eq!(db.get_tx(&hash)?.map(|info| info.tx), Some(coinbase));
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn check_integrity_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut db = create_db(":memory:")?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    db.commit_as_genesis(&genesis)?;
+    let tx = Tx::new(&wallet, &Address::default(), 1234)?;
+    let block1 = Block::from_previous(&genesis, Some([tx]));
+    db.commit_block(&block1)?;
+    let block2 = Block::from_previous(&block1, None::<Vec<Tx>>);
+    db.commit_block(&block2)?;
+    assert_eq!(db.check_integrity()?, 0);
+
+    // a block that can't be loaded isn't mistaken for a corrupt one
+    diesel::sql_query("UPDATE blocks SET created = 'never' WHERE id = 2")
+      .execute(&db.connection)?;
+    assert!(db.check_integrity().is_err());
+    let stored: i64 = blocks::table.count().get_result(&db.connection)?;
+    assert_eq!(stored, 3);
+    diesel::update(blocks::table.find(2))
+      .set(blocks::created.eq(chrono::Utc::now().naive_utc()))
+      .execute(&db.connection)?;
+
+    let unlinked = Block::from_previous(&genesis, None::<Vec<Tx>>);
+    diesel::update(blocks::table.find(2))
+      .set(blocks::block.eq(crate::stored::encode_block(&unlinked)?))
+      .execute(&db.connection)?;
+    assert_eq!(db.check_integrity()?, 1);
+
+    diesel::update(blocks::table.find(1))
+      .set(blocks::block.eq(vec![1, 2, 3]))
+      .execute(&db.connection)?;
+    assert_eq!(db.check_integrity()?, 1);
+    assert_eq!(db.latest_block()?, Some(genesis));
+    assert_eq!(db.balance(&wallet.address())?, 1_000_000);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn prune_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut db = create_db(":memory:")?;
+    db.set_prune(Some(2));
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    db.commit_as_genesis(&genesis)?;
+    let mut latest = genesis.clone();
+    let mut first_tx = None;
+    for amount in 1..5 {
+      let tx = Tx::new(&wallet, &Address::default(), amount * 1000)?;
+      first_tx.get_or_insert_with(|| tx.hash_digest().to_string());
+      latest = Block::from_previous(&latest, Some([tx]));
+      db.commit_block(&latest)?;
+    }
+
+    assert_eq!(db.serving_range()?, Some((3, 4)));
+    let first_tx = first_tx.unwrap_or_default();
+    assert_eq!(db.get_tx(&first_tx)?.map(|info| info.block_id), Some(1));
+    let history = db.history(&Address::default(), 0)?;
+    assert!(history.iter().any(|entry| entry.tx_hash == first_tx));
+    assert_eq!(db.get_block(0)?, None);
+    assert_eq!(db.get_block(2)?, None);
+    assert_eq!(
+      db.get_header(0)?.map(|h| h.hash),
+      Some(genesis.hash_digest())
+    );
+    assert_eq!(db.balance(&wallet.address())?, 1_000_000 - 10_000);
+    assert_eq!(db.check_integrity()?, 0);
+    assert!(db.rebuild_accounts().is_err());
+
+    db.rollback_block()?;
+    assert_eq!(db.balance(&wallet.address())?, 1_000_000 - 6000);
+    assert!(db.rollback_block().is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn bans_test() -> AppResult<()> {
+    let mut db = create_db(":memory:")?;
+    let now = chrono::Utc::now().naive_utc();
+    let ban = RawBan {
+      peer_id: "peer1".into(),
+      until: None,
+      reason: "invalid block".into(),
+    };
+    db.ban(&ban)?;
+    db.ban(&RawBan {
+      peer_id: "peer2".into(),
+      until: Some(now - chrono::Duration::minutes(1)),
+      reason: "spam".into(),
+    })?;
+    assert_eq!(db.bans()?, vec![ban]);
+    assert!(db.unban("peer1")?);
+    assert!(!db.unban("peer1")?);
+    assert!(db.bans()?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn peers_test() -> AppResult<()> {
+    let mut db = create_db(":memory:")?;
+    let now = chrono::Utc::now().naive_utc();
+    let peer = |peer_id: &str, age: i64, score: f64| RawPeer {
+      peer_id: peer_id.into(),
+      address: "/ip4/127.0.0.1/tcp/4001".into(),
+      last_seen: now - chrono::Duration::days(age),
+      score,
+    };
+    db.save_peer(&peer("peer1", 20, 0.0))?;
+    db.save_peer(&peer("peer2", 3, 5.0))?;
+    db.save_peer(&peer("peer1", 1, 10.0))?;
+    assert_eq!(
+      db.peers()?,
+      vec![peer("peer1", 1, 10.0), peer("peer2", 3, 5.0)]
+    );
+    assert_eq!(db.remove_stale_peers(now - chrono::Duration::days(2))?, 1);
+    assert_eq!(db.peers()?, vec![peer("peer1", 1, 10.0)]);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn tx_index_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let other = Address::default();
+    let mut db = create_db(":memory:")?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    db.commit_as_genesis(&genesis)?;
+    let tx = Tx::new(&wallet, &other, 1234)?;
+    let tx_hash = tx.hash_digest().to_string();
+    let block1 = Block::from_previous(&genesis, Some([tx.clone()]));
+    db.commit_block(&block1)?;
+    let replay = Block::from_previous(&block1, Some([tx.clone()]));
+    assert!(db.commit_block(&replay).is_err());
+    db.commit_block(&Block::from_previous(&block1, None::<Vec<Tx>>))?;
+
+    let info = db.get_tx(&tx_hash)?.unwrap();
+    assert_eq!(info.tx, tx);
+    assert_eq!(info.block_id, 1);
+    assert_eq!(info.confirmations, 2);
+
+    let history = db.history(&wallet.address(), 0)?;
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].tx_hash, tx_hash);
+    assert_eq!(history[0].diff, -1234);
+    assert!(db.history(&wallet.address(), 1)?.is_empty());
+
+    // the account index is rebuilt without touching the tx index
+    db.rebuild_accounts()?;
+    assert_eq!(db.get_tx(&tx_hash)?.map(|info| info.tx), Some(tx));
+    assert_eq!(db.history(&wallet.address(), 0)?.len(), 2);
+
+    db.rollback_block()?;
+    db.rollback_block()?;
+    assert_eq!(db.get_tx(&tx_hash)?, None);
+    assert_eq!(db.history_len(&wallet.address())?, 1);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn utxo_spend_and_rollback_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let other = Address::default();
+    let mut db = create_db(":memory:")?;
+    let coinbase = wallet.new_coinbase_tx(1_000_000)?;
+    let coinbase_out = OutPoint::new(&coinbase.hash_digest(), 0);
+    let genesis = Block::genesis(Ledger::Utxo, Some([coinbase]));
+    db.commit_as_genesis(&genesis)?;
+    assert_eq!(db.ledger()?, Ledger::Utxo);
+    // recorded apart from the genesis block, which may get pruned
+    diesel::delete(blocks::table.find(0)).execute(&db.connection)?;
+    db.load_ledger()?;
+    assert_eq!(db.ledger()?, Ledger::Utxo);
+    let raw_genesis: RawBlock = (&genesis).try_into()?;
+    diesel::insert_into(blocks::table)
+      .values(raw_genesis)
+      .execute(&db.connection)?;
+    assert_eq!(db.utxo_balance(&wallet.address())?, 1_000_000);
+
+    let outputs = vec![
+      TxOutput::new(&other, 1234),
+      TxOutput::new(&wallet.address(), 1_000_000 - 1234),
+    ];
+    let tx = Tx::spending(&wallet, vec![coinbase_out.clone()], outputs)?;
+    let block1 = Block::from_previous(&genesis, Some([tx]));
+    db.commit_block(&block1)?;
+    assert_eq!(db.utxo_balance(&other)?, 1234);
+    assert_eq!(db.utxo_balance(&wallet.address())?, 1_000_000 - 1234);
+
+    let double_spend = Tx::spending(&wallet, vec![coinbase_out], vec![TxOutput::new(&other, 1)])?;
+    let block2 = Block::from_previous(&block1, Some([double_spend]));
+    assert!(db.commit_block(&block2).is_err());
+    assert_eq!(db.latest_block()?, Some(block1.clone()));
+
+    let no_inputs = Tx::new(&wallet, &other, 1)?;
+    let block2 = Block::from_previous(&block1, Some([no_inputs]));
+    assert!(db.commit_block(&block2).is_err());
+
+    // only the genesis block may mint coins
+    let forged = wallet.new_coinbase_tx(1_000_000)?;
+    let block2 = Block::from_previous(&block1, Some([forged]));
+    assert!(db.commit_block(&block2).is_err());
+    assert_eq!(db.utxo_balance(&wallet.address())?, 1_000_000 - 1234);
+
+    assert_eq!(db.rollback_block()?, Some(block1));
+    assert_eq!(db.utxo_balance(&other)?, 0);
+    assert_eq!(db.utxo_balance(&wallet.address())?, 1_000_000);
+    Ok(())
+  }
+}
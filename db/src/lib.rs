@@ -1,8 +1,22 @@
+#![allow(non_local_definitions)]
 #[macro_use]
 extern crate diesel_migrations;
 #[macro_use]
 extern crate diesel;
 
+pub mod archive;
 pub mod database;
+pub mod memory_store;
+pub mod raw_account;
+pub mod raw_ban;
 pub mod raw_block;
+pub mod raw_header;
+pub mod raw_peer;
+pub mod raw_tx;
+pub mod raw_utxo;
+pub mod raw_watched;
 pub mod schema;
+pub mod sled_store;
+pub mod storage_error;
+pub mod store;
+pub mod stored;
@@ -0,0 +1,68 @@
+use crate::store::{verify_account_genesis, verify_next, verify_not_included, ChainStore};
+use bchain_domain::block::Block;
+use bchain_util::result::AppResult;
+
+/// keeps the chain in memory only, meant for tests and throwaway nodes
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+  blocks: Vec<Block>,
+}
+
+impl ChainStore for MemoryStore {
+  fn latest_block(&self) -> AppResult<Option<Block>> {
+    Ok(self.blocks.last().cloned())
+  }
+
+  fn get_block(&self, id: i64) -> AppResult<Option<Block>> {
+    if id < 0 {
+      return Ok(None);
+    }
+    Ok(self.blocks.get(id as usize).cloned())
+  }
+
+  fn commit_block(&mut self, block: &Block) -> AppResult<()> {
+    verify_next(self.blocks.last(), block)?;
+    verify_not_included(self, block)?;
+    self.blocks.push(block.clone());
+    Ok(())
+  }
+
+  fn commit_as_genesis(&mut self, block: &Block) -> AppResult<()> {
+    verify_account_genesis(block)?;
+    self.blocks.clear();
+    self.commit_block(block)
+  }
+
+  fn rollback_block(&mut self) -> AppResult<Option<Block>> {
+    Ok(self.blocks.pop())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_domain::tx::Tx;
+  use bchain_domain::utxo::Ledger;
+
+  #[test]
+  fn memory_store_test() -> AppResult<()> {
+    let mut store = MemoryStore::default();
+    let genesis = Block::default();
+    store.commit_as_genesis(&genesis)?;
+    let block1 = Block::from_previous(&genesis, None::<Vec<Tx>>);
+    assert!(store.commit_block(&Block::default()).is_err());
+    store.commit_block(&block1)?;
+    assert_eq!(store.blocks(0..10)?, vec![genesis.clone(), block1.clone()]);
+    assert_eq!(store.rollback_block()?, Some(block1));
+    assert_eq!(store.latest_block()?, Some(genesis.clone()));
+
+    // chain params are fixed at genesis, a utxo set isn't kept
+    let utxo = Block::genesis(Ledger::Utxo, None::<Vec<Tx>>);
+    assert!(store.commit_as_genesis(&utxo).is_err());
+    let mut params = Block::from_previous(&genesis, None::<Vec<Tx>>);
+    params.params = Some(Default::default());
+    assert!(store.commit_block(&params).is_err());
+    assert_eq!(store.ledger()?, Ledger::Account);
+    Ok(())
+  }
+}
@@ -0,0 +1,36 @@
+use crate::database::touched_addresses;
+use crate::schema::accounts;
+use bchain_domain::block::Block;
+use std::collections::BTreeMap;
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq, Default)]
+#[table_name = "accounts"]
+pub struct RawAccount {
+  pub address: String,
+  pub balance: i64,
+  pub nonce: i64,
+}
+
+impl RawAccount {
+  pub fn new(address: &str) -> Self {
+    RawAccount {
+      address: address.to_owned(),
+      ..Default::default()
+    }
+  }
+}
+
+/// balance and nonce changes a block makes, keyed by address
+pub(crate) fn account_diffs(block: &Block) -> BTreeMap<String, (i64, i64)> {
+  let mut diffs = BTreeMap::new();
+  for tx in block.txs.values() {
+    for address in touched_addresses(tx) {
+      let entry = diffs.entry(address.to_string()).or_insert((0, 0));
+      entry.0 += tx.diff_for_address(address);
+    }
+    if !tx.is_coinbase() {
+      diffs.entry(tx.sender().to_string()).or_insert((0, 0)).1 += 1;
+    }
+  }
+  diffs
+}
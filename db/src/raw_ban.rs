@@ -0,0 +1,20 @@
+use crate::schema::bans;
+use chrono::{NaiveDateTime, Utc};
+
+/// banned peer, `until` is empty for bans that don't expire
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "bans"]
+pub struct RawBan {
+  pub peer_id: String,
+  pub until: Option<NaiveDateTime>,
+  pub reason: String,
+}
+
+impl RawBan {
+  pub fn is_active(&self) -> bool {
+    match self.until {
+      Some(until) => until > Utc::now().naive_utc(),
+      None => true,
+    }
+  }
+}
@@ -1,7 +1,9 @@
 use crate::schema::blocks;
+use crate::storage_error::StorageError;
+use crate::stored::{decode_block, encode_block, CURRENT_FORMAT};
 use bchain_domain::block::Block;
 use bchain_util::error::AppError;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime};
 use std::convert::TryFrom;
 
 #[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
@@ -10,17 +12,20 @@ pub struct RawBlock {
   pub id: i32,
   pub block: Vec<u8>,
   pub created: NaiveDateTime,
+  pub format: i32,
 }
 
 impl TryFrom<&Block> for RawBlock {
   type Error = AppError;
 
   fn try_from(block: &Block) -> Result<Self, Self::Error> {
-    let str_json = serde_json::to_string(block)?;
     let raw_block = RawBlock {
       id: block.id as i32,
-      block: str_json.as_bytes().to_vec(),
-      created: NaiveDateTime::from_timestamp(block.timestamp, 0),
+      block: encode_block(block)?,
+      created: DateTime::from_timestamp(block.timestamp, 0)
+        .unwrap_or_default()
+        .naive_utc(),
+      format: CURRENT_FORMAT,
     };
     Ok(raw_block)
   }
@@ -30,17 +35,24 @@ impl TryFrom<RawBlock> for Block {
   type Error = AppError;
 
   fn try_from(raw_block: RawBlock) -> Result<Self, Self::Error> {
-    let str_json = String::from_utf8(raw_block.block).unwrap();
-    let block: Block = serde_json::from_str(&str_json)?;
-    assert_eq!(raw_block.id, block.id as i32);
-    Ok(block)
+    match decode_block(raw_block.format, &raw_block.block) {
+      Some(block) if block.id as i32 == raw_block.id => Ok(block),
+      _ => Err(
+        StorageError::Corrupt {
+          id: raw_block.id as i64,
+        }
+        .into(),
+      ),
+    }
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::stored::FORMAT_JSON;
   use bchain_domain::{block::Block, tx::Tx, wallet::Wallet};
+  use bchain_util::hash_digest::Hashable;
   use bchain_util::result::AppResult;
 
   const RSAKEY_PEM: &str = "../pem/rsakey.pem";
@@ -56,4 +68,54 @@ mod tests {
     assert_eq!(block, block1);
     Ok(())
   }
+
+  #[async_std::test]
+  async fn json_block_still_readable() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let tx = Tx::new(&wallet, &wallet.address(), 1234)?;
+    let block = Block::from_previous(&Block::default(), Some([tx]));
+    let mut raw = RawBlock::try_from(&block)?;
+    let binary_len = raw.block.len();
+    raw.block = serde_json::to_vec(&block)?;
+    raw.format = FORMAT_JSON;
+    assert!(binary_len < raw.block.len());
+    assert_eq!(block, Block::try_from(raw.clone())?);
+
+    raw.block = b"{}".to_vec();
+    let e = Block::try_from(raw).unwrap_err();
+    assert_eq!(StorageError::of(&e), StorageError::Corrupt { id: 1 });
+    Ok(())
+  }
+
+  #[test]
+  fn baseline_json_block_test() -> AppResult<()> {
+    // genesis and a block with one tx as written by the first release
+    let fixture = include_str!("../fixtures/baseline_blocks.jsonl");
+    let blocks = fixture
+      .lines()
+      .enumerate()
+      .map(|(id, json)| {
+        let raw = RawBlock {
+          id: id as i32,
+          block: json.as_bytes().to_vec(),
+          created: NaiveDateTime::default(),
+          format: FORMAT_JSON,
+        };
+        Block::try_from(raw)
+      })
+      .collect::<AppResult<Vec<_>>>()?;
+    for block in &blocks {
+      block.verify_txs()?;
+    }
+    assert_eq!(blocks[1].parent_hash, Some(blocks[0].hash_digest()));
+    let tx = blocks[1].txs.values().next().unwrap();
+    assert!(tx.is_legacy());
+    assert_eq!(tx.total()?, 1234);
+    // only readable from old storage, never committed as a new block
+    assert!(blocks[1].verify_no_legacy_txs().is_err());
+
+    let raw = RawBlock::try_from(&blocks[1])?;
+    assert_eq!(Block::try_from(raw)?.hash_digest(), blocks[1].hash_digest());
+    Ok(())
+  }
 }
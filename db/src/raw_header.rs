@@ -0,0 +1,41 @@
+use crate::schema::headers;
+use bchain_domain::block::BlockHeader;
+use bchain_util::error::AppError;
+use std::convert::{TryFrom, TryInto};
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "headers"]
+pub struct RawHeader {
+  pub id: i32,
+  pub hash: String,
+  pub parent_hash: Option<String>,
+  pub timestamp: i64,
+  /// hex, diesel can't read back empty blobs
+  pub nonce: String,
+}
+
+impl From<&BlockHeader> for RawHeader {
+  fn from(header: &BlockHeader) -> Self {
+    RawHeader {
+      id: header.id as i32,
+      hash: header.hash.to_string(),
+      parent_hash: header.parent_hash.map(|h| h.to_string()),
+      timestamp: header.timestamp,
+      nonce: hex::encode(&header.nonce),
+    }
+  }
+}
+
+impl TryFrom<RawHeader> for BlockHeader {
+  type Error = AppError;
+
+  fn try_from(raw: RawHeader) -> Result<Self, Self::Error> {
+    Ok(BlockHeader {
+      id: raw.id as i64,
+      timestamp: raw.timestamp,
+      parent_hash: raw.parent_hash.map(|h| h.try_into()).transpose()?,
+      nonce: hex::decode(raw.nonce)?,
+      hash: raw.hash.try_into()?,
+    })
+  }
+}
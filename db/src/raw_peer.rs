@@ -0,0 +1,12 @@
+use crate::schema::peers;
+use chrono::NaiveDateTime;
+
+/// peer that was connected to, dialed again on startup
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "peers"]
+pub struct RawPeer {
+  pub peer_id: String,
+  pub address: String,
+  pub last_seen: NaiveDateTime,
+  pub score: f64,
+}
@@ -0,0 +1,53 @@
+use crate::schema::{address_txs, transactions};
+use crate::stored::{decode_tx, encode_tx, CURRENT_FORMAT};
+use bchain_domain::tx::Tx;
+use bchain_util::error::AppError;
+use std::convert::TryFrom;
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "transactions"]
+pub struct RawTx {
+  pub hash: String,
+  pub block_id: i32,
+  pub tx: Vec<u8>,
+  pub format: i32,
+}
+
+impl RawTx {
+  pub fn new(hash: &str, block_id: i64, tx: &Tx) -> Result<Self, AppError> {
+    Ok(RawTx {
+      hash: hash.to_owned(),
+      block_id: block_id as i32,
+      tx: encode_tx(tx)?,
+      format: CURRENT_FORMAT,
+    })
+  }
+}
+
+impl TryFrom<RawTx> for Tx {
+  type Error = AppError;
+
+  fn try_from(raw_tx: RawTx) -> Result<Self, Self::Error> {
+    decode_tx(raw_tx.format, &raw_tx.tx).ok_or_else(|| {
+      let message = format!("Stored tx {} can't be decoded", raw_tx.hash);
+      AppError::msg(message)
+    })
+  }
+}
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "address_txs"]
+pub struct RawAddressTx {
+  pub address: String,
+  pub tx_hash: String,
+  pub block_id: i32,
+  pub diff: i64,
+}
+
+/// indexed tx together with where it sits in the chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxInfo {
+  pub tx: Tx,
+  pub block_id: i64,
+  pub confirmations: i64,
+}
@@ -0,0 +1,61 @@
+use crate::schema::{utxo_undo, utxos};
+use bchain_domain::tx::TxOutput;
+use bchain_domain::utxo::{OutPoint, Utxo};
+use bchain_util::error::AppError;
+use std::convert::{TryFrom, TryInto};
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "utxos"]
+pub struct RawUtxo {
+  pub tx_hash: String,
+  pub idx: i32,
+  pub address: String,
+  pub amount: i64,
+  pub block_id: i32,
+}
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "utxo_undo"]
+pub struct RawUtxoUndo {
+  pub tx_hash: String,
+  pub idx: i32,
+  pub address: String,
+  pub amount: i64,
+  pub block_id: i32,
+  pub spent_block_id: i32,
+}
+
+impl RawUtxoUndo {
+  pub fn new(utxo: RawUtxo, spent_block_id: i32) -> Self {
+    RawUtxoUndo {
+      tx_hash: utxo.tx_hash,
+      idx: utxo.idx,
+      address: utxo.address,
+      amount: utxo.amount,
+      block_id: utxo.block_id,
+      spent_block_id,
+    }
+  }
+}
+
+impl From<RawUtxoUndo> for RawUtxo {
+  fn from(undo: RawUtxoUndo) -> Self {
+    RawUtxo {
+      tx_hash: undo.tx_hash,
+      idx: undo.idx,
+      address: undo.address,
+      amount: undo.amount,
+      block_id: undo.block_id,
+    }
+  }
+}
+
+impl TryFrom<RawUtxo> for Utxo {
+  type Error = AppError;
+
+  fn try_from(raw: RawUtxo) -> Result<Self, Self::Error> {
+    let outpoint = OutPoint::new(&raw.tx_hash.try_into()?, raw.idx as u32);
+    let output = TxOutput::new(&raw.address.parse()?, raw.amount as u64);
+    Ok(Utxo { outpoint, output })
+  }
+}
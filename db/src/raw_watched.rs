@@ -0,0 +1,37 @@
+use crate::schema::{watched, watched_txs};
+use bchain_domain::address::Address;
+use chrono::{NaiveDateTime, Utc};
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "watched"]
+pub struct RawWatched {
+  pub address: String,
+  pub balance: i64,
+  pub created: NaiveDateTime,
+}
+
+impl From<&Address> for RawWatched {
+  fn from(address: &Address) -> Self {
+    RawWatched {
+      address: address.to_string(),
+      balance: 0,
+      created: Utc::now().naive_utc(),
+    }
+  }
+}
+
+#[derive(Queryable, Debug, Insertable, Clone, PartialEq)]
+#[table_name = "watched_txs"]
+pub struct RawWatchedTx {
+  pub address: String,
+  pub tx_hash: String,
+  pub block_id: i32,
+  pub diff: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watched {
+  pub address: Address,
+  pub balance: i64,
+  pub txs: i64,
+}
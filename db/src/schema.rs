@@ -3,5 +3,119 @@ table! {
         id -> Integer,
         block -> Binary,
         created -> Timestamp,
+        format -> Integer,
     }
 }
+
+table! {
+    watched (address) {
+        address -> Text,
+        balance -> BigInt,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    watched_txs (address, tx_hash) {
+        address -> Text,
+        tx_hash -> Text,
+        block_id -> Integer,
+        diff -> BigInt,
+    }
+}
+
+table! {
+    utxos (tx_hash, idx) {
+        tx_hash -> Text,
+        idx -> Integer,
+        address -> Text,
+        amount -> BigInt,
+        block_id -> Integer,
+    }
+}
+
+table! {
+    utxo_undo (tx_hash, idx) {
+        tx_hash -> Text,
+        idx -> Integer,
+        address -> Text,
+        amount -> BigInt,
+        block_id -> Integer,
+        spent_block_id -> Integer,
+    }
+}
+
+table! {
+    accounts (address) {
+        address -> Text,
+        balance -> BigInt,
+        nonce -> BigInt,
+    }
+}
+
+table! {
+    transactions (hash) {
+        hash -> Text,
+        block_id -> Integer,
+        tx -> Binary,
+        format -> Integer,
+    }
+}
+
+table! {
+    address_txs (address, tx_hash) {
+        address -> Text,
+        tx_hash -> Text,
+        block_id -> Integer,
+        diff -> BigInt,
+    }
+}
+
+table! {
+    headers (id) {
+        id -> Integer,
+        hash -> Text,
+        parent_hash -> Nullable<Text>,
+        timestamp -> BigInt,
+        nonce -> Text,
+    }
+}
+
+table! {
+    bans (peer_id) {
+        peer_id -> Text,
+        until -> Nullable<Timestamp>,
+        reason -> Text,
+    }
+}
+
+table! {
+    peers (peer_id) {
+        peer_id -> Text,
+        address -> Text,
+        last_seen -> Timestamp,
+        score -> Double,
+    }
+}
+
+table! {
+    chain_params (id) {
+        id -> Integer,
+        ledger -> Text,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(
+  blocks,
+  watched,
+  watched_txs,
+  utxos,
+  utxo_undo,
+  accounts,
+  transactions,
+  address_txs,
+  headers,
+  bans,
+  peers,
+  chain_params
+);
@@ -0,0 +1,207 @@
+use crate::storage_error::StorageError;
+use crate::store::{verify_account_genesis, verify_next, verify_not_included, ChainStore};
+use crate::stored::{
+  decode_block, encode_block, Relink, CURRENT_FORMAT, FORMAT_BINCODE_LEGACY_FLAG,
+};
+use bchain_domain::block::Block;
+use bchain_util::error::AppError;
+use bchain_util::result::AppResult;
+use log::info;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::Transactional;
+use std::ops::Range;
+
+const FORMAT_KEY: &[u8] = b"format";
+
+/// blocks in an embedded sled tree keyed by big endian id,
+/// the meta tree records the format they are encoded in
+pub struct SledStore {
+  db: sled::Db,
+  blocks: sled::Tree,
+  meta: sled::Tree,
+}
+
+impl SledStore {
+  pub fn open(path: &str) -> AppResult<Self> {
+    let db = sled::open(path)?;
+    info!("Using sled block chain database {}", path);
+    SledStore::new(db)
+  }
+
+  fn new(db: sled::Db) -> AppResult<Self> {
+    let blocks = db.open_tree("blocks")?;
+    let meta = db.open_tree("meta")?;
+    let store = SledStore { db, blocks, meta };
+    let converted = store.convert_blocks()?;
+    if converted > 0 {
+      info!(
+        "Converted {} blocks to format {}",
+        converted, CURRENT_FORMAT
+      );
+    }
+    Ok(store)
+  }
+
+  /// trees written before the format was recorded hold format 2 blocks
+  fn format(&self) -> AppResult<i32> {
+    match self.meta.get(FORMAT_KEY)? {
+      Some(format) if format.len() == 4 => {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&format);
+        Ok(i32::from_le_bytes(bytes))
+      }
+      Some(_) => Err(AppError::msg("Stored block format is corrupt")),
+      None => Ok(FORMAT_BINCODE_LEGACY_FLAG),
+    }
+  }
+
+  /// re-encodes blocks stored in an older format, relinking those hashed in map order,
+  /// together with recording the new format, returns number of converted blocks
+  fn convert_blocks(&self) -> AppResult<usize> {
+    let format = self.format()?;
+    if format == CURRENT_FORMAT && self.meta.contains_key(FORMAT_KEY)? {
+      return Ok(0);
+    }
+    let mut converted = vec![];
+    let mut relink = Relink::default();
+    for entry in self.blocks.iter() {
+      let (key, value) = entry?;
+      let mut block = SledStore::decode(format, &key, &value).map_err(|_| {
+        AppError::msg(format!(
+          "Block {} is stored in format {} which can't be read, not opening the database",
+          block_id(&key),
+          format
+        ))
+      })?;
+      relink.relink(&mut block);
+      converted.push((key, encode_block(&block)?));
+    }
+    let result = (&self.blocks, &self.meta).transaction(|(blocks, meta)| {
+      for (key, value) in &converted {
+        blocks.insert(key, value.clone())?;
+      }
+      meta.insert(FORMAT_KEY, &CURRENT_FORMAT.to_le_bytes())?;
+      Ok::<_, ConflictableTransactionError>(())
+    });
+    if let Err(TransactionError::Storage(e)) = result {
+      return Err(e.into());
+    }
+    self.db.flush()?;
+    Ok(converted.len())
+  }
+
+  fn decode(format: i32, key: &[u8], value: &[u8]) -> AppResult<Block> {
+    let id = block_id(key);
+    match decode_block(format, value) {
+      Some(block) if block.id == id => Ok(block),
+      _ => Err(StorageError::Corrupt { id }.into()),
+    }
+  }
+}
+
+fn block_id(key: &[u8]) -> i64 {
+  let mut id = [0u8; 8];
+  id.copy_from_slice(&key[..8]);
+  u64::from_be_bytes(id) as i64
+}
+
+fn key(id: i64) -> [u8; 8] {
+  (id as u64).to_be_bytes()
+}
+
+impl ChainStore for SledStore {
+  fn latest_block(&self) -> AppResult<Option<Block>> {
+    match self.blocks.last()? {
+      Some((key, value)) => Ok(Some(SledStore::decode(CURRENT_FORMAT, &key, &value)?)),
+      None => Ok(None),
+    }
+  }
+
+  fn get_block(&self, id: i64) -> AppResult<Option<Block>> {
+    if id < 0 {
+      return Ok(None);
+    }
+    match self.blocks.get(key(id))? {
+      Some(value) => Ok(Some(SledStore::decode(CURRENT_FORMAT, &key(id), &value)?)),
+      None => Ok(None),
+    }
+  }
+
+  fn commit_block(&mut self, block: &Block) -> AppResult<()> {
+    verify_next(self.latest_block()?.as_ref(), block)?;
+    verify_not_included(self, block)?;
+    self.blocks.insert(key(block.id), encode_block(block)?)?;
+    self.db.flush()?;
+    info!("Commited {}", block);
+    Ok(())
+  }
+
+  fn commit_as_genesis(&mut self, block: &Block) -> AppResult<()> {
+    verify_account_genesis(block)?;
+    self.blocks.clear()?;
+    self.commit_block(block)
+  }
+
+  fn rollback_block(&mut self) -> AppResult<Option<Block>> {
+    let latest = match self.blocks.pop_max()? {
+      Some((key, value)) => SledStore::decode(CURRENT_FORMAT, &key, &value)?,
+      None => return Ok(None),
+    };
+    self.db.flush()?;
+    info!("Rolled back {}", latest);
+    Ok(Some(latest))
+  }
+
+  fn blocks(&self, range: Range<i64>) -> AppResult<Vec<Block>> {
+    let range = key(range.start.max(0))..key(range.end.max(0));
+    let mut blocks = vec![];
+    for entry in self.blocks.range(range) {
+      let (key, value) = entry?;
+      blocks.push(SledStore::decode(CURRENT_FORMAT, &key, &value)?);
+    }
+    Ok(blocks)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_domain::tx::Tx;
+  use bchain_util::hash_digest::Hashable;
+
+  #[test]
+  fn sled_store_test() -> AppResult<()> {
+    let db = sled::Config::new().temporary(true).open()?;
+    let mut store = SledStore::new(db)?;
+    let genesis = Block::default();
+    store.commit_as_genesis(&genesis)?;
+    let block1 = Block::from_previous(&genesis, None::<Vec<Tx>>);
+    store.commit_block(&block1)?;
+    assert_eq!(store.latest_block()?, Some(block1.clone()));
+    assert_eq!(store.blocks(1..10)?, vec![block1.clone()]);
+    assert_eq!(store.rollback_block()?, Some(block1));
+    assert_eq!(store.get_block(1)?, None);
+    Ok(())
+  }
+
+  #[test]
+  fn sled_store_convert_test() -> AppResult<()> {
+    // trees written before the format was recorded
+    let db = sled::Config::new().temporary(true).open()?;
+    let bytes = include_bytes!("../fixtures/block_v2.bin");
+    let block = decode_block(FORMAT_BINCODE_LEGACY_FLAG, bytes).unwrap();
+    // linked by a hash over the txs in map order
+    let mut child = Block::from_previous(&block, None::<Vec<Tx>>);
+    child.parent_hash = block.hashes_in_any_order().last();
+    let blocks = db.open_tree("blocks")?;
+    blocks.insert(key(block.id), &bytes[..])?;
+    blocks.insert(key(child.id), encode_block(&child)?)?;
+    let store = SledStore::new(db)?;
+    assert_eq!(store.get_block(block.id)?, Some(block.clone()));
+    let child = store.latest_block()?.unwrap();
+    assert_eq!(child.parent_hash, Some(block.hash_digest()));
+    assert_eq!(store.format()?, CURRENT_FORMAT);
+    assert_eq!(store.convert_blocks()?, 0);
+    Ok(())
+  }
+}
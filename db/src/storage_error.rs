@@ -0,0 +1,59 @@
+use bchain_util::error::AppError;
+use std::fmt::Display;
+
+/// failures storage backends report through `AppResult`,
+/// recovered by callers with `StorageError::of`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+  /// block id doesn't directly follow the latest stored block
+  NotContiguous { expected: i64, got: i64 },
+  /// block doesn't point to the hash of the latest stored block
+  ParentMismatch { id: i64 },
+  /// stored block can't be decoded
+  Corrupt { id: i64 },
+  /// underlying database failed
+  Backend(String),
+}
+
+impl StorageError {
+  /// errors that didn't originate as a `StorageError` are backend failures
+  pub fn of(e: &AppError) -> StorageError {
+    match e.downcast_ref::<StorageError>() {
+      Some(e) => e.clone(),
+      None => StorageError::Backend(e.to_string()),
+    }
+  }
+}
+
+impl Display for StorageError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      StorageError::NotContiguous { expected, got } => {
+        write!(f, "Expected block {}, got block {}", expected, got)
+      }
+      StorageError::ParentMismatch { id } => {
+        write!(f, "Block {} does not extend the latest block", id)
+      }
+      StorageError::Corrupt { id } => write!(f, "Stored block {} is corrupt", id),
+      StorageError::Backend(e) => write!(f, "Storage failed: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for StorageError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn storage_error_of_test() {
+    let e: AppError = StorageError::Corrupt { id: 3 }.into();
+    assert_eq!(StorageError::of(&e), StorageError::Corrupt { id: 3 });
+    let e = AppError::msg("disk full");
+    assert_eq!(
+      StorageError::of(&e),
+      StorageError::Backend("disk full".into())
+    );
+  }
+}
@@ -0,0 +1,341 @@
+use crate::database::{create_db, touched_addresses, HISTORY_PAGE_SIZE};
+use crate::memory_store::MemoryStore;
+use crate::raw_ban::RawBan;
+use crate::raw_peer::RawPeer;
+use crate::raw_tx::{RawAddressTx, TxInfo};
+use crate::raw_watched::{RawWatchedTx, Watched};
+use crate::sled_store::SledStore;
+use crate::storage_error::StorageError;
+use bchain_domain::address::Address;
+use bchain_domain::block::{Block, BlockHeader};
+use bchain_domain::cli::{Cli, StoreKind};
+use bchain_domain::utxo::{Ledger, Utxo};
+use bchain_util::error::AppError;
+use bchain_util::result::AppResult;
+use chrono::NaiveDateTime;
+use log::info;
+use std::cmp::max;
+use std::ops::Range;
+
+/// block storage behind the node, only the chain itself is required,
+/// queries default to scanning blocks, sqlite answers them from its indexes,
+/// optional parts are separate traits a store hands out if it has them
+pub trait ChainStore: Send {
+  fn latest_block(&self) -> AppResult<Option<Block>>;
+  fn get_block(&self, id: i64) -> AppResult<Option<Block>>;
+  fn commit_block(&mut self, block: &Block) -> AppResult<()>;
+  /// drops the whole chain, starting over from `block`
+  fn commit_as_genesis(&mut self, block: &Block) -> AppResult<()>;
+  /// removes the latest block, returning it
+  fn rollback_block(&mut self) -> AppResult<Option<Block>>;
+
+  fn get_header(&self, id: i64) -> AppResult<Option<BlockHeader>> {
+    Ok(self.get_block(id)?.map(|block| (&block).into()))
+  }
+
+  /// first and latest block ids that full blocks can be served for
+  fn serving_range(&self) -> AppResult<Option<(i64, i64)>> {
+    Ok(self.latest_block()?.map(|latest| (0, latest.id)))
+  }
+
+  /// blocks with ids in `range`, ascending, stops at the first missing block
+  fn blocks(&self, range: Range<i64>) -> AppResult<Vec<Block>> {
+    let mut blocks = vec![];
+    for id in range {
+      match self.get_block(id)? {
+        Some(block) => blocks.push(block),
+        None => break,
+      }
+    }
+    Ok(blocks)
+  }
+
+  /// latest block and up to `num_blocks` before it, newest first
+  fn recent_blocks(&self, num_blocks: i64) -> AppResult<Vec<Block>> {
+    let latest = match self.latest_block()? {
+      Some(latest) => latest,
+      None => return Ok(vec![]),
+    };
+    let mut blocks = self.blocks(max(0, latest.id - num_blocks)..latest.id)?;
+    blocks.push(latest);
+    blocks.reverse();
+    Ok(blocks)
+  }
+
+  /// ledger fixed by the genesis block
+  fn ledger(&self) -> AppResult<Ledger> {
+    Ok(
+      self
+        .get_block(0)?
+        .map(|genesis| genesis.ledger())
+        .unwrap_or_default(),
+    )
+  }
+
+  fn balance(&self, address: &Address) -> AppResult<i64> {
+    let mut balance = 0;
+    scan(self, |block| balance += block.diff_for_address(address))?;
+    Ok(balance)
+  }
+
+  fn get_tx(&self, hash: &str) -> AppResult<Option<TxInfo>> {
+    let mut found = None;
+    scan(self, |block| {
+      if let Some(tx) = block.txs.get(hash) {
+        found = Some((tx.clone(), block.id));
+      }
+    })?;
+    let latest_id = self.latest_block()?.map(|b| b.id).unwrap_or_default();
+    Ok(found.map(|(tx, block_id)| TxInfo {
+      tx,
+      block_id,
+      confirmations: latest_id - block_id + 1,
+    }))
+  }
+
+  /// txs touching `address`, newest first, `HISTORY_PAGE_SIZE` per page
+  fn history(&self, address: &Address, page: i64) -> AppResult<Vec<RawAddressTx>> {
+    let history = address_history(self, address)?;
+    let skip = (page * HISTORY_PAGE_SIZE) as usize;
+    let page = history.into_iter().skip(skip);
+    Ok(page.take(HISTORY_PAGE_SIZE as usize).collect())
+  }
+
+  fn history_len(&self, address: &Address) -> AppResult<i64> {
+    Ok(address_history(self, address)?.len() as i64)
+  }
+
+  /// account, utxo and watch-only indexes, `None` if the store doesn't keep them
+  fn index(&self) -> Option<&dyn ChainIndex> {
+    None
+  }
+
+  fn index_mut(&mut self) -> Option<&mut dyn ChainIndex> {
+    None
+  }
+
+  /// `None` if known peers aren't persisted
+  fn peer_book(&mut self) -> Option<&mut dyn PeerBook> {
+    None
+  }
+
+  /// `None` if bans aren't persisted
+  fn ban_list(&mut self) -> Option<&mut dyn BanList> {
+    None
+  }
+}
+
+/// indexes maintained alongside the chain
+pub trait ChainIndex {
+  /// recomputes the account index from blocks, returns how many accounts were out of sync
+  fn rebuild_accounts(&mut self) -> AppResult<usize>;
+  fn unspent(&self, address: &Address) -> AppResult<Vec<Utxo>>;
+  fn utxo_balance(&self, address: &Address) -> AppResult<i64>;
+  fn watch(&mut self, address: &Address) -> AppResult<()>;
+  fn unwatch(&mut self, address: &Address) -> AppResult<bool>;
+  fn watched(&self) -> AppResult<Vec<Watched>>;
+  /// txs changing the balance of a watched address, latest first
+  fn watched_history(&self, address: &Address) -> AppResult<Vec<RawWatchedTx>>;
+}
+
+pub trait BanList {
+  /// replaces an existing ban of the same peer
+  fn ban(&mut self, ban: &RawBan) -> AppResult<()>;
+  fn unban(&mut self, peer_id: &str) -> AppResult<bool>;
+  /// bans that haven't expired yet
+  fn bans(&self) -> AppResult<Vec<RawBan>>;
+}
+
+pub trait PeerBook {
+  /// replaces the stored address, last seen time and score of the same peer
+  fn save_peer(&mut self, peer: &RawPeer) -> AppResult<()>;
+  /// most recently seen first
+  fn peers(&self) -> AppResult<Vec<RawPeer>>;
+  /// removes peers last seen before `before`, returns how many
+  fn remove_stale_peers(&mut self, before: NaiveDateTime) -> AppResult<usize>;
+}
+
+pub type Store = Box<dyn ChainStore>;
+
+/// checks that `block` extends `latest` and that its txs are signed and not legacy
+pub(crate) fn verify_next(latest: Option<&Block>, block: &Block) -> AppResult<()> {
+  if let Some(latest) = latest {
+    verify_link(&latest.into(), block)?;
+  }
+  block.verify_params()?;
+  block.verify_no_legacy_txs()?;
+  block.verify_txs()
+}
+
+/// a signed tx can only be included once, otherwise it could be replayed,
+/// for stores without a tx index, scans the chain once
+pub(crate) fn verify_not_included<S>(store: &S, block: &Block) -> AppResult<()>
+where
+  S: ChainStore + ?Sized,
+{
+  let mut included = None;
+  scan(store, |stored| {
+    let found = block.txs.keys().find(|hash| stored.txs.contains_key(*hash));
+    included = included
+      .take()
+      .or_else(|| found.map(|hash| (hash, stored.id)));
+  })?;
+  match included {
+    Some((hash, id)) => Err(replayed(hash, id)),
+    None => Ok(()),
+  }
+}
+
+pub(crate) fn replayed(hash: &str, block_id: i64) -> AppError {
+  AppError::msg(format!("Tx {} is already in block {}", hash, block_id))
+}
+
+pub(crate) fn verify_link(parent: &BlockHeader, block: &Block) -> AppResult<()> {
+  if parent.id + 1 != block.id {
+    let expected = parent.id + 1;
+    return Err(
+      StorageError::NotContiguous {
+        expected,
+        got: block.id,
+      }
+      .into(),
+    );
+  }
+  if Some(parent.hash) != block.parent_hash {
+    return Err(StorageError::ParentMismatch { id: block.id }.into());
+  }
+  Ok(())
+}
+
+/// `--ledger` only applies with `--init`, otherwise the genesis block decides,
+/// parts the chain needs are checked once the store is built
+pub fn create_store(cli: &Cli) -> AppResult<Store> {
+  if cli.prune.is_some() && cli.store != StoreKind::Sqlite {
+    return Err(unsupported("Pruning"));
+  }
+  if matches!(cli.prune, Some(depth) if depth < 1) {
+    return Err(AppError::msg("Prune depth has to be at least 1"));
+  }
+  let store: Store = match cli.store {
+    StoreKind::Sqlite => {
+      let mut db = create_db(&cli.database)?;
+      db.set_prune(cli.prune);
+      Box::new(db)
+    }
+    StoreKind::Sled => Box::new(SledStore::open(&cli.database)?),
+    StoreKind::Memory => {
+      info!("Using in-memory block chain, nothing is persisted");
+      Box::new(MemoryStore::default())
+    }
+  };
+  let ledger = match cli.init {
+    true => cli.ledger,
+    false => store.ledger()?,
+  };
+  if ledger == Ledger::Utxo && store.index().is_none() {
+    return Err(unsupported("Utxo ledger"));
+  }
+  Ok(store)
+}
+
+fn scan<S, F>(store: &S, mut f: F) -> AppResult<()>
+where
+  S: ChainStore + ?Sized,
+  F: FnMut(&Block),
+{
+  let mut id = 0;
+  while let Some(block) = store.get_block(id)? {
+    f(&block);
+    id += 1;
+  }
+  Ok(())
+}
+
+fn address_history<S>(store: &S, address: &Address) -> AppResult<Vec<RawAddressTx>>
+where
+  S: ChainStore + ?Sized,
+{
+  let mut history = vec![];
+  scan(store, |block| {
+    for (tx_hash, tx) in &block.txs {
+      if touched_addresses(tx).contains(address) {
+        history.push(RawAddressTx {
+          address: address.to_string(),
+          tx_hash: tx_hash.clone(),
+          block_id: block.id as i32,
+          diff: tx.diff_for_address(address),
+        });
+      }
+    }
+  })?;
+  history.sort_by(|a, b| (b.block_id, &a.tx_hash).cmp(&(a.block_id, &b.tx_hash)));
+  Ok(history)
+}
+
+/// stores without a utxo set can only hold account ledger chains
+pub(crate) fn verify_account_genesis(block: &Block) -> AppResult<()> {
+  match block.ledger() {
+    Ledger::Account => Ok(()),
+    Ledger::Utxo => Err(unsupported("Utxo ledger")),
+  }
+}
+
+pub fn unsupported(feature: &str) -> AppError {
+  AppError::msg(format!("{} needs the sqlite store", feature))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_domain::tx::Tx;
+  use bchain_domain::wallet::Wallet;
+  use bchain_util::hash_digest::Hashable;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn scanning_queries_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let other = Address::default();
+    let mut store = MemoryStore::default();
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(1_000_000)?]));
+    store.commit_as_genesis(&genesis)?;
+    let tx = Tx::new(&wallet, &other, 1234)?;
+    let tx_hash = tx.hash_digest().to_string();
+    let block1 = Block::from_previous(&genesis, Some([tx]));
+    store.commit_block(&block1)?;
+
+    assert_eq!(store.balance(&wallet.address())?, 1_000_000 - 1234);
+    assert_eq!(store.get_tx(&tx_hash)?.unwrap().confirmations, 1);
+    let history = store.history(&wallet.address(), 0)?;
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].tx_hash, tx_hash);
+    assert_eq!(
+      store.recent_blocks(10)?,
+      vec![block1.clone(), genesis.clone()]
+    );
+    assert!(store.index().is_none());
+    assert!(store.ban_list().is_none());
+    assert!(store.peer_book().is_none());
+
+    let mut replay = Block::from_previous(&block1, Some(block1.txs.values().cloned()));
+    assert!(store.commit_block(&replay).is_err());
+    replay.txs.clear();
+    store.commit_block(&replay)?;
+    store.rollback_block()?;
+
+    let e = store.commit_block(&genesis).unwrap_err();
+    let expected = StorageError::NotContiguous {
+      expected: 2,
+      got: 0,
+    };
+    assert_eq!(StorageError::of(&e), expected);
+    let fork = Block::from_previous(
+      &Block::from_previous(&genesis, None::<Vec<Tx>>),
+      None::<Vec<Tx>>,
+    );
+    let e = store.commit_block(&fork).unwrap_err();
+    assert_eq!(StorageError::of(&e), StorageError::ParentMismatch { id: 2 });
+    Ok(())
+  }
+}
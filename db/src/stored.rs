@@ -0,0 +1,341 @@
+use bchain_domain::address::Address;
+use bchain_domain::block::{Block, ChainParams, LegacyBlock};
+use bchain_domain::multisig::{MultisigPolicy, MultisigWitness};
+use bchain_domain::public_key::PublicKey;
+use bchain_domain::signature::Signature;
+use bchain_domain::tx::{Tx, TxOutput, TxParts};
+use bchain_domain::utxo::{Ledger, OutPoint};
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::{HashDigest, Hashable};
+use bchain_util::result::AppResult;
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+
+/// blocks and txs written before the format column was introduced
+pub const FORMAT_JSON: i32 = 0;
+/// bincode without the legacy flag of txs, can't be read anymore
+pub const FORMAT_BINCODE: i32 = 1;
+/// bincode of `BlockV2` and `TxV2`
+pub const FORMAT_BINCODE_LEGACY_FLAG: i32 = 2;
+/// layout of 2, parents are linked by hashes over txs in key order
+pub const FORMAT_KEY_ORDER_LINKS: i32 = 3;
+/// bincode of `BlockV4`, genesis blocks carry chain params
+pub const FORMAT_CHAIN_PARAMS: i32 = 4;
+pub const CURRENT_FORMAT: i32 = FORMAT_CHAIN_PARAMS;
+
+/// on-disk layout of blocks in format 2, a released layout is never changed,
+/// a block with new fields gets a new struct and format instead
+#[derive(Serialize, Deserialize)]
+struct BlockV2 {
+  id: i64,
+  timestamp: i64,
+  txs: Vec<(String, TxV2)>,
+  parent_hash: Option<HashDigest>,
+  nonce: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockV4 {
+  id: i64,
+  timestamp: i64,
+  txs: Vec<(String, TxV2)>,
+  parent_hash: Option<HashDigest>,
+  nonce: Vec<u8>,
+  params: Option<ChainParamsV4>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChainParamsV4 {
+  /// 0 account, 1 utxo
+  ledger: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TxV2 {
+  inputs: Vec<OutPointV2>,
+  outputs: Vec<OutputV2>,
+  timestamp: i64,
+  sender: Address,
+  signature: Signature,
+  multisig: Option<WitnessV2>,
+  legacy: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutPointV2 {
+  tx: HashDigest,
+  index: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutputV2 {
+  receiver: Address,
+  amount: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WitnessV2 {
+  threshold: usize,
+  keys: Vec<PublicKey>,
+  signatures: Vec<(usize, Signature)>,
+}
+
+impl From<&Block> for BlockV2 {
+  fn from(block: &Block) -> Self {
+    let mut txs: Vec<_> = block
+      .txs
+      .iter()
+      .map(|(key, tx)| (key.clone(), TxV2::from(tx)))
+      .collect();
+    txs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    BlockV2 {
+      id: block.id,
+      timestamp: block.timestamp,
+      txs,
+      parent_hash: block.parent_hash,
+      nonce: block.nonce.clone(),
+    }
+  }
+}
+
+impl TryFrom<BlockV2> for Block {
+  type Error = AppError;
+
+  fn try_from(block: BlockV2) -> Result<Self, Self::Error> {
+    let txs = block
+      .txs
+      .into_iter()
+      .map(|(key, tx)| Ok((key, tx.try_into()?)));
+    Ok(Block {
+      id: block.id,
+      timestamp: block.timestamp,
+      txs: txs.collect::<AppResult<_>>()?,
+      parent_hash: block.parent_hash,
+      nonce: block.nonce,
+      params: None,
+    })
+  }
+}
+
+impl From<&Block> for BlockV4 {
+  fn from(block: &Block) -> Self {
+    let BlockV2 {
+      id,
+      timestamp,
+      txs,
+      parent_hash,
+      nonce,
+    } = block.into();
+    let params = block.params.map(|params| ChainParamsV4 {
+      ledger: match params.ledger {
+        Ledger::Account => 0,
+        Ledger::Utxo => 1,
+      },
+    });
+    BlockV4 {
+      id,
+      timestamp,
+      txs,
+      parent_hash,
+      nonce,
+      params,
+    }
+  }
+}
+
+impl TryFrom<BlockV4> for Block {
+  type Error = AppError;
+
+  fn try_from(block: BlockV4) -> Result<Self, Self::Error> {
+    let params = match block.params.map(|params| params.ledger) {
+      None => None,
+      Some(0) => Some(ChainParams {
+        ledger: Ledger::Account,
+      }),
+      Some(1) => Some(ChainParams {
+        ledger: Ledger::Utxo,
+      }),
+      Some(ledger) => return Err(AppError::msg(format!("Unknown ledger {}", ledger))),
+    };
+    let block = BlockV2 {
+      id: block.id,
+      timestamp: block.timestamp,
+      txs: block.txs,
+      parent_hash: block.parent_hash,
+      nonce: block.nonce,
+    };
+    Ok(Block {
+      params,
+      ..block.try_into()?
+    })
+  }
+}
+
+impl From<&Tx> for TxV2 {
+  fn from(tx: &Tx) -> Self {
+    let parts = TxParts::from(tx);
+    let inputs = parts.inputs.into_iter().map(|input| OutPointV2 {
+      tx: input.tx,
+      index: input.index,
+    });
+    let outputs = parts.outputs.into_iter().map(|output| OutputV2 {
+      receiver: output.receiver,
+      amount: output.amount,
+    });
+    let multisig = parts.multisig.map(|witness| WitnessV2 {
+      threshold: witness.policy.threshold(),
+      keys: witness.policy.keys().to_vec(),
+      signatures: witness.signatures.into_iter().collect(),
+    });
+    TxV2 {
+      inputs: inputs.collect(),
+      outputs: outputs.collect(),
+      timestamp: parts.timestamp,
+      sender: parts.sender,
+      signature: parts.signature,
+      multisig,
+      legacy: parts.legacy,
+    }
+  }
+}
+
+impl TryFrom<TxV2> for Tx {
+  type Error = AppError;
+
+  fn try_from(tx: TxV2) -> Result<Self, Self::Error> {
+    let inputs = tx.inputs.into_iter().map(|input| OutPoint {
+      tx: input.tx,
+      index: input.index,
+    });
+    let outputs = tx.outputs.into_iter().map(|output| TxOutput {
+      receiver: output.receiver,
+      amount: output.amount,
+    });
+    let multisig = match tx.multisig {
+      Some(witness) => Some(MultisigWitness {
+        policy: MultisigPolicy::new(witness.threshold, witness.keys)?,
+        signatures: witness.signatures.into_iter().collect(),
+      }),
+      None => None,
+    };
+    let parts = TxParts {
+      inputs: inputs.collect(),
+      outputs: outputs.collect(),
+      timestamp: tx.timestamp,
+      sender: tx.sender,
+      signature: tx.signature,
+      multisig,
+      legacy: tx.legacy,
+    };
+    Ok(parts.into())
+  }
+}
+
+/// `block` in the current format
+pub fn encode_block(block: &Block) -> AppResult<Vec<u8>> {
+  Ok(bincode::serialize(&BlockV4::from(block))?)
+}
+
+/// `None` if `bytes` aren't a block in `format`
+pub fn decode_block(format: i32, bytes: &[u8]) -> Option<Block> {
+  match format {
+    // the oldest blocks hold txs with a single amount and receiver
+    FORMAT_JSON => serde_json::from_slice(bytes).ok().or_else(|| {
+      serde_json::from_slice::<LegacyBlock>(bytes)
+        .ok()
+        .map(Block::from)
+    }),
+    FORMAT_BINCODE_LEGACY_FLAG | FORMAT_KEY_ORDER_LINKS => {
+      bincode::deserialize::<BlockV2>(bytes).ok()?.try_into().ok()
+    }
+    FORMAT_CHAIN_PARAMS => bincode::deserialize::<BlockV4>(bytes).ok()?.try_into().ok(),
+    _ => None,
+  }
+}
+
+/// `tx` in the current format, the same layout txs have inside blocks
+pub fn encode_tx(tx: &Tx) -> AppResult<Vec<u8>> {
+  Ok(bincode::serialize(&TxV2::from(tx))?)
+}
+
+/// `None` if `bytes` aren't a tx in `format`
+pub fn decode_tx(format: i32, bytes: &[u8]) -> Option<Tx> {
+  match format {
+    FORMAT_JSON => serde_json::from_slice(bytes).ok(),
+    FORMAT_BINCODE_LEGACY_FLAG | FORMAT_KEY_ORDER_LINKS | FORMAT_CHAIN_PARAMS => {
+      bincode::deserialize::<TxV2>(bytes).ok()?.try_into().ok()
+    }
+    _ => None,
+  }
+}
+
+/// walks blocks written before format 3 in order, a parent hash taken over
+/// txs in map order is replaced by the key order hash of the relinked parent
+#[derive(Default)]
+pub(crate) struct Relink {
+  /// parent as it was stored, unknown if only its header is left
+  original: Option<Block>,
+  relinked: Option<HashDigest>,
+}
+
+impl Relink {
+  /// `parent` precedes the first walked block, `hash` is its current hash
+  pub(crate) fn new(parent: Option<Block>, hash: Option<HashDigest>) -> Self {
+    Relink {
+      original: parent,
+      relinked: hash,
+    }
+  }
+
+  /// whether the parent hash of `block` was replaced
+  pub(crate) fn relink(&mut self, block: &mut Block) -> bool {
+    let stale = match (&self.original, self.relinked, block.parent_hash) {
+      (Some(parent), Some(relinked), Some(hash)) if hash != relinked => {
+        parent.hashes_in_any_order().any(|h| h == hash)
+      }
+      _ => false,
+    };
+    self.original = Some(block.clone());
+    if stale {
+      block.parent_hash = self.relinked;
+    }
+    self.relinked = Some(block.hash_digest());
+    stale
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn block_v2_fixture_test() -> AppResult<()> {
+    // a block with a coinbase, a plain and a multisig tx as written in format 2
+    let bytes = include_bytes!("../fixtures/block_v2.bin");
+    let block = decode_block(FORMAT_BINCODE_LEGACY_FLAG, bytes)
+      .ok_or_else(|| AppError::msg("Fixture can't be decoded"))?;
+    assert_eq!(block.txs.len(), 3);
+    for (key, tx) in &block.txs {
+      assert_eq!(key, &tx.hash_digest().to_string());
+    }
+    let encoded = bincode::serialize(&BlockV2::from(&block))?;
+    assert_eq!(encoded.len(), bytes.len());
+    assert_eq!(
+      decode_block(FORMAT_BINCODE_LEGACY_FLAG, &encoded),
+      Some(block.clone())
+    );
+    assert_eq!(
+      decode_block(CURRENT_FORMAT, &encode_block(&block)?),
+      Some(block)
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn chain_params_test() -> AppResult<()> {
+    let genesis = Block::genesis(Ledger::Utxo, None::<Vec<Tx>>);
+    let decoded = decode_block(CURRENT_FORMAT, &encode_block(&genesis)?);
+    assert_eq!(decoded.map(|block| block.ledger()), Some(Ledger::Utxo));
+    Ok(())
+  }
+}
@@ -1,39 +1,79 @@
 use crate::public_key::PublicKey;
 use bchain_util::error::AppError;
-use bchain_util::hash_digest::{AsBytes, Hashable};
-use serde::{Deserialize, Serialize};
+use bchain_util::hash_digest::{AsBytes, HashDigest, Hashable};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
 use std::fmt::Display;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, Eq, Hash)]
-pub struct Address(pub PublicKey);
+const MULTISIG_PREFIX: u8 = 0x05;
+const MULTISIG_ADDRESS_LENGTH: usize = 33;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+  Key(PublicKey),
+  /// hash of a `MultisigPolicy`, see `crate::multisig`
+  Multisig(HashDigest),
+}
+
+impl Default for Address {
+  fn default() -> Self {
+    Address::Key(PublicKey::default())
+  }
+}
 
 impl Display for Address {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-    let friendly = bs58::encode(&self.0).into_string();
+    let friendly = bs58::encode(&self.as_bytes()).into_string();
     write!(f, "{}", friendly)
   }
 }
 
 impl Address {
   pub fn new(public_key: &PublicKey) -> Address {
-    Address(public_key.clone())
+    Address::Key(public_key.clone())
+  }
+
+  pub fn public_key(&self) -> Option<&PublicKey> {
+    match self {
+      Address::Key(public_key) => Some(public_key),
+      Address::Multisig(_) => None,
+    }
   }
 }
 
 impl AsBytes for Address {
   fn as_bytes(&self) -> std::vec::Vec<u8> {
-    self.0.to_vec()
+    match self {
+      Address::Key(public_key) => public_key.to_vec(),
+      Address::Multisig(digest) => {
+        let mut bytes = vec![MULTISIG_PREFIX];
+        bytes.extend_from_slice(&digest.as_bytes());
+        bytes
+      }
+    }
   }
 }
 
 impl Hashable for Address {}
 
+impl TryFrom<&[u8]> for Address {
+  type Error = AppError;
+
+  fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+    if bytes.len() == MULTISIG_ADDRESS_LENGTH && bytes[0] == MULTISIG_PREFIX {
+      Ok(Address::Multisig(bytes[1..].to_vec().into()))
+    } else {
+      Ok(Address::Key(PublicKey::try_new(bytes)?))
+    }
+  }
+}
+
 impl FromStr for Address {
   type Err = AppError;
   fn from_str(addr: &str) -> std::result::Result<Self, <Self as std::str::FromStr>::Err> {
     match bs58::decode(addr).into_vec() {
-      Ok(bytes) => Ok(Address(PublicKey::try_new(&bytes)?)),
+      Ok(bytes) => Address::try_from(&bytes[..]),
       Err(e) => Err(AppError::msg(format!("{:?}", e))),
     }
   }
@@ -45,6 +85,21 @@ impl From<PublicKey> for Address {
   }
 }
 
+// serialized as raw bytes, so key addresses keep the same
+// representation they had before multisig addresses were introduced
+impl Serialize for Address {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.as_bytes().serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Address {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let bytes = Vec::<u8>::deserialize(deserializer)?;
+    Address::try_from(&bytes[..]).map_err(de::Error::custom)
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use bchain_util::result::AppResult;
@@ -58,4 +113,22 @@ mod tests {
     assert_eq!(format!("{}", address), input);
     Ok(())
   }
+
+  #[test]
+  fn multisig_address_parse_test() -> AppResult<()> {
+    let address = Address::Multisig("abc".hash_digest());
+    let address1: Address = address.to_string().parse()?;
+    assert_eq!(address, address1);
+    Ok(())
+  }
+
+  #[test]
+  fn address_serde_test() -> AppResult<()> {
+    let address = Address::default();
+    let json = serde_json::to_string(&address)?;
+    assert_eq!(json, serde_json::to_string(&PublicKey::default())?);
+    let address1: Address = serde_json::from_str(&json)?;
+    assert_eq!(address, address1);
+    Ok(())
+  }
 }
@@ -1,20 +1,36 @@
 use crate::address::Address;
-use crate::tx::Tx;
+use crate::tx::{LegacyTx, Tx};
+use crate::utxo::Ledger;
 use async_std::task;
 use async_trait::async_trait;
+use bchain_util::error::AppError;
 use bchain_util::hash_digest::{AsBytes, HashDigest, Hashable};
 use bchain_util::mine::Mine;
 use bchain_util::result::AppResult;
 use chrono::Utc;
-use itertools::iterate;
+use itertools::{iterate, Itertools};
 use num::{BigUint, One, Zero};
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::iter::repeat;
 
+/// most txs of a block whose map order hash is searched for
+const MAX_REORDERED_TXS: usize = 8;
+
+/// coins created by the genesis coinbase, mined blocks carry no reward
+pub const GENESIS_REWARD: u64 = 1_000_000;
+
+/// most the coinbase of block `id` may create
+pub fn block_reward(id: i64) -> u64 {
+  match id {
+    0 => GENESIS_REWARD,
+    _ => 0,
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 pub struct Block {
   pub id: i64,
@@ -22,20 +38,79 @@ pub struct Block {
   pub txs: HashMap<String, Tx>,
   pub parent_hash: Option<HashDigest>,
   pub nonce: Vec<u8>,
+  /// only set on genesis blocks, chains started without them use the account ledger
+  #[serde(default)]
+  pub params: Option<ChainParams>,
+}
+
+/// fixed for the whole chain by its genesis block
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ChainParams {
+  pub ledger: Ledger,
+}
+
+impl AsBytes for ChainParams {
+  fn as_bytes(&self) -> Vec<u8> {
+    self.ledger.as_bytes()
+  }
+}
+
+/// block as serialized before txs had multiple outputs, only read from old databases
+#[derive(Debug, Clone, Deserialize)]
+pub struct LegacyBlock {
+  id: i64,
+  timestamp: i64,
+  txs: HashMap<String, LegacyTx>,
+  parent_hash: Option<HashDigest>,
+  nonce: Vec<u8>,
+}
+
+impl From<LegacyBlock> for Block {
+  fn from(block: LegacyBlock) -> Self {
+    Block {
+      id: block.id,
+      timestamp: block.timestamp,
+      txs: block
+        .txs
+        .into_iter()
+        .map(|(k, tx)| (k, tx.into()))
+        .collect(),
+      parent_hash: block.parent_hash,
+      nonce: block.nonce,
+      params: None,
+    }
+  }
+}
+
+/// what remains of a block once its body is pruned,
+/// `hash` is the hash of the full block
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockHeader {
+  pub id: i64,
+  pub timestamp: i64,
+  pub parent_hash: Option<HashDigest>,
+  pub nonce: Vec<u8>,
+  pub hash: HashDigest,
+}
+
+impl From<&Block> for BlockHeader {
+  fn from(block: &Block) -> Self {
+    BlockHeader {
+      id: block.id,
+      timestamp: block.timestamp,
+      parent_hash: block.parent_hash,
+      nonce: block.nonce.clone(),
+      hash: block.hash_digest(),
+    }
+  }
 }
 
 impl AsBytes for Block {
   fn as_bytes(&self) -> std::vec::Vec<u8> {
-    let mut res = vec![];
-    res.extend_from_slice(&self.id.as_bytes());
-    res.extend_from_slice(&self.timestamp.as_bytes());
-    let txs: Vec<Tx> = self.txs.iter().map(|(_, tx)| tx.clone()).collect();
-    for tx in txs {
-      res.extend_from_slice(&tx.as_bytes())
-    }
-    res.extend_from_slice(&self.parent_hash.as_bytes());
-    res.extend_from_slice(&self.nonce.clone());
-    res
+    // hashed in key order, map iteration order differs between instances
+    let mut txs: Vec<_> = self.txs.iter().collect();
+    txs.sort_by_key(|(key, _)| *key);
+    self.bytes_with_txs(txs.into_iter().map(|(_, tx)| tx.as_bytes()))
   }
 }
 
@@ -92,6 +167,33 @@ impl Block {
     }
   }
 
+  /// genesis block of a new chain using `ledger`
+  pub fn genesis<TXs>(ledger: Ledger, txs: Option<TXs>) -> Block
+  where
+    TXs: IntoIterator<Item = Tx>,
+  {
+    Block {
+      params: Some(ChainParams { ledger }),
+      ..Self::new(txs)
+    }
+  }
+
+  /// ledger the chain of this genesis block uses
+  pub fn ledger(&self) -> Ledger {
+    self.params.unwrap_or_default().ledger
+  }
+
+  /// only the genesis block fixes chain params
+  pub fn verify_params(&self) -> AppResult<()> {
+    match (self.id, self.params) {
+      (0, _) | (_, None) => Ok(()),
+      _ => Err(AppError::msg(format!(
+        "Block {} carries chain params",
+        self.id
+      ))),
+    }
+  }
+
   pub fn add(&mut self, tx: &Tx) {
     let key = tx.hash_digest().to_string();
     self.txs.insert(key, tx.clone());
@@ -104,12 +206,91 @@ impl Block {
       .fold(0, |acc, tx| acc + tx.diff_for_address(address))
   }
 
+  fn bytes_with_txs(&self, txs: impl Iterator<Item = Vec<u8>>) -> Vec<u8> {
+    let mut res = vec![];
+    res.extend_from_slice(&self.id.as_bytes());
+    res.extend_from_slice(&self.timestamp.as_bytes());
+    for tx in txs {
+      res.extend_from_slice(&tx)
+    }
+    res.extend_from_slice(&self.parent_hash.as_bytes());
+    res.extend_from_slice(&self.nonce.clone());
+    // absent params aren't hashed, so genesis blocks written before them keep their hash
+    res.extend_from_slice(&self.params.as_bytes());
+    res
+  }
+
+  /// hashes of the block with its txs in every order, starting with key order,
+  /// blocks used to be hashed in map order and their children link to one of these,
+  /// nothing is returned for blocks with more than `MAX_REORDERED_TXS` txs
+  pub fn hashes_in_any_order(&self) -> impl Iterator<Item = HashDigest> + '_ {
+    let mut txs: Vec<_> = self.txs.iter().collect();
+    txs.sort_by_key(|(key, _)| *key);
+    let len = txs.len();
+    let orders = match len <= MAX_REORDERED_TXS {
+      true => usize::MAX,
+      false => 0,
+    };
+    let txs = txs.into_iter().map(|(_, tx)| tx.as_bytes());
+    txs
+      .permutations(len)
+      .take(orders)
+      .map(move |order| self.bytes_with_txs(order.into_iter()).hash_digest())
+  }
+
   pub fn nonce_matches_difficulty(&self, nonce: &[u8], difficulty: usize) -> bool {
     let mut block = self.clone();
     block.nonce = nonce.to_owned();
     block.hash_difficulty() >= difficulty
   }
 
+  /// legacy txs are only read from blocks converted from old storage,
+  /// a new block holding one is refused
+  pub fn verify_no_legacy_txs(&self) -> AppResult<()> {
+    match self.txs.values().any(|tx| tx.is_legacy()) {
+      true => Err(AppError::msg(format!("Block {} holds legacy txs", self.id))),
+      false => Ok(()),
+    }
+  }
+
+  /// transactions are independent of each other, so they are verified in parallel
+  pub fn verify_txs(&self) -> AppResult<()> {
+    self.verify_coinbase()?;
+    self.txs.par_iter().try_for_each(|(key, tx)| {
+      if key != &tx.hash_digest().to_string() {
+        return Err(AppError::msg(format!("Tx {} stored under wrong hash", key)));
+      }
+      match tx.is_coinbase() {
+        true => Ok(()),
+        false => tx.verify_signature(),
+      }
+    })
+  }
+
+  /// coinbase txs aren't signed, so a block may hold only one, spending nothing
+  /// and creating no more than the block reward
+  fn verify_coinbase(&self) -> AppResult<()> {
+    let mut coinbases = self.txs.values().filter(|tx| tx.is_coinbase());
+    let coinbase = match coinbases.next() {
+      Some(coinbase) => coinbase,
+      None => return Ok(()),
+    };
+    if coinbases.next().is_some() {
+      return Err(AppError::msg("Block has more than one coinbase tx"));
+    }
+    if !coinbase.inputs().is_empty() {
+      return Err(AppError::msg("Coinbase tx spends outputs"));
+    }
+    let (total, reward) = (coinbase.total()?, block_reward(self.id));
+    if total > reward {
+      return Err(AppError::msg(format!(
+        "Coinbase creates ¢{}, block reward is ¢{}",
+        total, reward
+      )));
+    }
+    Ok(())
+  }
+
   pub fn is_empty(&self) -> bool {
     self.txs.is_empty()
   }
@@ -153,6 +334,69 @@ mod tests {
     Ok(())
   }
 
+  #[async_std::test]
+  async fn multiple_txs_hash_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let txs = (1..10)
+      .map(|amount| Tx::new(&wallet, &wallet.address(), amount))
+      .collect::<AppResult<Vec<_>>>()?;
+    let block = Block::from_previous(&Block::default(), Some(txs));
+    let json = serde_json::to_string(&block)?;
+    let block1: Block = serde_json::from_str(&json)?;
+    assert_eq!(block.hash_digest(), block1.hash_digest());
+    assert_eq!(BlockHeader::from(&block).hash, block1.hash_digest());
+    assert_eq!(block.hashes_in_any_order().count(), 0);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn hashes_in_any_order_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let txs = (1..4)
+      .map(|amount| Tx::new(&wallet, &wallet.address(), amount))
+      .collect::<AppResult<Vec<_>>>()?;
+    let block = Block::from_previous(&Block::default(), Some(txs));
+    let hashes: Vec<_> = block.hashes_in_any_order().collect();
+    assert_eq!(hashes.len(), 6);
+    assert_eq!(hashes[0], block.hash_digest());
+    assert_eq!(hashes.iter().unique().count(), 6);
+    assert_eq!(Block::default().hashes_in_any_order().count(), 1);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn coinbase_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(GENESIS_REWARD)?]));
+    genesis.verify_txs()?;
+
+    let too_much = Block::new(Some([wallet.new_coinbase_tx(GENESIS_REWARD + 1)?]));
+    assert!(too_much.verify_txs().is_err());
+    let coinbases = [wallet.new_coinbase_tx(1)?, wallet.new_coinbase_tx(2)?];
+    assert!(Block::new(Some(coinbases)).verify_txs().is_err());
+    let forged = Block::from_previous(&genesis, Some([wallet.new_coinbase_tx(1)?]));
+    assert!(forged.verify_txs().is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn chain_params_test() -> AppResult<()> {
+    let genesis = Block::genesis(Ledger::Utxo, None::<Vec<Tx>>);
+    assert_eq!(genesis.ledger(), Ledger::Utxo);
+    let without = Block {
+      params: None,
+      ..genesis.clone()
+    };
+    assert_eq!(without.ledger(), Ledger::Account);
+    assert_ne!(without.hash_digest(), genesis.hash_digest());
+    genesis.verify_params()?;
+    let mut block1 = Block::from_previous(&genesis, None::<Vec<Tx>>);
+    block1.verify_params()?;
+    block1.params = genesis.params;
+    assert!(block1.verify_params().is_err());
+    Ok(())
+  }
+
   #[async_std::test]
   async fn difficulty_test_1() -> AppResult<()> {
     let mut block = Block::default();
@@ -1,5 +1,8 @@
+use crate::utxo::Ledger;
+use bchain_util::error::AppError;
 use once_cell::sync::Lazy;
 use std::env::var;
+use std::str::FromStr;
 use structopt::StructOpt;
 
 static DEFAULT_DATABASE: Lazy<String> =
@@ -13,8 +16,9 @@ static DEFAULT_LISTEN: Lazy<String> =
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(name = env!("CARGO_PKG_NAME"), version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = env!("CARGO_PKG_DESCRIPTION"))]
 pub struct Cli {
+  /// one or more multiaddrs, /ws ones need --websocket
   #[structopt(name = "listen", long = "--listen", default_value = &DEFAULT_LISTEN)]
-  pub listen: String,
+  pub listen: Vec<String>,
   #[structopt(name = "wallet", long = "--wallet", default_value = &DEFAULT_WALLET)]
   pub wallet: String,
   #[structopt(name = "database", long = "--db", default_value = &DEFAULT_DATABASE)]
@@ -27,6 +31,120 @@ pub struct Cli {
   pub delay: usize,
   #[structopt(name = "init", long = "--init")]
   pub init: bool,
+  /// account or utxo, fixed by the genesis block so only used with --init
+  #[structopt(name = "ledger", long = "--ledger", default_value = "account")]
+  pub ledger: Ledger,
+  /// keep only this many latest full blocks, older ones are reduced to headers
+  #[structopt(name = "prune", long = "--prune")]
+  pub prune: Option<i64>,
+  /// sqlite, sled or memory, sled uses --db as a directory
+  #[structopt(name = "store", long = "--store", default_value = "sqlite")]
+  pub store: StoreKind,
+  /// none, deflate, zstd or snappy, compression of messages sent to peers
+  #[structopt(
+    name = "compression",
+    long = "--compression",
+    default_value = "deflate"
+  )]
+  pub compression: Compression,
+  /// find peers on the local network
+  #[structopt(name = "mdns", long = "--mdns")]
+  pub mdns: bool,
+  /// find peers through a Kademlia DHT, --peers ending in /p2p/<peer id> bootstrap it
+  #[structopt(name = "kademlia", long = "--kademlia")]
+  pub kademlia: bool,
+  /// outbound connections kept up, known peers are dialed again when some drop
+  #[structopt(name = "target-peers", long = "--target-peers", default_value = "8")]
+  pub target_peers: usize,
+  /// also accept websocket connections, e.g. from browser clients
+  #[structopt(name = "websocket", long = "--websocket")]
+  pub websocket: bool,
+  #[structopt(name = "max-incoming", long = "--max-incoming", default_value = "50")]
+  pub max_incoming: u32,
+  #[structopt(name = "max-outgoing", long = "--max-outgoing", default_value = "50")]
+  pub max_outgoing: u32,
+  #[structopt(name = "max-per-peer", long = "--max-per-peer", default_value = "2")]
+  pub max_per_peer: u32,
+  /// seconds a connection without traffic stays open
+  #[structopt(name = "idle-timeout", long = "--idle-timeout", default_value = "120")]
+  pub idle_timeout: u64,
+  /// ping peers every this many seconds, keeping idle connections open
+  #[structopt(name = "keep-alive", long = "--keep-alive")]
+  pub keep_alive: Option<u64>,
+  /// stored peers not seen for this many days are forgotten
+  #[structopt(name = "peer-max-age", long = "--peer-max-age", default_value = "14")]
+  pub peer_max_age: i64,
+  #[structopt(subcommand)]
+  pub cmd: Option<CliCommand>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CliCommand {
+  /// Signs an unsigned transaction file with --wallet, without touching the network
+  Sign {
+    #[structopt(name = "input")]
+    input: String,
+    #[structopt(name = "output")]
+    output: Option<String>,
+  },
+  /// Writes blocks from --db to a portable archive
+  Export {
+    #[structopt(name = "from", long = "--from", default_value = "0")]
+    from: i64,
+    /// latest block if not given
+    #[structopt(name = "to", long = "--to")]
+    to: Option<i64>,
+    #[structopt(name = "output")]
+    output: String,
+  },
+  /// Validates blocks from an archive and appends them to --db
+  Import {
+    #[structopt(name = "input")]
+    input: String,
+  },
+}
+
+/// backend keeping the block chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreKind {
+  Sqlite,
+  Sled,
+  Memory,
+}
+
+impl FromStr for StoreKind {
+  type Err = AppError;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "sqlite" => Ok(StoreKind::Sqlite),
+      "sled" => Ok(StoreKind::Sled),
+      "memory" => Ok(StoreKind::Memory),
+      _ => Err(AppError::msg(format!("Unknown store {}", s))),
+    }
+  }
+}
+
+/// compression of network messages, receivers handle every kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+  None,
+  #[default]
+  Deflate,
+  Zstd,
+  Snappy,
+}
+
+impl FromStr for Compression {
+  type Err = AppError;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(Compression::None),
+      "deflate" => Ok(Compression::Deflate),
+      "zstd" => Ok(Compression::Zstd),
+      "snappy" => Ok(Compression::Snappy),
+      _ => Err(AppError::msg(format!("Unknown compression {}", s))),
+    }
+  }
 }
 
 impl Cli {
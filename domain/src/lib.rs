@@ -1,8 +1,12 @@
 pub mod address;
 pub mod block;
 pub mod cli;
+pub mod message;
+pub mod multisig;
 pub mod public_key;
 pub mod signature;
 pub mod tx;
+pub mod tx_file;
 pub mod tx_pool;
+pub mod utxo;
 pub mod wallet;
@@ -0,0 +1,56 @@
+use crate::address::Address;
+use crate::signature::Signature;
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::{AsBytes, Hashable};
+use bchain_util::result::AppResult;
+
+/// prepended to every signed message, so that a message signature
+/// can never be mistaken for a transaction signature
+pub const MESSAGE_PREFIX: &str = "bchain signed message:";
+
+pub(crate) fn message_body(text: &str) -> Vec<u8> {
+  let mut body = vec![];
+  body.extend_from_slice(MESSAGE_PREFIX.as_bytes());
+  body.extend_from_slice(&(text.len() as u64).as_bytes());
+  body.extend_from_slice(text.as_bytes());
+  body
+}
+
+pub fn verify_message(address: &Address, signature: &Signature, text: &str) -> AppResult<()> {
+  match address.public_key() {
+    Some(public_key) => {
+      let digest = message_body(text).hash_digest();
+      public_key.verify_signature(&digest.to_vec(), signature)
+    }
+    None => Err(AppError::msg("Only key addresses can sign messages")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tx::{Tx, TxOutput};
+  use crate::wallet::Wallet;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn sign_and_verify_message_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let signature = wallet.sign_message("I own this address")?;
+    verify_message(&wallet.address(), &signature, "I own this address")?;
+    assert!(verify_message(&wallet.address(), &signature, "I own that address").is_err());
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn message_signature_is_not_tx_signature_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let outputs = vec![TxOutput::new(&Address::default(), 1234)];
+    let body = Tx::transaction_body(&wallet.address(), &[], &outputs, 0);
+    let signature = wallet.sign_hashable(&body)?;
+    let text = String::from_utf8_lossy(&body);
+    assert!(verify_message(&wallet.address(), &signature, &text).is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,135 @@
+use crate::address::Address;
+use crate::public_key::PublicKey;
+use crate::signature::Signature;
+use crate::tx_file::{read_json, write_json};
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::{AsBytes, HashDigest, Hashable};
+use bchain_util::result::AppResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// m-of-n spending policy, keys are kept sorted so that
+/// the same set of keys always yields the same address
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
+pub struct MultisigPolicy {
+  threshold: usize,
+  keys: Vec<PublicKey>,
+}
+
+impl MultisigPolicy {
+  pub fn new<K: IntoIterator<Item = PublicKey>>(threshold: usize, keys: K) -> AppResult<Self> {
+    let mut keys: Vec<PublicKey> = keys.into_iter().collect();
+    keys.sort();
+    keys.dedup();
+    if threshold == 0 || threshold > keys.len() {
+      return Err(AppError::msg(format!(
+        "Threshold has to be between 1 and {}",
+        keys.len()
+      )));
+    }
+    Ok(MultisigPolicy { threshold, keys })
+  }
+
+  pub fn threshold(&self) -> usize {
+    self.threshold
+  }
+
+  pub fn keys(&self) -> &[PublicKey] {
+    &self.keys
+  }
+
+  pub fn address(&self) -> Address {
+    Address::Multisig(self.hash_digest())
+  }
+
+  pub fn key_index(&self, public_key: &PublicKey) -> Option<usize> {
+    self.keys.iter().position(|k| k == public_key)
+  }
+
+  pub async fn read(path: &str) -> AppResult<Self> {
+    read_json(path).await
+  }
+
+  pub async fn write(&self, path: &str) -> AppResult<()> {
+    write_json(self, path).await
+  }
+}
+
+impl AsBytes for MultisigPolicy {
+  fn as_bytes(&self) -> Vec<u8> {
+    let mut res = vec![];
+    res.extend_from_slice(&(self.threshold as u64).as_bytes());
+    for key in &self.keys {
+      res.extend_from_slice(&key.as_bytes());
+    }
+    res
+  }
+}
+
+impl Hashable for MultisigPolicy {}
+
+/// signatures collected for a multisig spend, keyed by position of the signer in the policy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
+pub struct MultisigWitness {
+  pub policy: MultisigPolicy,
+  pub signatures: BTreeMap<usize, Signature>,
+}
+
+impl MultisigWitness {
+  pub fn new(policy: &MultisigPolicy) -> Self {
+    MultisigWitness {
+      policy: policy.clone(),
+      signatures: BTreeMap::new(),
+    }
+  }
+
+  pub fn valid_signatures(&self, digest: &HashDigest) -> usize {
+    self
+      .signatures
+      .iter()
+      .filter(|(&idx, sig)| match self.policy.keys.get(idx) {
+        Some(key) => key.verify_signature(&digest.to_vec(), sig).is_ok(),
+        None => false,
+      })
+      .count()
+  }
+
+  pub fn verify(&self, sender: &Address, digest: &HashDigest) -> AppResult<()> {
+    if &self.policy.address() != sender {
+      return Err(AppError::msg("Multisig policy does not match sender"));
+    }
+    let valid = self.valid_signatures(digest);
+    if valid < self.policy.threshold {
+      return Err(AppError::msg(format!(
+        "Multisig needs {} signatures, got {}",
+        self.policy.threshold, valid
+      )));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::wallet::Wallet;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn multisig_address_is_order_independent_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let keys = vec![wallet.public_key(), PublicKey::default()];
+    let policy1 = MultisigPolicy::new(1, keys.clone())?;
+    let policy2 = MultisigPolicy::new(1, keys.into_iter().rev())?;
+    assert_eq!(policy1.address(), policy2.address());
+    Ok(())
+  }
+
+  #[test]
+  fn multisig_threshold_test() -> AppResult<()> {
+    assert!(MultisigPolicy::new(0, vec![PublicKey::default()]).is_err());
+    assert!(MultisigPolicy::new(2, vec![PublicKey::default()]).is_err());
+    Ok(())
+  }
+}
@@ -7,13 +7,14 @@ use rsa::{BigUint, PaddingScheme, PublicKey as _, RsaPublicKey};
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::str::FromStr;
 
 pub const PUBLIC_KEY_LENGTH: usize = 0x100; //256
 pub const PADDING: PaddingScheme = PaddingScheme::PKCS1v15Sign {
   hash: Some(Hash::SHA2_256),
 };
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash, PartialOrd, Ord)]
 pub struct PublicKey(Vec<u8>);
 
 impl Deref for PublicKey {
@@ -48,14 +49,15 @@ impl AsBytes for PublicKey {
   }
 }
 
-impl From<Address> for PublicKey {
-  fn from(addr: Address) -> Self {
-    addr.0
+impl Hashable for PublicKey {}
+
+impl FromStr for PublicKey {
+  type Err = AppError;
+  fn from_str(hex_key: &str) -> std::result::Result<Self, <Self as std::str::FromStr>::Err> {
+    PublicKey::try_new(&hex::decode(hex_key)?)
   }
 }
 
-impl Hashable for PublicKey {}
-
 impl PublicKey {
   pub fn try_new(bytes: &[u8]) -> AppResult<Self> {
     if bytes.len() >= PUBLIC_KEY_LENGTH {
@@ -2,8 +2,10 @@ use bchain_util::error::AppError;
 use bchain_util::hash_digest::{AsBytes, Hashable};
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::fmt::Display;
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::str::FromStr;
 
 const SIGNATURE_LENGTH: usize = 256;
 
@@ -50,3 +52,16 @@ impl TryFrom<&[u8]> for Signature {
     }
   }
 }
+
+impl Display for Signature {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+    write!(f, "{}", hex::encode(&self.0))
+  }
+}
+
+impl FromStr for Signature {
+  type Err = AppError;
+  fn from_str(hex_sig: &str) -> std::result::Result<Self, <Self as std::str::FromStr>::Err> {
+    Signature::try_from(&hex::decode(hex_sig)?[..])
+  }
+}
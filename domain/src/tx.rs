@@ -1,73 +1,287 @@
 use crate::address::Address;
-use crate::public_key::PublicKey;
+use crate::multisig::MultisigWitness;
 use crate::signature::Signature;
+use crate::utxo::OutPoint;
 use crate::wallet::Wallet;
+use bchain_util::error::AppError;
 use bchain_util::hash_digest::{AsBytes, Hashable};
 use bchain_util::result::AppResult;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
+pub struct TxOutput {
+  pub receiver: Address,
+  pub amount: u64,
+}
+
+impl TxOutput {
+  pub fn new(receiver: &Address, amount: u64) -> TxOutput {
+    TxOutput {
+      receiver: receiver.clone(),
+      amount,
+    }
+  }
+}
+
+impl AsBytes for TxOutput {
+  fn as_bytes(&self) -> std::vec::Vec<u8> {
+    let mut res = vec![];
+    res.extend_from_slice(&self.amount.as_bytes());
+    res.extend_from_slice(&self.receiver.as_bytes());
+    res
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
 pub struct Tx {
-  amount: u64,
+  #[serde(default)]
+  inputs: Vec<OutPoint>, // spent outputs, used by the utxo ledger
+  outputs: Vec<TxOutput>,
   timestamp: i64,
   sender: Address, //PublicKey::default() for coinbase tx
+  signature: Signature,
+  #[serde(default)]
+  multisig: Option<MultisigWitness>, // present only when sender is a multisig address
+  #[serde(default)]
+  legacy: bool, // read from blocks stored before multiple outputs, hashed and signed the old way
+}
+
+/// every field of a tx, storage keeps its own layout of them so that
+/// a field added to `Tx` doesn't change how stored txs are read
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxParts {
+  pub inputs: Vec<OutPoint>,
+  pub outputs: Vec<TxOutput>,
+  pub timestamp: i64,
+  pub sender: Address,
+  pub signature: Signature,
+  pub multisig: Option<MultisigWitness>,
+  pub legacy: bool,
+}
+
+impl From<TxParts> for Tx {
+  fn from(parts: TxParts) -> Self {
+    Tx {
+      inputs: parts.inputs,
+      outputs: parts.outputs,
+      timestamp: parts.timestamp,
+      sender: parts.sender,
+      signature: parts.signature,
+      multisig: parts.multisig,
+      legacy: parts.legacy,
+    }
+  }
+}
+
+impl From<&Tx> for TxParts {
+  fn from(tx: &Tx) -> Self {
+    TxParts {
+      inputs: tx.inputs.clone(),
+      outputs: tx.outputs.clone(),
+      timestamp: tx.timestamp,
+      sender: tx.sender.clone(),
+      signature: tx.signature.clone(),
+      multisig: tx.multisig.clone(),
+      legacy: tx.legacy,
+    }
+  }
+}
+
+/// tx as serialized before multiple outputs, only read from old databases
+#[derive(Debug, Clone, Deserialize)]
+pub struct LegacyTx {
+  amount: u64,
+  timestamp: i64,
+  sender: Address,
   receiver: Address,
   signature: Signature,
 }
 
+impl From<LegacyTx> for Tx {
+  fn from(tx: LegacyTx) -> Self {
+    Tx {
+      inputs: vec![],
+      outputs: vec![TxOutput::new(&tx.receiver, tx.amount)],
+      timestamp: tx.timestamp,
+      sender: tx.sender,
+      signature: tx.signature,
+      multisig: None,
+      legacy: true,
+    }
+  }
+}
+
 impl Tx {
-  fn transaction_body(amount: u64, sender: &Address, receiver: &Address) -> Vec<u8> {
+  /// what gets signed, the timestamp makes a resent tx a different one,
+  /// a tx already in the chain can't be included again
+  pub(crate) fn transaction_body(
+    sender: &Address,
+    inputs: &[OutPoint],
+    outputs: &[TxOutput],
+    timestamp: i64,
+  ) -> Vec<u8> {
     let mut transaction_body = vec![];
-    transaction_body.extend_from_slice(&amount.as_bytes());
+    transaction_body.extend_from_slice(&timestamp.as_bytes());
     transaction_body.extend_from_slice(&sender.as_bytes());
-    transaction_body.extend_from_slice(&receiver.as_bytes());
+    for input in inputs {
+      transaction_body.extend_from_slice(&input.as_bytes());
+    }
+    for output in outputs {
+      transaction_body.extend_from_slice(&output.as_bytes());
+    }
     transaction_body
   }
 
+  /// sum of all outputs, it has to fit into i64 so balances can't overflow either
+  pub fn outputs_total(outputs: &[TxOutput]) -> AppResult<u64> {
+    if outputs.is_empty() {
+      return Err(AppError::msg("Transaction has no outputs"));
+    }
+    outputs
+      .iter()
+      .try_fold(0u64, |acc, output| acc.checked_add(output.amount))
+      .filter(|&total| total <= i64::MAX as u64)
+      .ok_or_else(|| AppError::msg("Transaction outputs total overflows"))
+  }
+
   pub fn new_coinbase(wallet: &Wallet, amount: u64) -> AppResult<Tx> {
     let sender = Address::default();
-    let receiver = wallet.address();
-    let transaction_body = Tx::transaction_body(amount, &sender, &receiver);
-    let signature = wallet.sign_hashable(&transaction_body)?;
+    let outputs = vec![TxOutput::new(&wallet.address(), amount)];
     let timestamp = Utc::now().timestamp();
+    let transaction_body = Tx::transaction_body(&sender, &[], &outputs, timestamp);
+    let signature = wallet.sign_hashable(&transaction_body)?;
     Ok(Tx {
-      amount,
+      inputs: vec![],
+      outputs,
       timestamp,
       sender,
-      receiver,
       signature,
+      multisig: None,
+      legacy: false,
     })
   }
 
   pub fn new(wallet: &Wallet, receiver: &Address, amount: u64) -> AppResult<Tx> {
+    Tx::with_outputs(wallet, vec![TxOutput::new(receiver, amount)])
+  }
+
+  pub fn with_outputs(wallet: &Wallet, outputs: Vec<TxOutput>) -> AppResult<Tx> {
+    Tx::spending(wallet, vec![], outputs)
+  }
+
+  /// transaction for the utxo ledger, any change has to be paid back as an explicit output
+  pub fn spending(wallet: &Wallet, inputs: Vec<OutPoint>, outputs: Vec<TxOutput>) -> AppResult<Tx> {
+    Tx::new_with_timestamp(wallet, inputs, outputs, Utc::now().timestamp())
+  }
+
+  pub(crate) fn new_with_timestamp(
+    wallet: &Wallet,
+    inputs: Vec<OutPoint>,
+    outputs: Vec<TxOutput>,
+    timestamp: i64,
+  ) -> AppResult<Tx> {
+    Tx::outputs_total(&outputs)?;
     let sender = wallet.address();
-    let transaction_body = Tx::transaction_body(amount, &sender, receiver);
+    let transaction_body = Tx::transaction_body(&sender, &inputs, &outputs, timestamp);
     let signature = wallet.sign_hashable(&transaction_body)?;
-    let receiver = receiver.clone();
-    let timestamp = Utc::now().timestamp();
     Ok(Tx {
-      amount,
+      inputs,
+      outputs,
       timestamp,
       sender,
-      receiver,
       signature,
+      multisig: None,
+      legacy: false,
     })
   }
 
+  pub(crate) fn new_multisig(
+    witness: MultisigWitness,
+    inputs: Vec<OutPoint>,
+    outputs: Vec<TxOutput>,
+    timestamp: i64,
+  ) -> AppResult<Tx> {
+    let tx = Tx {
+      inputs,
+      outputs,
+      timestamp,
+      sender: witness.policy.address(),
+      signature: Signature::default(),
+      multisig: Some(witness),
+      legacy: false,
+    };
+    tx.verify_signature()?;
+    Ok(tx)
+  }
+
+  pub fn sender(&self) -> &Address {
+    &self.sender
+  }
+
+  pub fn inputs(&self) -> &[OutPoint] {
+    &self.inputs
+  }
+
+  pub fn outputs(&self) -> &[TxOutput] {
+    &self.outputs
+  }
+
+  pub fn is_coinbase(&self) -> bool {
+    self.sender == Address::default()
+  }
+
+  pub fn is_legacy(&self) -> bool {
+    self.legacy
+  }
+
+  pub fn timestamp(&self) -> i64 {
+    self.timestamp
+  }
+
+  pub fn total(&self) -> AppResult<u64> {
+    Tx::outputs_total(&self.outputs)
+  }
+
+  /// what legacy txs signed, the amount, sender and receiver of their only output
+  fn legacy_body(&self) -> AppResult<Vec<u8>> {
+    match (&self.inputs[..], &self.outputs[..], &self.multisig) {
+      ([], [output], None) => {
+        let mut transaction_body = vec![];
+        transaction_body.extend_from_slice(&output.amount.as_bytes());
+        transaction_body.extend_from_slice(&self.sender.as_bytes());
+        transaction_body.extend_from_slice(&output.receiver.as_bytes());
+        Ok(transaction_body)
+      }
+      _ => Err(AppError::msg("Legacy tx has to have a single output")),
+    }
+  }
+
   pub fn verify_signature(&self) -> AppResult<()> {
-    let transaction_body = Tx::transaction_body(self.amount, &self.sender, &self.receiver);
-    let pub_key: PublicKey = self.sender.clone().into();
-    pub_key.verify_signature(&transaction_body.hash_digest().to_vec(), &self.signature)
+    self.total()?;
+    let transaction_body = match self.legacy {
+      true => self.legacy_body()?,
+      false => Tx::transaction_body(&self.sender, &self.inputs, &self.outputs, self.timestamp),
+    };
+    let digest = transaction_body.hash_digest();
+    match (&self.sender, &self.multisig) {
+      (Address::Key(pub_key), None) => pub_key.verify_signature(&digest.to_vec(), &self.signature),
+      (Address::Multisig(_), Some(witness)) => witness.verify(&self.sender, &digest),
+      _ => Err(AppError::msg("Signature does not match sender address")),
+    }
   }
 
   pub fn diff_for_address(&self, address: &Address) -> i64 {
+    let received: i64 = self
+      .outputs
+      .iter()
+      .filter(|output| &output.receiver == address)
+      .map(|output| output.amount as i64)
+      .sum();
     if address == &self.sender {
-      0 - (self.amount as i64)
-    } else if address == &self.receiver {
-      self.amount as i64
+      received - self.total().unwrap_or_default() as i64
     } else {
-      0
+      received
     }
   }
 }
@@ -75,10 +289,26 @@ impl Tx {
 impl AsBytes for Tx {
   fn as_bytes(&self) -> std::vec::Vec<u8> {
     let mut res = vec![];
-    res.extend_from_slice(&self.amount.as_bytes());
+    if self.legacy {
+      // amount, timestamp, sender and receiver, as the block hashes of old chains expect
+      for output in &self.outputs {
+        res.extend_from_slice(&output.amount.as_bytes());
+      }
+      res.extend_from_slice(&self.timestamp.as_bytes());
+      res.extend_from_slice(&self.sender.as_bytes());
+      for output in &self.outputs {
+        res.extend_from_slice(&output.receiver.as_bytes());
+      }
+      return res;
+    }
     res.extend_from_slice(&self.timestamp.as_bytes());
     res.extend_from_slice(&self.sender.as_bytes());
-    res.extend_from_slice(&self.receiver.as_bytes());
+    for input in &self.inputs {
+      res.extend_from_slice(&input.as_bytes());
+    }
+    for output in &self.outputs {
+      res.extend_from_slice(&output.as_bytes());
+    }
     // do not hash signature
     // signature used to sign bytes, so cant be included!
     res
@@ -127,4 +357,37 @@ mod tests {
     assert!(sig_verify_result.is_err());
     Ok(())
   }
+
+  #[async_std::test]
+  async fn timestamp_is_signed_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut tx = Tx::new(&wallet, &Address::default(), 1234)?;
+    tx.timestamp += 1; // resent as a new tx
+    assert!(tx.verify_signature().is_err());
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn multiple_outputs_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let other = Address::default();
+    let outputs = vec![TxOutput::new(&other, 10), TxOutput::new(&other, 20)];
+    let tx = Tx::with_outputs(&wallet, outputs)?;
+    tx.verify_signature()?;
+    assert_eq!(tx.diff_for_address(&other), 30);
+    assert_eq!(tx.diff_for_address(&wallet.address()), -30);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn reject_overflowing_outputs_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let other = Address::default();
+    let outputs = vec![TxOutput::new(&other, u64::MAX), TxOutput::new(&other, 1)];
+    assert!(Tx::with_outputs(&wallet, outputs).is_err());
+    let outputs = vec![TxOutput::new(&other, i64::MAX as u64 + 1)];
+    assert!(Tx::with_outputs(&wallet, outputs).is_err());
+    assert!(Tx::with_outputs(&wallet, vec![]).is_err());
+    Ok(())
+  }
 }
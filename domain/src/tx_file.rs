@@ -0,0 +1,218 @@
+use crate::address::Address;
+use crate::multisig::{MultisigPolicy, MultisigWitness};
+use crate::tx::{Tx, TxOutput};
+use crate::utxo::OutPoint;
+use crate::wallet::Wallet;
+use async_std::fs;
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::Hashable;
+use bchain_util::result::AppResult;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// transaction that has been prepared on a networked node,
+/// but is still to be signed, possibly on an air-gapped machine
+/// sender is not known until signing, it is derived from the signing wallet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnsignedTx {
+  #[serde(default)]
+  pub inputs: Vec<OutPoint>,
+  pub outputs: Vec<TxOutput>,
+  pub timestamp: i64,
+}
+
+impl UnsignedTx {
+  pub fn new(receiver: &Address, amount: u64) -> UnsignedTx {
+    UnsignedTx::with_outputs(vec![TxOutput::new(receiver, amount)])
+  }
+
+  pub fn with_outputs(outputs: Vec<TxOutput>) -> UnsignedTx {
+    UnsignedTx {
+      inputs: vec![],
+      outputs,
+      timestamp: Utc::now().timestamp(),
+    }
+  }
+
+  pub fn sign(&self, wallet: &Wallet) -> AppResult<Tx> {
+    let inputs = self.inputs.clone();
+    Tx::new_with_timestamp(wallet, inputs, self.outputs.clone(), self.timestamp)
+  }
+}
+
+/// multisig spend that signers fill in one by one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartiallySignedTx {
+  pub unsigned: UnsignedTx,
+  pub witness: MultisigWitness,
+}
+
+impl PartiallySignedTx {
+  pub fn new(unsigned: UnsignedTx, policy: &MultisigPolicy) -> Self {
+    PartiallySignedTx {
+      unsigned,
+      witness: MultisigWitness::new(policy),
+    }
+  }
+
+  fn transaction_body(&self) -> Vec<u8> {
+    let sender = self.witness.policy.address();
+    let unsigned = &self.unsigned;
+    Tx::transaction_body(
+      &sender,
+      &unsigned.inputs,
+      &unsigned.outputs,
+      unsigned.timestamp,
+    )
+  }
+
+  pub fn sign(&mut self, wallet: &Wallet) -> AppResult<()> {
+    match self.witness.policy.key_index(&wallet.public_key()) {
+      Some(idx) => {
+        let signature = wallet.sign_hashable(&self.transaction_body())?;
+        self.witness.signatures.insert(idx, signature);
+        Ok(())
+      }
+      None => Err(AppError::msg("Wallet is not a signer of this multisig")),
+    }
+  }
+
+  pub fn signatures(&self) -> usize {
+    let digest = self.transaction_body().hash_digest();
+    self.witness.valid_signatures(&digest)
+  }
+
+  pub fn is_complete(&self) -> bool {
+    self.signatures() >= self.witness.policy.threshold()
+  }
+
+  pub fn finalize(self) -> AppResult<Tx> {
+    let UnsignedTx {
+      inputs,
+      outputs,
+      timestamp,
+    } = self.unsigned;
+    Tx::new_multisig(self.witness, inputs, outputs, timestamp)
+  }
+}
+
+/// on-disk format used to move transactions between machines
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TxFile {
+  Unsigned(UnsignedTx),
+  PartiallySigned(PartiallySignedTx),
+  Signed(Tx),
+}
+
+impl TxFile {
+  pub async fn read(path: &str) -> AppResult<TxFile> {
+    read_json(path).await
+  }
+
+  pub async fn write(&self, path: &str) -> AppResult<()> {
+    write_json(self, path).await
+  }
+
+  /// verified transaction, ready to be sent to the network
+  pub fn signed(self) -> AppResult<Tx> {
+    match self {
+      TxFile::Signed(tx) => {
+        tx.verify_signature()?;
+        Ok(tx)
+      }
+      TxFile::PartiallySigned(partial) => partial.finalize(),
+      _ => Err(AppError::msg("Transaction file is not signed")),
+    }
+  }
+}
+
+/// signs transaction from `input` writing the result to `output`,
+/// multisig transactions get one more signature
+pub async fn sign_tx_file(wallet: &Wallet, input: &str, output: &str) -> AppResult<TxFile> {
+  let signed = match TxFile::read(input).await? {
+    TxFile::Unsigned(unsigned) => TxFile::Signed(unsigned.sign(wallet)?),
+    TxFile::PartiallySigned(mut partial) => {
+      partial.sign(wallet)?;
+      TxFile::PartiallySigned(partial)
+    }
+    TxFile::Signed(_) => return Err(AppError::msg("Transaction file is already signed")),
+  };
+  signed.write(output).await?;
+  Ok(signed)
+}
+
+pub(crate) async fn read_json<T: DeserializeOwned>(path: &str) -> AppResult<T> {
+  let json = fs::read_to_string(path).await?;
+  Ok(serde_json::from_str(&json)?)
+}
+
+pub(crate) async fn write_json<T: Serialize>(value: &T, path: &str) -> AppResult<()> {
+  let json = serde_json::to_string_pretty(value)?;
+  fs::write(path, json).await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::public_key::PublicKey;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn sign_unsigned_tx_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let unsigned = UnsignedTx::new(&wallet.address(), 1234);
+    let json = serde_json::to_string(&TxFile::Unsigned(unsigned.clone()))?;
+    let unsigned1 = match serde_json::from_str(&json)? {
+      TxFile::Unsigned(unsigned) => unsigned,
+      _ => panic!("expected unsigned tx"),
+    };
+    assert_eq!(unsigned, unsigned1);
+
+    let tx = unsigned1.sign(&wallet)?;
+    let tx1 = TxFile::Signed(tx.clone()).signed()?;
+    assert_eq!(tx.hash_digest(), tx1.hash_digest());
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn unsigned_is_not_signed_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let file = TxFile::Unsigned(UnsignedTx::new(&wallet.address(), 1234));
+    assert!(file.signed().is_err());
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn partially_signed_tx_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let keys = vec![wallet.public_key(), PublicKey::default()];
+    let policy = MultisigPolicy::new(1, keys)?;
+    let mut partial = PartiallySignedTx::new(UnsignedTx::new(&wallet.address(), 1234), &policy);
+    assert!(!partial.is_complete());
+    assert!(partial.clone().finalize().is_err());
+
+    partial.sign(&wallet)?;
+    assert!(partial.is_complete());
+    let tx = TxFile::PartiallySigned(partial).signed()?;
+    tx.verify_signature()?;
+    assert_eq!(tx.diff_for_address(&policy.address()), -1234);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn multisig_threshold_not_met_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let keys = vec![wallet.public_key(), PublicKey::default()];
+    let policy = MultisigPolicy::new(2, keys)?;
+    let mut partial = PartiallySignedTx::new(UnsignedTx::new(&wallet.address(), 1234), &policy);
+    partial.sign(&wallet)?;
+    assert_eq!(partial.signatures(), 1);
+    assert!(!partial.is_complete());
+    assert!(partial.finalize().is_err());
+    Ok(())
+  }
+}
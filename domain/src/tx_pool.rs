@@ -1,11 +1,23 @@
-use crate::{block::Block, tx::Tx};
+use crate::{address::Address, block::Block, tx::Tx, utxo::OutPoint};
 use async_trait::async_trait;
-use bchain_util::{mine::Mine, result::AppResult};
+use bchain_util::{error::AppError, mine::Mine, result::AppResult};
 use std::collections::HashSet;
 
-#[derive(Debug, Default)]
+/// most txs kept pending by default, the oldest is evicted for a newer one
+pub const MAX_POOL_SIZE: usize = 5000;
+/// seconds a tx stays pending after its timestamp
+pub const MAX_TX_AGE: i64 = 3 * 60 * 60;
+
+#[derive(Debug)]
 pub struct TxPool {
   pool: HashSet<Tx>,
+  max_size: usize,
+}
+
+impl Default for TxPool {
+  fn default() -> Self {
+    TxPool::new(MAX_POOL_SIZE)
+  }
 }
 
 #[async_trait]
@@ -16,12 +28,111 @@ impl Mine for TxPool {
 }
 
 impl TxPool {
-  pub fn add(&mut self, tx: Tx) -> AppResult<()> {
+  pub fn new(max_size: usize) -> TxPool {
+    TxPool {
+      pool: HashSet::new(),
+      max_size,
+    }
+  }
+
+  /// expires old txs first, `now` is in seconds like tx timestamps
+  pub fn add(&mut self, tx: Tx, now: i64) -> AppResult<()> {
+    self.expire(now);
+    if tx.timestamp() < now - MAX_TX_AGE {
+      return Err(AppError::msg("Tx is too old to be pooled"));
+    }
+    if self.pool.len() >= self.max_size && !self.pool.contains(&tx) {
+      let oldest = self.pool.iter().min_by_key(|tx| tx.timestamp()).cloned();
+      match oldest {
+        Some(oldest) if oldest.timestamp() < tx.timestamp() => self.pool.remove(&oldest),
+        _ => return Err(AppError::msg("Tx pool is full")),
+      };
+    }
     self.pool.insert(tx);
     Ok(())
   }
 
+  /// drops txs older than `MAX_TX_AGE`, returns how many
+  pub fn expire(&mut self, now: i64) -> usize {
+    let len = self.pool.len();
+    self.pool.retain(|tx| tx.timestamp() >= now - MAX_TX_AGE);
+    len - self.pool.len()
+  }
+
+  pub fn len(&self) -> usize {
+    self.pool.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.pool.is_empty()
+  }
+
+  pub fn txs(&self) -> impl Iterator<Item = &Tx> {
+    self.pool.iter()
+  }
+
+  /// total pending txs of `sender` take from its balance
+  pub fn spent_by(&self, sender: &Address) -> i64 {
+    self
+      .pool
+      .iter()
+      .filter(|tx| tx.sender() == sender)
+      .map(|tx| -tx.diff_for_address(sender))
+      .sum()
+  }
+
+  /// whether a pending tx already spends `outpoint`
+  pub fn spends(&self, outpoint: &OutPoint) -> bool {
+    self.pool.iter().any(|tx| tx.inputs().contains(outpoint))
+  }
+
+  /// txs of a block are no longer pending
+  pub fn remove_block(&mut self, block: &Block) {
+    for tx in block.txs.values() {
+      self.pool.remove(tx);
+    }
+  }
+
   pub fn proposed_block(&self) -> Block {
     Block::default()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tx::TxOutput;
+  use crate::wallet::Wallet;
+  use chrono::Utc;
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn pool_bounds_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let now = Utc::now().timestamp();
+    let tx_at = |timestamp| {
+      let outputs = vec![TxOutput::new(&Address::default(), 1)];
+      Tx::new_with_timestamp(&wallet, vec![], outputs, timestamp)
+    };
+    let mut pool = TxPool::new(3);
+
+    assert!(pool.add(tx_at(now - MAX_TX_AGE - 1)?, now).is_err());
+    let tx = tx_at(now - 10)?;
+    pool.add(tx.clone(), now)?;
+    assert_eq!(pool.expire(now + MAX_TX_AGE - 10), 0);
+    assert_eq!(pool.expire(now + MAX_TX_AGE - 9), 1);
+    assert!(pool.is_empty());
+
+    for i in 0..3 {
+      pool.add(tx_at(now - i)?, now)?;
+    }
+    assert_eq!(pool.spent_by(&wallet.address()), 3);
+    assert!(pool.add(tx, now).is_err());
+    let newer = tx_at(now + 1)?;
+    pool.add(newer.clone(), now)?;
+    assert_eq!(pool.len(), 3);
+    assert!(pool.txs().any(|tx| tx == &newer));
+    assert!(!pool.txs().any(|tx| tx.timestamp() == now - 2));
+    Ok(())
+  }
+}
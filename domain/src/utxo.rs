@@ -0,0 +1,108 @@
+use crate::tx::TxOutput;
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::{AsBytes, HashDigest};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// reference to an output of an earlier transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
+pub struct OutPoint {
+  pub tx: HashDigest,
+  pub index: u32,
+}
+
+impl OutPoint {
+  pub fn new(tx: &HashDigest, index: u32) -> OutPoint {
+    OutPoint { tx: *tx, index }
+  }
+}
+
+impl AsBytes for OutPoint {
+  fn as_bytes(&self) -> Vec<u8> {
+    let mut res = self.tx.as_bytes();
+    res.extend_from_slice(&(self.index as u64).as_bytes());
+    res
+  }
+}
+
+impl Display for OutPoint {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}:{}", self.tx, self.index)
+  }
+}
+
+impl FromStr for OutPoint {
+  type Err = AppError;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.split_once(':') {
+      Some((tx, index)) => Ok(OutPoint {
+        tx: HashDigest::try_from(tx.to_owned())?,
+        index: index.parse()?,
+      }),
+      None => Err(AppError::msg(format!("Invalid outpoint {}", s))),
+    }
+  }
+}
+
+/// unspent transaction output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash)]
+pub struct Utxo {
+  pub outpoint: OutPoint,
+  pub output: TxOutput,
+}
+
+/// which ledger model validates transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Ledger {
+  /// balances are plain sums over all transactions
+  #[default]
+  Account,
+  /// every non-coinbase transaction has to spend existing outputs
+  Utxo,
+}
+
+impl AsBytes for Ledger {
+  fn as_bytes(&self) -> Vec<u8> {
+    match self {
+      Ledger::Account => vec![0],
+      Ledger::Utxo => vec![1],
+    }
+  }
+}
+
+impl Display for Ledger {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Ledger::Account => write!(f, "account"),
+      Ledger::Utxo => write!(f, "utxo"),
+    }
+  }
+}
+
+impl FromStr for Ledger {
+  type Err = AppError;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "account" => Ok(Ledger::Account),
+      "utxo" => Ok(Ledger::Utxo),
+      _ => Err(AppError::msg(format!("Unknown ledger {}", s))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::hash_digest::Hashable;
+  use bchain_util::result::AppResult;
+
+  #[test]
+  fn outpoint_parse_test() -> AppResult<()> {
+    let outpoint = OutPoint::new(&"abc".hash_digest(), 3);
+    let outpoint1: OutPoint = outpoint.to_string().parse()?;
+    assert_eq!(outpoint, outpoint1);
+    Ok(())
+  }
+}
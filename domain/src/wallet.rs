@@ -1,6 +1,7 @@
 use crate::address::Address;
+use crate::message::message_body;
 use crate::public_key::PublicKey;
-use crate::tx::Tx;
+use crate::tx::{Tx, TxOutput};
 use bchain_util::hash_digest::Hashable;
 use bchain_util::result::AppResult;
 use pkcs8::{FromPrivateKey, PrivateKeyDocument, ToPrivateKey};
@@ -33,8 +34,8 @@ impl Wallet {
     }
   }
 
-  pub fn new_tx(&self, receiver: &Address, amount: u64) -> AppResult<Tx> {
-    Tx::new(self, receiver, amount)
+  pub fn new_tx(&self, outputs: Vec<TxOutput>) -> AppResult<Tx> {
+    Tx::with_outputs(self, outputs)
   }
 
   pub fn new_coinbase_tx(&self, amount: u64) -> AppResult<Tx> {
@@ -62,6 +63,10 @@ impl Wallet {
     Ok(signature)
   }
 
+  pub fn sign_message(&self, text: &str) -> AppResult<Signature> {
+    self.sign_hashable(&message_body(text))
+  }
+
   pub fn to_pkcs8_der(&self) -> AppResult<Vec<u8>> {
     let der: PrivateKeyDocument = self.private_key.to_pkcs8_der()?;
     Ok(der.as_ref().to_vec())
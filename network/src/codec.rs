@@ -0,0 +1,149 @@
+use bchain_domain::cli::Compression;
+use bchain_util::error::AppError;
+use bchain_util::result::AppResult;
+use bincode::Options;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+/// wire format: version, compression, payload length as u32 le, bincode payload
+pub const WIRE_VERSION: u8 = 1;
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+/// smaller payloads aren't worth compressing
+const COMPRESS_THRESHOLD: usize = 1024;
+pub const HEADER_LEN: usize = 6;
+
+const NO_COMPRESSION: u8 = 0;
+const DEFLATE: u8 = 1;
+const ZSTD: u8 = 2;
+const SNAPPY: u8 = 3;
+
+fn bincode_options() -> impl Options {
+  bincode::DefaultOptions::new()
+    .with_fixint_encoding()
+    .with_limit(MAX_MESSAGE_SIZE as u64)
+}
+
+pub fn encode<T: Serialize>(message: &T, compression: Compression) -> AppResult<Vec<u8>> {
+  let payload = bincode_options().serialize(message)?;
+  let (method, payload) = match compression {
+    Compression::Deflate if payload.len() > COMPRESS_THRESHOLD => {
+      let mut encoder = DeflateEncoder::new(vec![], flate2::Compression::default());
+      encoder.write_all(&payload)?;
+      (DEFLATE, encoder.finish()?)
+    }
+    Compression::Zstd if payload.len() > COMPRESS_THRESHOLD => (
+      ZSTD,
+      zstd::encode_all(&payload[..], zstd::DEFAULT_COMPRESSION_LEVEL)?,
+    ),
+    Compression::Snappy if payload.len() > COMPRESS_THRESHOLD => {
+      let mut encoder = snap::write::FrameEncoder::new(vec![]);
+      encoder.write_all(&payload)?;
+      (SNAPPY, encoder.into_inner().map_err(|e| e.into_error())?)
+    }
+    _ => (NO_COMPRESSION, payload),
+  };
+  if payload.len() > MAX_MESSAGE_SIZE {
+    return Err(AppError::msg(format!(
+      "Message of {} bytes is too large",
+      payload.len()
+    )));
+  }
+  let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+  bytes.extend_from_slice(&[WIRE_VERSION, method]);
+  bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+  bytes.extend_from_slice(&payload);
+  Ok(bytes)
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> AppResult<T> {
+  if bytes.len() < HEADER_LEN {
+    return Err(AppError::msg("Message too short"));
+  }
+  let (header, payload) = bytes.split_at(HEADER_LEN);
+  if header[0] != WIRE_VERSION {
+    return Err(AppError::msg(format!(
+      "Unsupported wire version {}",
+      header[0]
+    )));
+  }
+  let len = u32::from_le_bytes(<[u8; 4]>::try_from(&header[2..])?) as usize;
+  if len > MAX_MESSAGE_SIZE || len != payload.len() {
+    return Err(AppError::msg(format!("Invalid message length {}", len)));
+  }
+  let payload = match header[1] {
+    NO_COMPRESSION => payload.to_vec(),
+    DEFLATE => inflate(DeflateDecoder::new(payload))?,
+    ZSTD => inflate(zstd::stream::read::Decoder::new(payload)?)?,
+    SNAPPY => inflate(snap::read::FrameDecoder::new(payload))?,
+    other => return Err(AppError::msg(format!("Unknown compression {}", other))),
+  };
+  Ok(bincode_options().deserialize(&payload)?)
+}
+
+/// bounded, a small message must not inflate beyond the limit
+fn inflate<R: Read>(decoder: R) -> AppResult<Vec<u8>> {
+  let mut inflated = vec![];
+  decoder
+    .take(MAX_MESSAGE_SIZE as u64 + 1)
+    .read_to_end(&mut inflated)?;
+  if inflated.len() > MAX_MESSAGE_SIZE {
+    return Err(AppError::msg("Inflated message is too large"));
+  }
+  Ok(inflated)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::{BchainRequest, Frame};
+  use bchain_domain::block::Block;
+  use bchain_domain::tx::Tx;
+  use bchain_domain::wallet::Wallet;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn frame_roundtrip_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let txs = (1..5)
+      .map(|amount| Tx::new(&wallet, &wallet.address(), amount))
+      .collect::<AppResult<Vec<_>>>()?;
+    let block = Block::from_previous(&Block::default(), Some(txs));
+    let frame = Frame::BchainRequest(BchainRequest::SubmitBlock(block));
+
+    let plain = encode(&frame, Compression::None)?;
+    assert!(plain.len() < serde_json::to_vec(&frame)?.len());
+    assert_eq!(decode::<Frame>(&plain)?, frame);
+    for compression in [Compression::Deflate, Compression::Zstd, Compression::Snappy] {
+      let compressed = encode(&frame, compression)?;
+      assert!(compressed.len() < plain.len());
+      assert_eq!(decode::<Frame>(&compressed)?, frame);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn malformed_message_test() -> AppResult<()> {
+    let frame = Frame::BchainRequest(BchainRequest::Msg("hello".into()));
+    let bytes = encode(&frame, Compression::Deflate)?;
+    assert!(decode::<Frame>(&bytes[..3]).is_err());
+    assert!(decode::<Frame>(&bytes[..bytes.len() - 1]).is_err());
+    let mut bytes1 = bytes.clone();
+    bytes1[0] = WIRE_VERSION + 1;
+    assert!(decode::<Frame>(&bytes1).is_err());
+    let mut bytes2 = bytes;
+    bytes2[1] = 7;
+    assert!(decode::<Frame>(&bytes2).is_err());
+    let mut bomb = vec![WIRE_VERSION, ZSTD];
+    let zeros = zstd::encode_all(&vec![0u8; MAX_MESSAGE_SIZE + 1][..], 0)?;
+    bomb.extend_from_slice(&(zeros.len() as u32).to_le_bytes());
+    bomb.extend_from_slice(&zeros);
+    assert!(decode::<Frame>(&bomb).is_err());
+    assert!(decode::<Frame>(b"{\"BchainRequest\":\"AskLatest\"}").is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,53 @@
+use super::UserCommand;
+
+use nom::{
+  bytes::complete::tag,
+  character::complete::{alphanumeric1, digit1, space0, space1},
+  combinator::{eof, map_res, opt},
+  sequence::{preceded, terminated, tuple},
+  IResult,
+};
+
+/// without minutes the ban doesn't expire
+pub(crate) fn ban_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/ban"), space1);
+  let minutes = opt(preceded(space1, map_res(digit1, str::parse::<i64>)));
+  let mut command = preceded(command, terminated(tuple((alphanumeric1, minutes)), space0));
+  let (remainder, (peer_id, minutes)) = command(input)?;
+  let (remainder, _) = eof(remainder)?;
+  Ok((remainder, UserCommand::Ban(peer_id.to_owned(), minutes)))
+}
+
+pub(crate) fn unban_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/unban"), space1);
+  let mut command = preceded(command, terminated(alphanumeric1, space0));
+  let (remainder, peer_id) = command(input)?;
+  let (remainder, _) = eof(remainder)?;
+  Ok((remainder, UserCommand::Unban(peer_id.to_owned())))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  const PEER_ID: &str = "12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA";
+
+  #[test]
+  fn user_command_ban_test() -> AppResult<()> {
+    let cmd: UserCommand = format!("/ban {}", PEER_ID).parse()?;
+    assert_eq!(cmd, UserCommand::Ban(PEER_ID.into(), None));
+    let cmd: UserCommand = format!("/ban {} 30", PEER_ID).parse()?;
+    assert_eq!(cmd, UserCommand::Ban(PEER_ID.into(), Some(30)));
+    let cmd: UserCommand = format!("/ban {} soon", PEER_ID).parse()?;
+    assert_eq!(cmd, UserCommand::Unrecognized);
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_unban_test() -> AppResult<()> {
+    let cmd: UserCommand = format!("/unban {}", PEER_ID).parse()?;
+    assert_eq!(cmd, UserCommand::Unban(PEER_ID.into()));
+    Ok(())
+  }
+}
@@ -0,0 +1,22 @@
+use nom::{bytes::complete::tag, character::complete::space0, sequence::preceded, IResult};
+
+use super::UserCommand;
+
+pub(crate) fn check_accounts_command(input: &str) -> IResult<&str, UserCommand> {
+  let mut command = preceded(tag("/check-accounts"), space0);
+  let (remainder, _) = command(input)?;
+  Ok((remainder, UserCommand::CheckAccounts))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  #[test]
+  fn user_command_check_accounts_test() -> AppResult<()> {
+    let msg = "/check-accounts".parse::<UserCommand>()?;
+    assert_eq!(msg, UserCommand::CheckAccounts);
+    Ok(())
+  }
+}
@@ -3,13 +3,28 @@ use nom::{bytes::complete::tag, character::complete::space0, sequence::preceded,
 use super::UserCommand;
 
 const HELP_TEXT: &str = "
-/peers - display peers
+/peers [-v] - display peers, with scores and handshake details if verbose
+/ban <peer> [minutes] - disconnect and ban a peer, for good if no minutes given
+/unban <peer> - lift a ban
 /blocks - list blocks
 /bootstrap - run bootstrap again
+/sync - progress of the running block download
 /msg <some msg> - send message to peers
 /dial <addr1> [<addr2>] - dial peer by address
 /balance [address] - balance for address, own address used if not specified
-/tx <addr> <amount> - send transaction to network 
+/tx <addr> <amount> [<addr2> <amount2>] - send transaction to network
+/tx-build <addr> <amount> [file] - write unsigned transaction for offline signing
+/tx-submit <file> - verify signed transaction file and send it to network
+/multisig <m> <addr1> <addr2> [..] - create m-of-n multisig address and its policy file
+/tx-build-multisig <policy> <addr> <amount> [file] - write multisig transaction to collect signatures
+/tx-info <hash> - block and confirmations of a transaction
+/history [address] [page] - transactions of address, newest first, own address used if not specified
+/watch add|remove <addr|pubkey> - track a watch-only address
+/watch list - watch-only addresses with their balances
+/watch history <addr|pubkey> - balance changes of a watch-only address
+/check-accounts - verify account index against blocks, rebuilding it
+/sign-message <text> - sign text with own wallet
+/verify-message <addr> <signature> <text> - verify text was signed by address
 /help - this help
 ";
 
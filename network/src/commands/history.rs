@@ -0,0 +1,61 @@
+use super::{parse_address, UserCommand};
+
+use nom::{
+  branch::alt,
+  bytes::complete::tag,
+  character::complete::{alphanumeric1, digit1, space0, space1},
+  combinator::{eof, map, map_res, opt},
+  sequence::{pair, preceded, terminated, tuple},
+  IResult,
+};
+
+/// `/history [address] [page]`, a lone number is a page of the own history
+pub(crate) fn history_command(input: &str) -> IResult<&str, UserCommand> {
+  let page = || opt(preceded(space1, map_res(digit1, str::parse::<i64>)));
+  let own = map(terminated(page(), pair(space0, eof)), |page| {
+    Some(UserCommand::History(None, page.unwrap_or_default()))
+  });
+  let address = map(
+    terminated(
+      tuple((preceded(space1, alphanumeric1), page())),
+      pair(space0, eof),
+    ),
+    |(a, page)| parse_address(a).map(|a| UserCommand::History(Some(a), page.unwrap_or_default())),
+  );
+  let mut command = preceded(tag("/history"), alt((own, address)));
+  let (remainder, history) = command(input)?;
+  Ok((remainder, history.unwrap_or(UserCommand::Unrecognized)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  const ADDRESS: &str = "FzpuKhDdqVu7Q3E7bCJLHnWGGxgaPjN9pi9ScvJiLt1XnFdrP1RBUTzpVkAGN2mNcUtAFrCVF1x7PbnKJRCHcXs2nEusKLnuFKR6fA4vXZC92vMDoWip71eUy7yGfFcFNTF17oHUrvPAwxfu2NKFp2wb8xtYPV4vCHowKG2Bh3kT5DVxjmjzDuNVSU6StVX3Lx7nj5Wz7AkmHL9rszTPQuVpfpLWQwUSnLb2Q4XfUsTCpuCvnxQDaxE8wH8nw7xBZV5SL8v4idCrqQVjcEt5uddwBRyYgEiGJyysYjiWWdfpf7QeoG6Qj4C9ZYmXCRqRJxJAd1Gioey2iF4stkxxEmLurwrR8r7sma";
+
+  #[test]
+  fn user_command_history_test() -> AppResult<()> {
+    let cmd: UserCommand = format!("/history {}", ADDRESS).parse()?;
+    assert_eq!(cmd, UserCommand::History(Some(ADDRESS.parse()?), 0));
+    let cmd: UserCommand = format!("/history {} 3", ADDRESS).parse()?;
+    assert_eq!(cmd, UserCommand::History(Some(ADDRESS.parse()?), 3));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_own_history_test() -> AppResult<()> {
+    let cmd: UserCommand = "/history ".parse()?;
+    assert_eq!(cmd, UserCommand::History(None, 0));
+    let cmd: UserCommand = "/history 2".parse()?;
+    assert_eq!(cmd, UserCommand::History(None, 2));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_history_negative_test() -> AppResult<()> {
+    let cmd: UserCommand = "/history $%^".parse()?;
+    assert_eq!(cmd, UserCommand::Unrecognized);
+    Ok(())
+  }
+}
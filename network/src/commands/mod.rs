@@ -1,34 +1,64 @@
 use bchain_domain::address::Address;
+use bchain_domain::public_key::PublicKey;
+use bchain_domain::signature::Signature;
+use bchain_domain::tx::TxOutput;
 use bchain_util::error::AppError;
 use nom::branch::alt;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
 use self::{
-  balance::balance_command, blocks::blocks_command, bootstrap::bootstrap_command,
-  dial::dial_command, help::help_command, message::message_command, peers::peers_command,
-  tx::tx_command,
+  balance::balance_command, ban::ban_command, ban::unban_command, blocks::blocks_command,
+  bootstrap::bootstrap_command, check_accounts::check_accounts_command, dial::dial_command,
+  help::help_command, history::history_command, message::message_command,
+  multisig::multisig_command, multisig::tx_build_multisig_command, peers::peers_command,
+  sign_message::sign_message_command, sign_message::verify_message_command, sync::sync_command,
+  tx::tx_command, tx_build::tx_build_command, tx_info::tx_info_command,
+  tx_submit::tx_submit_command, watch::watch_command, watch::WatchCommand,
 };
 
 pub mod balance;
+pub mod ban;
 pub mod blocks;
 pub mod bootstrap;
+pub mod check_accounts;
 pub mod dial;
 pub mod help;
+pub mod history;
 pub mod message;
+pub mod multisig;
 pub mod peers;
+pub mod sign_message;
+pub mod sync;
 pub mod tx;
+pub mod tx_build;
+pub mod tx_info;
+pub mod tx_submit;
+pub mod watch;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum UserCommand {
-  Peers,
+  Peers(bool),
   Blocks,
   Bootstrap,
   Msg(String),
   Unrecognized,
   Dial(Vec<String>),
   Balance(Option<Address>),
-  Tx(Address, u64),
+  Tx(Vec<TxOutput>),
+  TxBuild(Address, u64, Option<String>),
+  TxSubmit(String),
+  Multisig(usize, Vec<PublicKey>),
+  TxBuildMultisig(String, Address, u64, Option<String>),
+  SignMessage(String),
+  VerifyMessage(Address, Signature, String),
+  Watch(WatchCommand),
+  CheckAccounts,
+  TxInfo(String),
+  History(Option<Address>, i64),
+  Sync,
+  Ban(String, Option<i64>),
+  Unban(String),
   Help(&'static str),
 }
 
@@ -38,12 +68,25 @@ impl FromStr for UserCommand {
   fn from_str(msg: &str) -> Result<Self, Self::Err> {
     if let Ok((_, cmd)) = alt((
       tx_command,
+      tx_build_multisig_command,
+      tx_build_command,
+      tx_submit_command,
+      tx_info_command,
+      history_command,
+      watch_command,
+      check_accounts_command,
+      multisig_command,
+      sign_message_command,
+      verify_message_command,
       dial_command,
       peers_command,
+      ban_command,
+      unban_command,
       blocks_command,
       message_command,
       balance_command,
       bootstrap_command,
+      sync_command,
       help_command,
     ))(msg)
     {
@@ -53,3 +96,14 @@ impl FromStr for UserCommand {
     }
   }
 }
+
+/// addresses can be given either in their bs58 form or as a hex encoded public key
+pub(crate) fn parse_address(input: &str) -> Option<Address> {
+  let is_hex = input.len().is_multiple_of(2) && input.chars().all(|c| c.is_ascii_hexdigit());
+  if is_hex {
+    if let Ok(public_key) = input.parse::<PublicKey>() {
+      return Some(public_key.into());
+    }
+  }
+  input.parse().ok()
+}
@@ -0,0 +1,101 @@
+use super::{parse_address, UserCommand};
+
+use bchain_domain::address::Address;
+use nom::{
+  bytes::complete::{is_not, tag},
+  character::complete::{alphanumeric1, digit1, space0, space1},
+  combinator::{eof, opt},
+  multi::separated_list1,
+  sequence::{preceded, tuple},
+  IResult,
+};
+
+pub(crate) fn multisig_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/multisig"), space1);
+  let keys = separated_list1(space1, alphanumeric1);
+  let mut command = preceded(command, tuple((digit1, space1, keys, space0, eof)));
+  let (remainder, (threshold, _, keys, _, _)) = command(input)?;
+
+  let threshold = threshold.parse();
+  let keys: Option<Vec<_>> = keys
+    .iter()
+    .map(|&k| parse_address(k).and_then(|a| a.public_key().cloned()))
+    .collect();
+
+  match (threshold, keys) {
+    (Ok(threshold), Some(keys)) => Ok((remainder, UserCommand::Multisig(threshold, keys))),
+    _ => Ok((remainder, UserCommand::Unrecognized)),
+  }
+}
+
+pub(crate) fn tx_build_multisig_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/tx-build-multisig"), space1);
+  let path = || is_not(" \t\r\n");
+  let file = opt(preceded(space1, path()));
+  let mut command = preceded(
+    command,
+    tuple((
+      path(),
+      space1,
+      alphanumeric1,
+      space1,
+      digit1,
+      file,
+      space0,
+      eof,
+    )),
+  );
+  let (remainder, (policy, _, recipient, _, amount, file, _, _)) = command(input)?;
+
+  let recipient = recipient.parse::<Address>();
+  let amount = amount.parse();
+  let file = file.map(String::from);
+
+  match (recipient, amount) {
+    (Ok(recipient), Ok(amount)) => Ok((
+      remainder,
+      UserCommand::TxBuildMultisig(policy.into(), recipient, amount, file),
+    )),
+    _ => Ok((remainder, UserCommand::Unrecognized)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  const ADDRESS: &str = "FzpuKhDdqVu7Q3E7bCJLHnWGGxgaPjN9pi9ScvJiLt1XnFdrP1RBUTzpVkAGN2mNcUtAFrCVF1x7PbnKJRCHcXs2nEusKLnuFKR6fA4vXZC92vMDoWip71eUy7yGfFcFNTF17oHUrvPAwxfu2NKFp2wb8xtYPV4vCHowKG2Bh3kT5DVxjmjzDuNVSU6StVX3Lx7nj5Wz7AkmHL9rszTPQuVpfpLWQwUSnLb2Q4XfUsTCpuCvnxQDaxE8wH8nw7xBZV5SL8v4idCrqQVjcEt5uddwBRyYgEiGJyysYjiWWdfpf7QeoG6Qj4C9ZYmXCRqRJxJAd1Gioey2iF4stkxxEmLurwrR8r7sma";
+
+  #[test]
+  fn user_command_multisig_test() -> AppResult<()> {
+    let input = format!("/multisig 2 {} {}", ADDRESS, ADDRESS);
+    let cmd: UserCommand = input.parse()?;
+    let key = ADDRESS.parse::<Address>()?.public_key().cloned().unwrap();
+    assert_eq!(cmd, UserCommand::Multisig(2, vec![key.clone(), key]));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_multisig_negative_test() -> AppResult<()> {
+    let cmd: UserCommand = "/multisig two abc".parse()?;
+    assert_eq!(cmd, UserCommand::Unrecognized);
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_tx_build_multisig_test() -> AppResult<()> {
+    let input = format!("/tx-build-multisig policy.json {} 10 out.json", ADDRESS);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(
+      cmd,
+      UserCommand::TxBuildMultisig(
+        "policy.json".into(),
+        ADDRESS.parse()?,
+        10,
+        Some("out.json".into())
+      )
+    );
+    Ok(())
+  }
+}
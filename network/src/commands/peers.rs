@@ -1,11 +1,19 @@
 use super::UserCommand;
 
-use nom::{bytes::complete::tag, character::complete::space0, sequence::preceded, IResult};
+use nom::{
+  bytes::complete::tag,
+  character::complete::space0,
+  combinator::{eof, opt},
+  sequence::{preceded, terminated},
+  IResult,
+};
 
 pub(crate) fn peers_command(input: &str) -> IResult<&str, UserCommand> {
-  let mut command = preceded(tag("/peers"), space0);
-  let (remainder, _) = command(input)?;
-  Ok((remainder, UserCommand::Peers))
+  let command = terminated(tag("/peers"), space0);
+  let mut command = preceded(command, opt(terminated(tag("-v"), space0)));
+  let (remainder, verbose) = command(input)?;
+  let (remainder, _) = eof(remainder)?;
+  Ok((remainder, UserCommand::Peers(verbose.is_some())))
 }
 
 #[cfg(test)]
@@ -17,7 +25,9 @@ mod tests {
   fn user_command_peers_test() -> AppResult<()> {
     let input = "/peers";
     let msg = input.parse::<UserCommand>()?;
-    assert_eq!(msg, UserCommand::Peers);
+    assert_eq!(msg, UserCommand::Peers(false));
+    let msg = "/peers -v".parse::<UserCommand>()?;
+    assert_eq!(msg, UserCommand::Peers(true));
     Ok(())
   }
 }
@@ -0,0 +1,69 @@
+use super::UserCommand;
+
+use nom::{
+  bytes::complete::tag,
+  character::complete::{alphanumeric1, hex_digit1, space1},
+  combinator::rest,
+  sequence::{preceded, tuple},
+  IResult,
+};
+
+pub(crate) fn sign_message_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/sign-message"), space1);
+  let mut command = preceded(command, rest);
+  let (remainder, message) = command(input)?;
+  Ok((remainder, UserCommand::SignMessage(message.into())))
+}
+
+pub(crate) fn verify_message_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/verify-message"), space1);
+  let mut command = preceded(
+    command,
+    tuple((alphanumeric1, space1, hex_digit1, space1, rest)),
+  );
+  let (remainder, (address, _, signature, _, message)) = command(input)?;
+
+  match (address.parse(), signature.parse()) {
+    (Ok(address), Ok(signature)) => Ok((
+      remainder,
+      UserCommand::VerifyMessage(address, signature, message.into()),
+    )),
+    _ => Ok((remainder, UserCommand::Unrecognized)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_domain::signature::Signature;
+  use bchain_util::result::AppResult;
+
+  const ADDRESS: &str = "FzpuKhDdqVu7Q3E7bCJLHnWGGxgaPjN9pi9ScvJiLt1XnFdrP1RBUTzpVkAGN2mNcUtAFrCVF1x7PbnKJRCHcXs2nEusKLnuFKR6fA4vXZC92vMDoWip71eUy7yGfFcFNTF17oHUrvPAwxfu2NKFp2wb8xtYPV4vCHowKG2Bh3kT5DVxjmjzDuNVSU6StVX3Lx7nj5Wz7AkmHL9rszTPQuVpfpLWQwUSnLb2Q4XfUsTCpuCvnxQDaxE8wH8nw7xBZV5SL8v4idCrqQVjcEt5uddwBRyYgEiGJyysYjiWWdfpf7QeoG6Qj4C9ZYmXCRqRJxJAd1Gioey2iF4stkxxEmLurwrR8r7sma";
+
+  #[test]
+  fn user_command_sign_message_test() -> AppResult<()> {
+    let cmd: UserCommand = "/sign-message I own this address".parse()?;
+    assert_eq!(cmd, UserCommand::SignMessage("I own this address".into()));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_verify_message_test() -> AppResult<()> {
+    let signature = Signature::default();
+    let input = format!("/verify-message {} {} I own it", ADDRESS, signature);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(
+      cmd,
+      UserCommand::VerifyMessage(ADDRESS.parse()?, signature, "I own it".into())
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_verify_message_negative_test() -> AppResult<()> {
+    let input = format!("/verify-message {} abcd I own it", ADDRESS);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(cmd, UserCommand::Unrecognized);
+    Ok(())
+  }
+}
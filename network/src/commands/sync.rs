@@ -0,0 +1,22 @@
+use nom::{bytes::complete::tag, character::complete::space0, sequence::preceded, IResult};
+
+use super::UserCommand;
+
+pub(crate) fn sync_command(input: &str) -> IResult<&str, UserCommand> {
+  let mut command = preceded(tag("/sync"), space0);
+  let (remainder, _) = command(input)?;
+  Ok((remainder, UserCommand::Sync))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  #[test]
+  fn user_command_sync_test() -> AppResult<()> {
+    let msg = "/sync".parse::<UserCommand>()?;
+    assert_eq!(msg, UserCommand::Sync);
+    Ok(())
+  }
+}
@@ -1,23 +1,34 @@
 use super::UserCommand;
+use bchain_domain::tx::TxOutput;
 
 use nom::{
   bytes::complete::tag,
-  character::complete::{alphanumeric1, digit1, space1},
+  character::complete::{alphanumeric1, digit1, space0, space1},
   combinator::eof,
-  sequence::{preceded, tuple},
+  multi::separated_list1,
+  sequence::{preceded, separated_pair, tuple},
   IResult,
 };
 
 pub(crate) fn tx_command(input: &str) -> IResult<&str, UserCommand> {
   let command = preceded(tag("/tx"), space1);
-  let mut command = preceded(command, tuple((alphanumeric1, space1, digit1, eof)));
-  let (remainder, (recipient, _, amount, _)) = command(input)?;
+  let output = separated_pair(alphanumeric1, space1, digit1);
+  let outputs = separated_list1(space1, output);
+  let mut command = preceded(command, tuple((outputs, space0, eof)));
+  let (remainder, (outputs, _, _)) = command(input)?;
 
-  let recipient = recipient.parse();
-  let amount = amount.parse();
+  let outputs: Option<Vec<TxOutput>> = outputs
+    .iter()
+    .map(
+      |(recipient, amount)| match (recipient.parse(), amount.parse()) {
+        (Ok(recipient), Ok(amount)) => Some(TxOutput::new(&recipient, amount)),
+        _ => None,
+      },
+    )
+    .collect();
 
-  match (recipient, amount) {
-    (Ok(recipient), Ok(amount)) => Ok((remainder, UserCommand::Tx(recipient, amount))),
+  match outputs {
+    Some(outputs) => Ok((remainder, UserCommand::Tx(outputs))),
     _ => Ok((remainder, UserCommand::Unrecognized)),
   }
 }
@@ -33,7 +44,28 @@ mod tests {
   fn user_command_tx_positive_test() -> AppResult<()> {
     let input = format!("/tx {} {}", ADDRESS, 123);
     let tx: UserCommand = input.parse()?;
-    assert_eq!(tx, UserCommand::Tx(ADDRESS.parse()?, 123));
+    let outputs = vec![TxOutput::new(&ADDRESS.parse()?, 123)];
+    assert_eq!(tx, UserCommand::Tx(outputs));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_tx_multiple_outputs_test() -> AppResult<()> {
+    let input = format!("/tx {} {} {} {}", ADDRESS, 123, ADDRESS, 456);
+    let tx: UserCommand = input.parse()?;
+    let outputs = vec![
+      TxOutput::new(&ADDRESS.parse()?, 123),
+      TxOutput::new(&ADDRESS.parse()?, 456),
+    ];
+    assert_eq!(tx, UserCommand::Tx(outputs));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_tx_dangling_address_test() -> AppResult<()> {
+    let input = format!("/tx {} {} {}", ADDRESS, 123, ADDRESS);
+    let msg = input.parse::<UserCommand>()?;
+    assert_eq!(msg, UserCommand::Unrecognized);
     Ok(())
   }
 
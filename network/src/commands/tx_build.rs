@@ -0,0 +1,55 @@
+use super::UserCommand;
+
+use nom::{
+  bytes::complete::{is_not, tag},
+  character::complete::{alphanumeric1, digit1, space0, space1},
+  combinator::{eof, opt},
+  sequence::{preceded, tuple},
+  IResult,
+};
+
+pub(crate) fn tx_build_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/tx-build"), space1);
+  let file = opt(preceded(space1, is_not(" \t\r\n")));
+  let mut command = preceded(
+    command,
+    tuple((alphanumeric1, space1, digit1, file, space0, eof)),
+  );
+  let (remainder, (recipient, _, amount, file, _, _)) = command(input)?;
+
+  let recipient = recipient.parse();
+  let amount = amount.parse();
+  let file = file.map(String::from);
+
+  match (recipient, amount) {
+    (Ok(recipient), Ok(amount)) => Ok((remainder, UserCommand::TxBuild(recipient, amount, file))),
+    _ => Ok((remainder, UserCommand::Unrecognized)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  const ADDRESS: &str = "FzpuKhDdqVu7Q3E7bCJLHnWGGxgaPjN9pi9ScvJiLt1XnFdrP1RBUTzpVkAGN2mNcUtAFrCVF1x7PbnKJRCHcXs2nEusKLnuFKR6fA4vXZC92vMDoWip71eUy7yGfFcFNTF17oHUrvPAwxfu2NKFp2wb8xtYPV4vCHowKG2Bh3kT5DVxjmjzDuNVSU6StVX3Lx7nj5Wz7AkmHL9rszTPQuVpfpLWQwUSnLb2Q4XfUsTCpuCvnxQDaxE8wH8nw7xBZV5SL8v4idCrqQVjcEt5uddwBRyYgEiGJyysYjiWWdfpf7QeoG6Qj4C9ZYmXCRqRJxJAd1Gioey2iF4stkxxEmLurwrR8r7sma";
+
+  #[test]
+  fn user_command_tx_build_test() -> AppResult<()> {
+    let input = format!("/tx-build {} {}", ADDRESS, 123);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(cmd, UserCommand::TxBuild(ADDRESS.parse()?, 123, None));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_tx_build_file_test() -> AppResult<()> {
+    let input = format!("/tx-build {} {} data/payout.json", ADDRESS, 123);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(
+      cmd,
+      UserCommand::TxBuild(ADDRESS.parse()?, 123, Some("data/payout.json".into()))
+    );
+    Ok(())
+  }
+}
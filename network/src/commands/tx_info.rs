@@ -0,0 +1,39 @@
+use super::UserCommand;
+
+use nom::{
+  bytes::complete::tag,
+  character::complete::{hex_digit1, space0, space1},
+  combinator::eof,
+  sequence::{preceded, terminated},
+  IResult,
+};
+
+pub(crate) fn tx_info_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/tx-info"), space1);
+  let mut command = preceded(command, terminated(hex_digit1, space0));
+  let (remainder, hash) = command(input)?;
+  let (remainder, _) = eof(remainder)?;
+  Ok((remainder, UserCommand::TxInfo(hash.to_lowercase())))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::hash_digest::Hashable;
+  use bchain_util::result::AppResult;
+
+  #[test]
+  fn user_command_tx_info_test() -> AppResult<()> {
+    let hash = "tx".hash_digest().to_string();
+    let cmd: UserCommand = format!("/tx-info {}", hash).parse()?;
+    assert_eq!(cmd, UserCommand::TxInfo(hash));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_tx_info_negative_test() -> AppResult<()> {
+    let cmd: UserCommand = "/tx-info xyz".parse()?;
+    assert_eq!(cmd, UserCommand::Unrecognized);
+    Ok(())
+  }
+}
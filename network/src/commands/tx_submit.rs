@@ -0,0 +1,30 @@
+use super::UserCommand;
+
+use nom::{
+  bytes::complete::{is_not, tag},
+  character::complete::{space0, space1},
+  combinator::eof,
+  sequence::{preceded, terminated},
+  IResult,
+};
+
+pub(crate) fn tx_submit_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/tx-submit"), space1);
+  let mut command = preceded(command, terminated(is_not(" \t\r\n"), space0));
+  let (remainder, file) = command(input)?;
+  let (remainder, _) = eof(remainder)?;
+  Ok((remainder, UserCommand::TxSubmit(file.into())))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  #[test]
+  fn user_command_tx_submit_test() -> AppResult<()> {
+    let cmd: UserCommand = "/tx-submit data/payout.json.signed".parse()?;
+    assert_eq!(cmd, UserCommand::TxSubmit("data/payout.json.signed".into()));
+    Ok(())
+  }
+}
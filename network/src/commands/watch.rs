@@ -0,0 +1,109 @@
+use super::{parse_address, UserCommand};
+use bchain_domain::address::Address;
+use serde::{Deserialize, Serialize};
+
+use nom::{
+  branch::alt,
+  bytes::complete::tag,
+  character::complete::{alphanumeric1, space0, space1},
+  combinator::{eof, map},
+  sequence::{preceded, terminated},
+  IResult,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WatchCommand {
+  Add(Address),
+  Remove(Address),
+  List,
+  History(Address),
+}
+
+pub(crate) fn watch_command(input: &str) -> IResult<&str, UserCommand> {
+  let command = preceded(tag("/watch"), space1);
+  let add = map(preceded(tag("add"), preceded(space1, alphanumeric1)), |a| {
+    parse_address(a).map(WatchCommand::Add)
+  });
+  let remove = map(
+    preceded(tag("remove"), preceded(space1, alphanumeric1)),
+    |a| parse_address(a).map(WatchCommand::Remove),
+  );
+  let list = map(tag("list"), |_| Some(WatchCommand::List));
+  let history = map(
+    preceded(tag("history"), preceded(space1, alphanumeric1)),
+    |a| parse_address(a).map(WatchCommand::History),
+  );
+  let mut command = preceded(
+    command,
+    terminated(alt((add, remove, list, history)), space0),
+  );
+  let (remainder, watch) = command(input)?;
+  let (remainder, _) = eof(remainder)?;
+
+  match watch {
+    Some(watch) => Ok((remainder, UserCommand::Watch(watch))),
+    _ => Ok((remainder, UserCommand::Unrecognized)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+
+  const ADDRESS: &str = "FzpuKhDdqVu7Q3E7bCJLHnWGGxgaPjN9pi9ScvJiLt1XnFdrP1RBUTzpVkAGN2mNcUtAFrCVF1x7PbnKJRCHcXs2nEusKLnuFKR6fA4vXZC92vMDoWip71eUy7yGfFcFNTF17oHUrvPAwxfu2NKFp2wb8xtYPV4vCHowKG2Bh3kT5DVxjmjzDuNVSU6StVX3Lx7nj5Wz7AkmHL9rszTPQuVpfpLWQwUSnLb2Q4XfUsTCpuCvnxQDaxE8wH8nw7xBZV5SL8v4idCrqQVjcEt5uddwBRyYgEiGJyysYjiWWdfpf7QeoG6Qj4C9ZYmXCRqRJxJAd1Gioey2iF4stkxxEmLurwrR8r7sma";
+
+  #[test]
+  fn user_command_watch_add_test() -> AppResult<()> {
+    let input = format!("/watch add {}", ADDRESS);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(cmd, UserCommand::Watch(WatchCommand::Add(ADDRESS.parse()?)));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_watch_add_public_key_test() -> AppResult<()> {
+    let address: Address = ADDRESS.parse()?;
+    let public_key = address.public_key().unwrap();
+    let input = format!("/watch add {}", hex::encode(public_key.as_ref()));
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(cmd, UserCommand::Watch(WatchCommand::Add(address)));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_watch_remove_test() -> AppResult<()> {
+    let input = format!("/watch remove {}", ADDRESS);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(
+      cmd,
+      UserCommand::Watch(WatchCommand::Remove(ADDRESS.parse()?))
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_watch_list_test() -> AppResult<()> {
+    let cmd: UserCommand = "/watch list".parse()?;
+    assert_eq!(cmd, UserCommand::Watch(WatchCommand::List));
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_watch_history_test() -> AppResult<()> {
+    let input = format!("/watch history {}", ADDRESS);
+    let cmd: UserCommand = input.parse()?;
+    assert_eq!(
+      cmd,
+      UserCommand::Watch(WatchCommand::History(ADDRESS.parse()?))
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn user_command_watch_negative_test() -> AppResult<()> {
+    let cmd: UserCommand = "/watch add $%^".parse()?;
+    assert_eq!(cmd, UserCommand::Unrecognized);
+    Ok(())
+  }
+}
@@ -0,0 +1,273 @@
+use bchain_domain::block::{Block, BlockHeader};
+use bchain_domain::tx::Tx;
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::{HashDigest, Hashable};
+use bchain_util::result::AppResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+/// compact blocks waiting longer for their txs are given up on
+const PARTIAL_BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_PARTIAL_BLOCKS: usize = 32;
+
+/// tx hash shortened to 8 bytes, salted with the block hash so that
+/// colliding txs can't be prepared ahead of a block
+pub fn short_id(block_hash: &HashDigest, tx_hash: &HashDigest) -> u64 {
+  let mut bytes = block_hash.to_vec();
+  bytes.extend_from_slice(&tx_hash[..]);
+  let digest = bytes.hash_digest();
+  u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// txs in the order their short ids are sent, the order the block hashes them in
+fn ordered_txs(block: &Block) -> Vec<&Tx> {
+  let mut txs: Vec<_> = block.txs.iter().collect();
+  txs.sort_by_key(|(key, _)| *key);
+  txs.into_iter().map(|(_, tx)| tx).collect()
+}
+
+/// block relayed as its header and short tx ids, receivers take the txs
+/// they already have from their pool
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompactBlock {
+  pub header: BlockHeader,
+  pub short_ids: Vec<u64>,
+}
+
+impl CompactBlock {
+  pub fn new(block: &Block) -> CompactBlock {
+    let header = BlockHeader::from(block);
+    let short_ids = ordered_txs(block)
+      .into_iter()
+      .map(|tx| short_id(&header.hash, &tx.hash_digest()))
+      .collect();
+    CompactBlock { header, short_ids }
+  }
+}
+
+/// txs of `block` at the given positions of its compact form
+pub fn block_txs(block: &Block, indexes: &[usize]) -> Vec<Tx> {
+  let txs = ordered_txs(block);
+  indexes
+    .iter()
+    .filter_map(|idx| txs.get(*idx))
+    .map(|tx| (*tx).clone())
+    .collect()
+}
+
+/// compact block being rebuilt, missing txs are asked from the sending peer
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+  header: BlockHeader,
+  txs: Vec<Option<Tx>>,
+}
+
+impl PartialBlock {
+  pub fn new<'a, T>(compact: CompactBlock, pool: T) -> PartialBlock
+  where
+    T: IntoIterator<Item = &'a Tx>,
+  {
+    let hash = compact.header.hash;
+    let known: HashMap<u64, &Tx> = pool
+      .into_iter()
+      .map(|tx| (short_id(&hash, &tx.hash_digest()), tx))
+      .collect();
+    let txs = compact
+      .short_ids
+      .iter()
+      .map(|id| known.get(id).map(|tx| (*tx).clone()))
+      .collect();
+    PartialBlock {
+      header: compact.header,
+      txs,
+    }
+  }
+
+  pub fn hash(&self) -> HashDigest {
+    self.header.hash
+  }
+
+  /// positions of the txs that aren't in the pool
+  pub fn missing(&self) -> Vec<usize> {
+    let missing = self.txs.iter().enumerate().filter(|(_, tx)| tx.is_none());
+    missing.map(|(idx, _)| idx).collect()
+  }
+
+  /// `txs` are the missing ones, in the order `missing` returned
+  pub fn fill(&mut self, txs: Vec<Tx>) -> AppResult<()> {
+    let missing = self.missing();
+    if txs.len() != missing.len() {
+      let message = format!("Expected {} txs, got {}", missing.len(), txs.len());
+      return Err(AppError::msg(message));
+    }
+    for (idx, tx) in missing.into_iter().zip(txs) {
+      self.txs[idx] = Some(tx);
+    }
+    Ok(())
+  }
+
+  /// fails while txs are missing, or if a short id matched the wrong tx
+  pub fn block(&self) -> AppResult<Block> {
+    let txs = self.txs.iter().cloned().collect::<Option<Vec<Tx>>>();
+    let txs = txs.ok_or_else(|| AppError::msg("Block is missing txs"))?;
+    let mut block = Block {
+      id: self.header.id,
+      timestamp: self.header.timestamp,
+      parent_hash: self.header.parent_hash,
+      nonce: self.header.nonce.clone(),
+      ..Block::default()
+    };
+    for tx in &txs {
+      block.add(tx);
+    }
+    if block.hash_digest() != self.header.hash {
+      return Err(AppError::msg(format!(
+        "Rebuilt block doesn't match {}",
+        self.header.hash
+      )));
+    }
+    Ok(block)
+  }
+}
+
+/// compact blocks waiting for their missing txs, bounded in number and age
+#[derive(Debug)]
+pub struct PartialBlocks {
+  capacity: usize,
+  timeout: Duration,
+  blocks: HashMap<HashDigest, (Instant, PartialBlock)>,
+}
+
+impl Default for PartialBlocks {
+  fn default() -> Self {
+    PartialBlocks::new(MAX_PARTIAL_BLOCKS, PARTIAL_BLOCK_TIMEOUT)
+  }
+}
+
+impl PartialBlocks {
+  pub fn new(capacity: usize, timeout: Duration) -> PartialBlocks {
+    PartialBlocks {
+      capacity,
+      timeout,
+      blocks: HashMap::new(),
+    }
+  }
+
+  /// drops stale blocks, and the oldest ones while there are too many, returns their hashes
+  pub fn insert(&mut self, partial: PartialBlock) -> Vec<HashDigest> {
+    let timeout = self.timeout;
+    let mut dropped: Vec<HashDigest> = self
+      .blocks
+      .iter()
+      .filter(|(_, (since, _))| since.elapsed() >= timeout)
+      .map(|(hash, _)| *hash)
+      .collect();
+    for hash in &dropped {
+      self.blocks.remove(hash);
+    }
+    while self.blocks.len() >= self.capacity {
+      let oldest = self.blocks.iter().min_by_key(|(_, (since, _))| *since);
+      let hash = match oldest {
+        Some((hash, _)) => *hash,
+        None => break,
+      };
+      self.blocks.remove(&hash);
+      dropped.push(hash);
+    }
+    self
+      .blocks
+      .insert(partial.hash(), (Instant::now(), partial));
+    dropped
+  }
+
+  pub fn remove(&mut self, hash: &HashDigest) -> Option<PartialBlock> {
+    self.blocks.remove(hash).map(|(_, partial)| partial)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::codec::encode;
+  use bchain_domain::cli::Compression;
+  use bchain_domain::wallet::Wallet;
+
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  async fn block_and_txs() -> AppResult<(Block, Vec<Tx>)> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let txs = (1..=8)
+      .map(|amount| Tx::new(&wallet, &wallet.address(), amount))
+      .collect::<AppResult<Vec<_>>>()?;
+    let block = Block::from_previous(&Block::default(), Some(txs.clone()));
+    Ok((block, txs))
+  }
+
+  #[async_std::test]
+  async fn partial_overlap_test() -> AppResult<()> {
+    let (block, txs) = block_and_txs().await?;
+    let compact = CompactBlock::new(&block);
+    let full_size = encode(&block, Compression::None)?.len();
+    assert!(encode(&compact, Compression::None)?.len() * 4 < full_size);
+
+    // pool has every other tx of the block and an unrelated one
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let mut pool: Vec<Tx> = txs.iter().step_by(2).cloned().collect();
+    pool.push(Tx::new(&wallet, &wallet.address(), 100)?);
+    let mut partial = PartialBlock::new(compact.clone(), &pool);
+    let missing = partial.missing();
+    assert_eq!(missing.len(), 4);
+    assert!(partial.block().is_err());
+
+    let present: Vec<usize> = (0..txs.len()).filter(|i| !missing.contains(i)).collect();
+    let wrong = block_txs(&block, &present);
+    let mut wrong_partial = partial.clone();
+    wrong_partial.fill(wrong)?;
+    assert!(wrong_partial.block().is_err());
+
+    assert!(partial.fill(vec![]).is_err());
+    partial.fill(block_txs(&block, &missing))?;
+    assert_eq!(partial.block()?, block);
+    Ok(())
+  }
+
+  #[async_std::test]
+  async fn full_and_no_overlap_test() -> AppResult<()> {
+    let (block, txs) = block_and_txs().await?;
+    let compact = CompactBlock::new(&block);
+    let partial = PartialBlock::new(compact.clone(), &txs);
+    assert!(partial.missing().is_empty());
+    assert_eq!(partial.block()?, block);
+
+    let mut partial = PartialBlock::new(compact, &Vec::new());
+    assert_eq!(partial.missing(), (0..txs.len()).collect::<Vec<_>>());
+    partial.fill(block_txs(&block, &partial.missing()))?;
+    assert_eq!(partial.block()?, block);
+    Ok(())
+  }
+
+  #[test]
+  fn partial_blocks_test() {
+    let partials: Vec<PartialBlock> = (0..3)
+      .scan(Block::default(), |block, _| {
+        *block = Block::from_previous(block, None::<Vec<_>>);
+        Some(PartialBlock::new(CompactBlock::new(block), &Vec::new()))
+      })
+      .collect();
+    let mut partial_blocks = PartialBlocks::new(2, Duration::from_secs(60));
+    assert!(partial_blocks.insert(partials[0].clone()).is_empty());
+    assert!(partial_blocks.insert(partials[1].clone()).is_empty());
+    assert_eq!(
+      partial_blocks.insert(partials[2].clone()),
+      vec![partials[0].hash()]
+    );
+    assert!(partial_blocks.remove(&partials[0].hash()).is_none());
+    assert!(partial_blocks.remove(&partials[1].hash()).is_some());
+
+    let mut stale = PartialBlocks::new(2, Duration::ZERO);
+    stale.insert(partials[0].clone());
+    assert_eq!(stale.insert(partials[1].clone()), vec![partials[0].hash()]);
+  }
+}
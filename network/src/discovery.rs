@@ -0,0 +1,303 @@
+use libp2p::core::ConnectedPoint;
+use libp2p::kad::KademliaEvent;
+use libp2p::mdns::MdnsEvent;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+const BASE_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// wait before dialing a peer again, doubles with every failed dial
+pub fn backoff(failures: u32) -> Duration {
+  BASE_BACKOFF
+    .checked_mul(1 << failures.saturating_sub(1).min(16))
+    .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+/// peer id of an address ending in `/p2p/<peer id>`
+pub fn peer_id_of(address: &Multiaddr) -> Option<PeerId> {
+  match address.iter().last() {
+    Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).ok(),
+    _ => None,
+  }
+}
+
+/// peers learned from mDNS, Kademlia and past connections, dialed again
+/// while there are fewer outbound connections than the target
+#[derive(Debug, Default)]
+pub struct Discovery {
+  target: usize,
+  known: HashMap<PeerId, Multiaddr>,
+  outbound: HashSet<PeerId>,
+  /// failed dials in a row and when to try again
+  failures: HashMap<PeerId, (u32, Instant)>,
+}
+
+impl Discovery {
+  pub fn new(target: usize) -> Discovery {
+    Discovery {
+      target,
+      ..Discovery::default()
+    }
+  }
+
+  /// true for peers not known before
+  pub fn add_address(&mut self, peer_id: PeerId, address: Multiaddr) -> bool {
+    self.known.insert(peer_id, address).is_none()
+  }
+
+  /// incompatible peers aren't dialed again
+  pub fn forget(&mut self, peer_id: &PeerId) {
+    self.known.remove(peer_id);
+    self.failures.remove(peer_id);
+  }
+
+  pub fn address(&self, peer_id: &PeerId) -> Option<&Multiaddr> {
+    self.known.get(peer_id)
+  }
+
+  pub fn connected(&mut self, peer_id: PeerId, endpoint: &ConnectedPoint) {
+    if let ConnectedPoint::Dialer { address } = endpoint {
+      self.outbound.insert(peer_id);
+      self.known.insert(peer_id, address.clone());
+      self.failures.remove(&peer_id);
+    }
+  }
+
+  pub fn disconnected(&mut self, peer_id: &PeerId) {
+    self.outbound.remove(peer_id);
+  }
+
+  pub fn is_outbound(&self, peer_id: &PeerId) -> bool {
+    self.outbound.contains(peer_id)
+  }
+
+  /// the peer known at `address` isn't dialed again until its backoff passed
+  pub fn dial_failed(&mut self, address: &Multiaddr) {
+    let peer_id = self
+      .known
+      .iter()
+      .find(|(_, known)| *known == address)
+      .map(|(peer_id, _)| *peer_id);
+    if let Some(peer_id) = peer_id {
+      let entry = self.failures.entry(peer_id).or_insert((0, Instant::now()));
+      entry.0 += 1;
+      entry.1 = Instant::now() + backoff(entry.0);
+    }
+  }
+
+  fn backing_off(&self, peer_id: &PeerId) -> bool {
+    match self.failures.get(peer_id) {
+      Some((_, retry)) => *retry > Instant::now(),
+      None => false,
+    }
+  }
+
+  pub fn outbound(&self) -> usize {
+    self.outbound.len()
+  }
+
+  pub fn target(&self) -> usize {
+    self.target
+  }
+
+  /// known peers to dial to reach the target, `skip` filters connected and banned ones
+  pub fn candidates<F>(&self, skip: F) -> Vec<(PeerId, Multiaddr)>
+  where
+    F: Fn(&PeerId) -> bool,
+  {
+    let missing = self.target.saturating_sub(self.outbound.len());
+    self
+      .known
+      .iter()
+      .filter(|(peer_id, _)| !self.outbound.contains(peer_id) && !self.backing_off(peer_id))
+      .filter(|(peer_id, _)| !skip(peer_id))
+      .take(missing)
+      .map(|(peer_id, address)| (*peer_id, address.clone()))
+      .collect()
+  }
+
+  /// true if new peers were found
+  pub fn on_mdns_event(&mut self, event: MdnsEvent) -> bool {
+    match event {
+      MdnsEvent::Discovered(peers) => {
+        let mut found = false;
+        for (peer_id, address) in peers {
+          found |= self.add_address(peer_id, address);
+        }
+        found
+      }
+      MdnsEvent::Expired(peers) => {
+        for (peer_id, address) in peers {
+          if self.known.get(&peer_id) == Some(&address) && !self.outbound.contains(&peer_id) {
+            self.known.remove(&peer_id);
+          }
+        }
+        false
+      }
+    }
+  }
+
+  /// true if new peers were found
+  pub fn on_kademlia_event(&mut self, event: &KademliaEvent) -> bool {
+    match event {
+      KademliaEvent::RoutingUpdated {
+        peer, addresses, ..
+      } => {
+        let address = addresses.iter().next().cloned();
+        address.is_some_and(|address| self.add_address(*peer, address))
+      }
+      _ => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::swarm::{create_behaviour, BchainEvent, BchainSwarm};
+  use async_std::future::timeout;
+  use async_std::task;
+  use bchain_domain::cli::Cli;
+  use bchain_util::error::AppError;
+  use bchain_util::result::AppResult;
+  use futures::prelude::*;
+  use libp2p::core::{transport::MemoryTransport, upgrade, Transport};
+  use libp2p::gossipsub::IdentTopic as Topic;
+  use libp2p::plaintext::PlainText2Config;
+  use libp2p::swarm::SwarmEvent;
+  use libp2p::{identity::Keypair, yamux::YamuxConfig, Swarm};
+  use structopt::StructOpt;
+
+  async fn memory_swarm(cli: &Cli) -> AppResult<BchainSwarm> {
+    let key = Keypair::generate_ed25519();
+    let peer_id = PeerId::from(key.public());
+    let transport = MemoryTransport
+      .upgrade(upgrade::Version::V1)
+      .authenticate(PlainText2Config {
+        local_public_key: key.public(),
+      })
+      .multiplex(YamuxConfig::default())
+      .boxed();
+    let behaviour = create_behaviour(&key, &Topic::new("test"), cli).await?;
+    Ok(Swarm::new(transport, behaviour, peer_id))
+  }
+
+  #[test]
+  fn candidates_test() {
+    let (peer1, peer2, peer3) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let address: Multiaddr = "/memory/1".parse().unwrap();
+    let address2: Multiaddr = "/memory/2".parse().unwrap();
+    let mut discovery = Discovery::new(2);
+    assert!(discovery.add_address(peer1, address.clone()));
+    assert!(!discovery.add_address(peer1, address.clone()));
+    discovery.add_address(peer2, address2.clone());
+    discovery.add_address(peer3, address.clone());
+    assert_eq!(discovery.candidates(|_| false).len(), 2);
+    assert_eq!(
+      discovery.candidates(|p| *p != peer3),
+      vec![(peer3, address.clone())]
+    );
+
+    let dialer = ConnectedPoint::Dialer {
+      address: address.clone(),
+    };
+    discovery.connected(peer1, &dialer);
+    assert_eq!(discovery.candidates(|_| false).len(), 1);
+    discovery.connected(peer2, &dialer);
+    assert!(discovery.candidates(|_| false).is_empty());
+    discovery.disconnected(&peer2);
+    discovery.forget(&peer3);
+    assert_eq!(
+      discovery.candidates(|_| false),
+      vec![(peer2, address.clone())]
+    );
+
+    discovery.add_address(peer2, address2.clone());
+    discovery.dial_failed(&address2);
+    assert!(discovery.candidates(|_| false).is_empty());
+    discovery.connected(peer2, &dialer);
+    discovery.disconnected(&peer2);
+    assert_eq!(discovery.candidates(|_| false).len(), 1);
+
+    let with_id = format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", peer1)
+      .parse()
+      .unwrap();
+    assert_eq!(peer_id_of(&with_id), Some(peer1));
+    assert_eq!(
+      peer_id_of(&"/ip4/127.0.0.1/tcp/4001".parse().unwrap()),
+      None
+    );
+  }
+
+  #[test]
+  fn backoff_test() {
+    assert_eq!(backoff(1), BASE_BACKOFF);
+    assert_eq!(backoff(3), BASE_BACKOFF * 4);
+    assert_eq!(backoff(20), MAX_BACKOFF);
+    assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+  }
+
+  /// a peer only added to kademlia is dialed, and dialed again after it dropped
+  #[async_std::test]
+  async fn redial_test() -> AppResult<()> {
+    let cli = Cli::from_iter(&["bchain", "--kademlia"]);
+    let mut remote = memory_swarm(&cli).await?;
+    let remote_id = *remote.local_peer_id();
+    remote.listen_on("/memory/0".parse()?)?;
+    let address = loop {
+      if let SwarmEvent::NewListenAddr { address, .. } = remote.select_next_some().await {
+        break address;
+      }
+    };
+    task::spawn(async move {
+      loop {
+        remote.select_next_some().await;
+      }
+    });
+
+    let mut swarm = memory_swarm(&cli).await?;
+    let kademlia = swarm.behaviour_mut().kademlia.as_mut().unwrap();
+    kademlia.add_address(&remote_id, address);
+    let mut discovery = Discovery::new(1);
+    let mut connections = 0;
+    let redials = async {
+      while connections < 2 {
+        let redial = match swarm.select_next_some().await {
+          SwarmEvent::Behaviour(BchainEvent::Kademlia(event)) => {
+            discovery.on_kademlia_event(&event)
+          }
+          SwarmEvent::ConnectionEstablished {
+            peer_id, endpoint, ..
+          } => {
+            discovery.connected(peer_id, &endpoint);
+            assert!(discovery.candidates(|_| false).is_empty());
+            connections += 1;
+            if connections == 1 {
+              swarm.disconnect_peer_id(peer_id).unwrap();
+            }
+            false
+          }
+          SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            discovery.disconnected(&peer_id);
+            true
+          }
+          _ => false,
+        };
+        if redial {
+          for (_, address) in discovery.candidates(|p| swarm.is_connected(p)) {
+            swarm.dial_addr(address)?;
+          }
+        }
+      }
+      AppResult::Ok(())
+    };
+    timeout(Duration::from_secs(30), redials)
+      .await
+      .map_err(|_| AppError::msg("Peer wasn't dialed again in time"))??;
+    assert_eq!(discovery.outbound(), 1);
+    Ok(())
+  }
+}
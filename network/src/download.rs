@@ -0,0 +1,426 @@
+use crate::network::{describe_votes, TIMEOUT};
+use crate::peer_manager::Behaviour;
+use crate::sync::{next_request_id, RequestId, SyncRequest, MAX_HEADERS};
+use async_std::channel::{Receiver, Sender};
+use async_std::future::timeout;
+use async_std::sync::{Mutex, RwLock};
+use async_std::task;
+use bchain_db::storage_error::StorageError;
+use bchain_db::store::Store;
+use bchain_domain::block::{Block, BlockHeader};
+use bchain_util::error::AppError;
+use bchain_util::group::vote_by;
+use bchain_util::hash_digest::Hashable;
+use bchain_util::result::AppResult;
+use bchain_util::short::ShortDisplay;
+use futures::prelude::*;
+use libp2p::PeerId;
+use log::{debug, info, warn};
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// blocks requested at once, spread over all serving peers
+const WINDOW: usize = 32;
+const MAX_ATTEMPTS: usize = 3;
+/// most local blocks a sync rolls back to switch to a competing chain
+pub(crate) const MAX_REORG_DEPTH: i64 = 100;
+
+/// sync request, `None` sends it to every peer
+pub type TargetedRequest = (SyncRequest, Option<PeerId>);
+
+#[derive(Debug, Clone)]
+pub struct SyncProgress {
+  start_id: i64,
+  target_id: i64,
+  current_id: i64,
+  started: Instant,
+}
+
+impl SyncProgress {
+  pub fn new(start_id: i64, target_id: i64) -> SyncProgress {
+    SyncProgress {
+      start_id,
+      target_id,
+      current_id: start_id,
+      started: Instant::now(),
+    }
+  }
+
+  pub fn advance(&mut self, current_id: i64) {
+    self.current_id = current_id;
+  }
+
+  fn done(&self) -> i64 {
+    self.current_id - self.start_id
+  }
+
+  fn total(&self) -> i64 {
+    self.target_id - self.start_id
+  }
+
+  pub fn percent(&self) -> f64 {
+    match self.total() {
+      0 => 100.0,
+      total => self.done() as f64 * 100.0 / total as f64,
+    }
+  }
+
+  /// extrapolated from the rate so far, unknown until a block is in
+  pub fn eta(&self) -> Option<Duration> {
+    match self.done() {
+      0 => None,
+      done => {
+        let remaining = (self.total() - done) as u32;
+        Some(self.started.elapsed() / done as u32 * remaining)
+      }
+    }
+  }
+}
+
+impl Display for SyncProgress {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "Synced block {}/{} ({:.1}%)",
+      self.current_id,
+      self.target_id,
+      self.percent()
+    )?;
+    match self.eta() {
+      Some(eta) => write!(f, ", eta {}s", eta.as_secs()),
+      None => Ok(()),
+    }
+  }
+}
+
+/// headers have to extend `parent` and each other
+pub(crate) fn verify_headers(
+  parent: Option<&BlockHeader>,
+  headers: &[BlockHeader],
+) -> AppResult<()> {
+  let mut parent = parent;
+  for header in headers {
+    let expected = parent.map(|p| p.id + 1).unwrap_or_default();
+    if header.id != expected {
+      let got = header.id;
+      return Err(StorageError::NotContiguous { expected, got }.into());
+    }
+    if parent.map(|p| p.hash) != header.parent_hash {
+      return Err(StorageError::ParentMismatch { id: header.id }.into());
+    }
+    parent = Some(header);
+  }
+  Ok(())
+}
+
+/// work proven by the hashes of `headers`, every leading zero byte makes a block 256 times harder
+pub(crate) fn chain_work(headers: &[BlockHeader]) -> u128 {
+  headers
+    .iter()
+    .map(|header| 1u128 << (8 * header.hash.difficulty().min(15)))
+    .fold(0, u128::saturating_add)
+}
+
+/// latest header both chains share, `local` and `remote` have to start at the same id
+pub(crate) fn fork_point<'a>(
+  local: &'a [BlockHeader],
+  remote: &[BlockHeader],
+) -> Option<&'a BlockHeader> {
+  local
+    .iter()
+    .zip(remote)
+    .take_while(|(local, remote)| local.id == remote.id && local.hash == remote.hash)
+    .last()
+    .map(|(local, _)| local)
+}
+
+/// headers-first download, the header chain is agreed on by a majority of peers,
+/// then bodies are fetched in windows from single peers and checked against it
+pub(crate) struct Downloader {
+  pub db: Arc<Mutex<Store>>,
+  pub sync_requests: Sender<TargetedRequest>,
+  pub network_headers: Receiver<(PeerId, RequestId, Vec<BlockHeader>)>,
+  pub network_blocks: Receiver<(PeerId, Block)>,
+  pub peers: Vec<PeerId>,
+  pub serving_ranges: HashMap<PeerId, (i64, i64)>,
+  pub majority: usize,
+  pub progress: Arc<RwLock<Option<SyncProgress>>>,
+  pub peer_reports: Sender<(PeerId, Behaviour)>,
+}
+
+impl Downloader {
+  pub async fn run(&self, local: Option<&Block>, target: &Block) -> AppResult<()> {
+    let parent = local.map(BlockHeader::from);
+    let from = local.map(|b| b.id + 1).unwrap_or_default();
+    let headers = self.fetch_headers(parent, from, target).await?;
+    let start_id = local.map(|b| b.id).unwrap_or(-1);
+    *self.progress.write().await = Some(SyncProgress::new(start_id, target.id));
+    for window in headers.chunks(WINDOW) {
+      let blocks = self.fetch_window(window).await?;
+      let mut db = self.db.lock().await;
+      for block in &blocks {
+        db.commit_block(block)?;
+      }
+      let mut progress = self.progress.write().await;
+      if let (Some(progress), Some(last)) = (progress.as_mut(), blocks.last()) {
+        progress.advance(last.id);
+        info!("{}", progress);
+      }
+    }
+    Ok(())
+  }
+
+  /// switches to the chain of `target` if it forked off the local chain at most
+  /// `MAX_REORG_DEPTH` blocks back and has more work, the local blocks after the fork
+  /// are only rolled back once all competing blocks are in and restored if they don't commit
+  pub async fn reorg(&self, target: &Block) -> AppResult<()> {
+    let (start, local) = {
+      let db = self.db.lock().await;
+      let (first, latest) = match db.serving_range()? {
+        Some(range) => range,
+        None => return Err(AppError::msg("No local chain to reorg")),
+      };
+      let start = max(first, latest - MAX_REORG_DEPTH);
+      let mut local = vec![];
+      for id in start..=latest {
+        match db.get_header(id)? {
+          Some(header) => local.push(header),
+          None => break,
+        }
+      }
+      (start, local)
+    };
+    let remote = self.fetch_headers(None, start, target).await?;
+    let fork = match fork_point(&local, &remote) {
+      Some(fork) => fork.id,
+      None => {
+        let message = format!("Fork is deeper than {} blocks", MAX_REORG_DEPTH);
+        return Err(AppError::msg(message));
+      }
+    };
+    let after_fork = (fork - start + 1) as usize;
+    let (ours, theirs) = (&local[after_fork..], &remote[after_fork..]);
+    if chain_work(theirs) <= chain_work(ours) {
+      return Err(AppError::msg("Competing chain doesn't have more work"));
+    }
+    info!(
+      "Switching to the chain of block {}, forked at {}, {} local blocks are dropped",
+      target.id,
+      fork,
+      ours.len()
+    );
+    let mut blocks = vec![];
+    for window in theirs.chunks(WINDOW) {
+      blocks.extend(self.fetch_window(window).await?);
+    }
+
+    let mut db = self.db.lock().await;
+    let mut dropped = vec![];
+    while dropped.len() < ours.len() {
+      match db.rollback_block()? {
+        Some(block) => dropped.push(block),
+        None => break,
+      }
+    }
+    for (committed, block) in blocks.iter().enumerate() {
+      if let Err(e) = db.commit_block(block) {
+        for _ in 0..committed {
+          db.rollback_block()?;
+        }
+        for block in dropped.iter().rev() {
+          db.commit_block(block)?;
+        }
+        return Err(AppError::msg(format!("Restored the local chain, {}", e)));
+      }
+    }
+    Ok(())
+  }
+
+  /// headers from `from` up to `target`, linked to `parent` if there is one
+  async fn fetch_headers(
+    &self,
+    parent: Option<BlockHeader>,
+    mut from: i64,
+    target: &Block,
+  ) -> AppResult<Vec<BlockHeader>> {
+    let mut headers: Vec<BlockHeader> = vec![];
+    while from <= target.id {
+      let count = (target.id - from + 1).min(MAX_HEADERS);
+      let request_id = next_request_id();
+      let request = SyncRequest::Headers(request_id, from, count);
+      self.sync_requests.send((request, None)).await?;
+      let votes = self
+        .network_headers
+        .clone()
+        .filter(move |(_, id, _)| future::ready(*id == request_id))
+        .map(|(peer, _, batch)| (peer, batch))
+        .take_until(task::sleep(TIMEOUT));
+      let consensus = vote_by(votes, &self.peers, self.majority, |batch| {
+        (batch.first().map(|h| h.id), batch.last().map(|h| h.hash))
+      })
+      .await;
+      debug!("Headers from {} {}", from, describe_votes(&consensus));
+      let batch = match consensus.value {
+        Some(batch) => batch,
+        None => return Err(AppError::msg(format!("No headers agreed on from {}", from))),
+      };
+      match (headers.last().or(parent.as_ref()), batch.split_first()) {
+        (None, Some((first, rest))) if from > 0 => {
+          if first.id != from {
+            let got = first.id;
+            return Err(
+              StorageError::NotContiguous {
+                expected: from,
+                got,
+              }
+              .into(),
+            );
+          }
+          verify_headers(Some(first), rest)?
+        }
+        (parent, _) => verify_headers(parent, &batch)?,
+      }
+      from += batch.len() as i64;
+      info!("Received headers up to {}", from - 1);
+      headers.extend(batch);
+    }
+    match headers.last() {
+      Some(last) if last.hash == target.hash_digest() => Ok(headers),
+      _ => Err(AppError::msg("Headers don't lead to the latest block")),
+    }
+  }
+
+  fn serving_peers(&self, id: i64) -> Vec<PeerId> {
+    self
+      .peers
+      .iter()
+      .filter(|peer| match self.serving_ranges.get(peer) {
+        Some((first, latest)) => (*first..=*latest).contains(&id),
+        None => true,
+      })
+      .copied()
+      .collect()
+  }
+
+  /// missing blocks are asked from the next serving peer on every attempt
+  async fn fetch_window(&self, window: &[BlockHeader]) -> AppResult<Vec<Block>> {
+    let mut blocks: BTreeMap<i64, Block> = BTreeMap::new();
+    for attempt in 0..MAX_ATTEMPTS {
+      let missing = window.iter().filter(|h| !blocks.contains_key(&h.id));
+      for (idx, header) in missing.enumerate() {
+        let peers = self.serving_peers(header.id);
+        if peers.is_empty() {
+          return Err(AppError::msg(format!("No peer serves block {}", header.id)));
+        }
+        let peer = peers[(idx + attempt) % peers.len()];
+        let request = SyncRequest::Block(header.id);
+        self.sync_requests.send((request, Some(peer))).await?;
+      }
+      let deadline = Instant::now() + TIMEOUT;
+      while blocks.len() < window.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let (peer, block) = match timeout(remaining, self.network_blocks.recv()).await {
+          Ok(received) => received?,
+          Err(_) => break,
+        };
+        match window.iter().find(|h| h.id == block.id) {
+          Some(header) if header.hash == block.hash_digest() => {
+            blocks.insert(block.id, block);
+            self.peer_reports.send((peer, Behaviour::Useful)).await?;
+          }
+          Some(_) => {
+            let short = peer.short_display();
+            warn!("Block {} from {} doesn't match its header", block.id, short);
+            self
+              .peer_reports
+              .send((peer, Behaviour::InvalidBlock))
+              .await?;
+          }
+          None => (),
+        }
+      }
+      if blocks.len() == window.len() {
+        return Ok(blocks.into_values().collect());
+      }
+      warn!("{} blocks missing, retrying", window.len() - blocks.len());
+    }
+    Err(AppError::msg("Block download timed out"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn header_chain(len: usize) -> Vec<BlockHeader> {
+    let mut block = Block::default();
+    let mut headers = vec![BlockHeader::from(&block)];
+    for _ in 1..len {
+      block = Block::from_previous(&block, None::<Vec<_>>);
+      headers.push(BlockHeader::from(&block));
+    }
+    headers
+  }
+
+  #[test]
+  fn verify_headers_test() -> AppResult<()> {
+    let headers = header_chain(4);
+    verify_headers(None, &headers)?;
+    verify_headers(Some(&headers[1]), &headers[2..])?;
+
+    let e = verify_headers(Some(&headers[0]), &headers[2..]).unwrap_err();
+    assert_eq!(
+      StorageError::of(&e),
+      StorageError::NotContiguous {
+        expected: 1,
+        got: 2
+      }
+    );
+    let mut forked = headers.clone();
+    forked[2].parent_hash = Some(forked[0].hash);
+    let e = verify_headers(None, &forked).unwrap_err();
+    assert_eq!(StorageError::of(&e), StorageError::ParentMismatch { id: 2 });
+    Ok(())
+  }
+
+  #[test]
+  fn fork_point_test() {
+    let local = header_chain(5);
+    let mut remote = local.clone();
+    remote[3].hash = "other".hash_digest();
+    remote[4].parent_hash = Some(remote[3].hash);
+    assert_eq!(fork_point(&local, &remote), Some(&local[2]));
+    assert_eq!(fork_point(&local[1..], &remote[1..]), Some(&local[2]));
+    assert_eq!(fork_point(&local, &remote[1..]), None);
+    assert_eq!(fork_point(&local[3..], &remote[3..]), None);
+  }
+
+  #[test]
+  fn chain_work_test() {
+    let mut headers = header_chain(3);
+    for (idx, header) in headers.iter_mut().enumerate() {
+      header.hash.fill(1);
+      header.hash[..idx].fill(0);
+    }
+    assert_eq!(chain_work(&headers[..1]), 1);
+    assert_eq!(chain_work(&headers), 1 + 256 + 65536);
+    assert!(chain_work(&headers[2..]) > chain_work(&headers[..2]));
+    headers[0].hash = Default::default();
+    assert_eq!(chain_work(&headers[..1]), 1 << 120);
+  }
+
+  #[test]
+  fn sync_progress_test() {
+    let mut progress = SyncProgress::new(9, 109);
+    assert_eq!(progress.eta(), None);
+    progress.advance(59);
+    assert_eq!(progress.percent(), 50.0);
+    assert!(progress.eta().is_some());
+    assert!(progress
+      .to_string()
+      .starts_with("Synced block 59/109 (50.0%)"));
+  }
+}
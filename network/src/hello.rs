@@ -0,0 +1,229 @@
+use bchain_db::store::Store;
+use bchain_domain::utxo::Ledger;
+use bchain_util::error::AppError;
+use bchain_util::hash_digest::{HashDigest, Hashable};
+use bchain_util::result::AppResult;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+pub const PROTOCOL_VERSION: u32 = 6;
+/// oldest protocol version still understood, only v6 peers are accepted
+pub const MIN_PROTOCOL_VERSION: u32 = 6;
+
+/// what a peer can be asked for, combined as bit flags
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Services(u32);
+
+impl Services {
+  /// serves every block since genesis
+  pub const FULL: Services = Services(1);
+  /// serves only recent blocks, older ones are reduced to headers
+  pub const PRUNED: Services = Services(1 << 1);
+  /// doesn't store blocks, only follows headers
+  pub const LIGHT: Services = Services(1 << 2);
+
+  pub fn contains(&self, other: Services) -> bool {
+    self.0 & other.0 == other.0
+  }
+}
+
+impl std::ops::BitOr for Services {
+  type Output = Services;
+  fn bitor(self, other: Services) -> Services {
+    Services(self.0 | other.0)
+  }
+}
+
+impl Display for Services {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let names = [
+      (Services::FULL, "full"),
+      (Services::PRUNED, "pruned"),
+      (Services::LIGHT, "light"),
+    ];
+    let names: Vec<_> = names
+      .iter()
+      .filter(|(flag, _)| self.contains(*flag))
+      .map(|(_, name)| *name)
+      .collect();
+    write!(f, "{}", names.join("|"))
+  }
+}
+
+/// exchanged on every new connection, peers that don't match are disconnected
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+  pub version: u32,
+  pub chain_id: String,
+  pub genesis: Option<HashDigest>,
+  /// ledger fixed by the genesis block, unknown without one
+  pub ledger: Option<Ledger>,
+  pub best_height: Option<i64>,
+  pub best_hash: Option<HashDigest>,
+  pub user_agent: String,
+  pub services: Services,
+}
+
+impl Hello {
+  pub fn new(chain_id: &str, db: &Store) -> AppResult<Hello> {
+    let latest = db.latest_block()?;
+    let genesis = db.get_header(0)?.map(|h| h.hash);
+    let services = match db.serving_range()? {
+      Some((first, _)) if first > 0 => Services::PRUNED,
+      _ => Services::FULL,
+    };
+    Ok(Hello {
+      version: PROTOCOL_VERSION,
+      chain_id: chain_id.to_owned(),
+      genesis,
+      ledger: match genesis {
+        Some(_) => Some(db.ledger()?),
+        None => None,
+      },
+      best_height: latest.as_ref().map(|b| b.id),
+      best_hash: latest.as_ref().map(|b| b.hash_digest()),
+      user_agent: format!("bchain/{}", env!("CARGO_PKG_VERSION")),
+      services,
+    })
+  }
+
+  /// nodes that haven't got a genesis block yet accept any
+  pub fn verify(&self, local: &Hello) -> AppResult<()> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.version) {
+      let message = format!("Unsupported protocol version {}", self.version);
+      return Err(AppError::msg(message));
+    }
+    if self.chain_id != local.chain_id {
+      return Err(AppError::msg(format!("Different chain {}", self.chain_id)));
+    }
+    if let (Some(theirs), Some(ours)) = (self.genesis, local.genesis) {
+      if theirs != ours {
+        return Err(AppError::msg(format!("Different genesis {}", theirs)));
+      }
+    }
+    if let (Some(theirs), Some(ours)) = (self.ledger, local.ledger) {
+      if theirs != ours {
+        return Err(AppError::msg(format!("Different ledger {}", theirs)));
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Display for Hello {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{} v{} {} height {:?}",
+      self.user_agent, self.version, self.services, self.best_height
+    )
+  }
+}
+
+/// connected peers and their hello, peers that don't send one in time are dropped
+#[derive(Debug, Default)]
+pub struct Handshakes {
+  pending: HashMap<PeerId, Instant>,
+  done: HashMap<PeerId, Hello>,
+}
+
+impl Handshakes {
+  pub fn connected(&mut self, peer_id: PeerId) {
+    if !self.done.contains_key(&peer_id) {
+      self.pending.entry(peer_id).or_insert_with(Instant::now);
+    }
+  }
+
+  /// false if the peer disconnected in the meantime
+  pub fn completed(&mut self, peer_id: PeerId, hello: Hello) -> bool {
+    if self.pending.remove(&peer_id).is_none() && !self.done.contains_key(&peer_id) {
+      return false;
+    }
+    self.done.insert(peer_id, hello);
+    true
+  }
+
+  pub fn disconnected(&mut self, peer_id: &PeerId) {
+    self.pending.remove(peer_id);
+    self.done.remove(peer_id);
+  }
+
+  pub fn get(&self, peer_id: &PeerId) -> Option<&Hello> {
+    self.done.get(peer_id)
+  }
+
+  pub fn is_done(&self, peer_id: &PeerId) -> bool {
+    self.done.contains_key(peer_id)
+  }
+
+  /// peers connected longer than `timeout` without a hello
+  pub fn expired(&self, timeout: Duration) -> Vec<PeerId> {
+    let expired = self
+      .pending
+      .iter()
+      .filter(|(_, since)| since.elapsed() >= timeout);
+    expired.map(|(peer_id, _)| *peer_id).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_db::memory_store::MemoryStore;
+  use bchain_domain::block::Block;
+
+  #[test]
+  fn hello_verify_test() -> AppResult<()> {
+    let mut store: Store = Box::<MemoryStore>::default();
+    let empty = Hello::new("bchain", &store)?;
+    store.commit_as_genesis(&Block::default())?;
+    let local = Hello::new("bchain", &store)?;
+    assert_eq!(local.best_height, Some(0));
+    assert!(local.services.contains(Services::FULL));
+
+    empty.verify(&local)?;
+    local.verify(&empty)?;
+    let other_chain = Hello::new("other", &store)?;
+    assert!(other_chain.verify(&local).is_err());
+    let other_genesis = Hello {
+      genesis: Some("other".hash_digest()),
+      ..local.clone()
+    };
+    assert!(other_genesis.verify(&local).is_err());
+    assert_eq!(local.ledger, Some(Ledger::Account));
+    let other_ledger = Hello {
+      ledger: Some(Ledger::Utxo),
+      ..local.clone()
+    };
+    assert!(other_ledger.verify(&local).is_err());
+    let newer = Hello {
+      version: PROTOCOL_VERSION + 1,
+      ..local.clone()
+    };
+    assert!(newer.verify(&local).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn handshakes_test() -> AppResult<()> {
+    let store: Store = Box::<MemoryStore>::default();
+    let hello = Hello::new("bchain", &store)?;
+    let (greeted, silent) = (PeerId::random(), PeerId::random());
+    let mut handshakes = Handshakes::default();
+    handshakes.connected(greeted);
+    handshakes.connected(silent);
+    assert!(handshakes.completed(greeted, hello.clone()));
+    assert!(handshakes.is_done(&greeted));
+    assert!(!handshakes.is_done(&silent));
+    assert!(handshakes.expired(Duration::from_secs(60)).is_empty());
+    assert_eq!(handshakes.expired(Duration::ZERO), vec![silent]);
+
+    handshakes.disconnected(&silent);
+    assert!(!handshakes.completed(silent, hello));
+    assert!(handshakes.expired(Duration::ZERO).is_empty());
+    Ok(())
+  }
+}
@@ -0,0 +1,302 @@
+use crate::compact::CompactBlock;
+use bchain_domain::{block::Block, tx::Tx};
+use bchain_util::hash_digest::{HashDigest, Hashable};
+use libp2p::request_response::RequestId;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// upper bound of items asked for in one GetData
+pub const MAX_INV: usize = 1000;
+/// hashes remembered, older ones may be fetched again
+const SEEN_CAPACITY: usize = 10_000;
+/// items not delivered by then are fetched again when announced
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// peers remembered per item to fetch it from if the first one fails
+const MAX_ANNOUNCERS: usize = 8;
+
+/// announced by hash, peers fetch what they are missing with GetData
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InvItem {
+  Tx(HashDigest),
+  Block(HashDigest),
+  /// asks for a block as header and short tx ids, never announced
+  CompactBlock(HashDigest),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum InvData {
+  Tx(Tx),
+  Block(Block),
+  CompactBlock(CompactBlock),
+}
+
+impl InvItem {
+  /// the item as announced, compact blocks are announced as blocks
+  pub fn announced(self) -> InvItem {
+    match self {
+      InvItem::CompactBlock(hash) => InvItem::Block(hash),
+      item => item,
+    }
+  }
+}
+
+impl InvData {
+  pub fn item(&self) -> InvItem {
+    match self {
+      InvData::Tx(tx) => InvItem::Tx(tx.hash_digest()),
+      InvData::Block(block) => InvItem::Block(block.hash_digest()),
+      InvData::CompactBlock(compact) => InvItem::CompactBlock(compact.header.hash),
+    }
+  }
+}
+
+impl Display for InvItem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      InvItem::Tx(hash) => write!(f, "tx {}", hash),
+      InvItem::Block(hash) => write!(f, "block {}", hash),
+      InvItem::CompactBlock(hash) => write!(f, "compact block {}", hash),
+    }
+  }
+}
+
+/// item being fetched, with the peers that announced it
+#[derive(Debug)]
+struct Fetch {
+  since: Instant,
+  announcers: Vec<PeerId>,
+}
+
+/// bounded cache of seen items, the data of announced ones is kept to answer GetData,
+/// items are only seen once their data was validated, until then they are in flight
+#[derive(Debug)]
+pub struct Inventory<R = RequestId> {
+  capacity: usize,
+  order: VecDeque<InvItem>,
+  seen: HashSet<InvItem>,
+  data: HashMap<InvItem, InvData>,
+  in_flight: HashMap<InvItem, Fetch>,
+  /// items asked for by each outstanding GetData
+  requests: HashMap<R, Vec<InvItem>>,
+}
+
+impl<R: Eq + Hash> Default for Inventory<R> {
+  fn default() -> Self {
+    Inventory::new(SEEN_CAPACITY)
+  }
+}
+
+impl<R: Eq + Hash> Inventory<R> {
+  pub fn new(capacity: usize) -> Inventory<R> {
+    Inventory {
+      capacity,
+      order: VecDeque::new(),
+      seen: HashSet::new(),
+      data: HashMap::new(),
+      in_flight: HashMap::new(),
+      requests: HashMap::new(),
+    }
+  }
+
+  pub fn is_seen(&self, item: &InvItem) -> bool {
+    self.seen.contains(item)
+  }
+
+  pub fn is_in_flight(&self, item: &InvItem) -> bool {
+    self.in_flight.contains_key(item)
+  }
+
+  /// false if the item was seen before, it's no longer in flight
+  pub fn see(&mut self, item: InvItem) -> bool {
+    self.in_flight.remove(&item);
+    if !self.seen.insert(item) {
+      return false;
+    }
+    self.order.push_back(item);
+    while self.order.len() > self.capacity {
+      if let Some(oldest) = self.order.pop_front() {
+        self.seen.remove(&oldest);
+        self.data.remove(&oldest);
+      }
+    }
+    true
+  }
+
+  /// false if the data was there already, the item is marked as seen
+  pub fn insert(&mut self, data: InvData) -> bool {
+    let item = data.item();
+    self.see(item);
+    self.data.insert(item, data).is_none()
+  }
+
+  /// announced items neither seen nor in flight, they are in flight from now on,
+  /// `source` is remembered to fetch the others from if their fetch fails
+  pub fn missing(&mut self, source: PeerId, items: &[InvItem]) -> Vec<InvItem> {
+    self
+      .in_flight
+      .retain(|_, fetch| fetch.since.elapsed() < FETCH_TIMEOUT);
+    let mut missing = vec![];
+    for item in items.iter().take(MAX_INV) {
+      if self.seen.contains(item) {
+        continue;
+      }
+      match self.in_flight.get_mut(item) {
+        Some(fetch) => {
+          let announcers = &mut fetch.announcers;
+          if announcers.len() < MAX_ANNOUNCERS && !announcers.contains(&source) {
+            announcers.push(source);
+          }
+        }
+        None => {
+          let fetch = Fetch {
+            since: Instant::now(),
+            announcers: vec![source],
+          };
+          self.in_flight.insert(*item, fetch);
+          missing.push(*item);
+        }
+      }
+    }
+    missing
+  }
+
+  /// remembers what a GetData asked for, until it is answered or failed
+  pub fn requested(&mut self, request: R, items: &[InvItem]) {
+    let items = items.iter().map(|item| item.announced());
+    let items: Vec<InvItem> = items.filter(|item| self.is_in_flight(item)).collect();
+    if !items.is_empty() {
+      self.requests.insert(request, items);
+    }
+  }
+
+  /// items the request asked for that aren't among `received` and are still in flight,
+  /// a failed request received nothing
+  pub fn unanswered(&mut self, request: &R, received: &[InvItem]) -> Vec<InvItem> {
+    let asked = self.requests.remove(request).unwrap_or_default();
+    let received: HashSet<InvItem> = received.iter().map(|item| item.announced()).collect();
+    asked
+      .into_iter()
+      .filter(|item| !received.contains(item) && self.is_in_flight(item))
+      .collect()
+  }
+
+  /// another peer that announced the item `failed` didn't deliver, if there is none
+  /// the item is no longer in flight and fetched again when announced again
+  pub fn refetch(&mut self, item: InvItem, failed: &PeerId) -> Option<PeerId> {
+    let fetch = self.in_flight.get_mut(&item)?;
+    fetch.announcers.retain(|peer| peer != failed);
+    match fetch.announcers.first() {
+      Some(peer) => {
+        fetch.since = Instant::now();
+        Some(*peer)
+      }
+      None => {
+        self.in_flight.remove(&item);
+        None
+      }
+    }
+  }
+
+  /// compact blocks are made from the full blocks kept
+  pub fn get(&self, items: &[InvItem]) -> Vec<InvData> {
+    let get = |item: &InvItem| match item {
+      InvItem::CompactBlock(hash) => self
+        .block(hash)
+        .map(CompactBlock::new)
+        .map(InvData::CompactBlock),
+      item => self.data.get(item).cloned(),
+    };
+    items.iter().take(MAX_INV).filter_map(get).collect()
+  }
+
+  pub fn block(&self, hash: &HashDigest) -> Option<&Block> {
+    match self.data.get(&InvItem::Block(*hash)) {
+      Some(InvData::Block(block)) => Some(block),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn inventory_test() {
+    let blocks: Vec<Block> = (0..3)
+      .scan(Block::default(), |block, _| {
+        *block = Block::from_previous(block, None::<Vec<_>>);
+        Some(block.clone())
+      })
+      .collect();
+    let items: Vec<InvItem> = blocks
+      .iter()
+      .map(|b| InvItem::Block(b.hash_digest()))
+      .collect();
+    let peer = PeerId::random();
+    let mut inventory = Inventory::<u32>::new(2);
+
+    assert!(inventory.insert(InvData::Block(blocks[0].clone())));
+    assert!(!inventory.insert(InvData::Block(blocks[0].clone())));
+    assert_eq!(inventory.missing(peer, &items), items[1..].to_vec());
+    assert!(inventory.missing(peer, &items[1..]).is_empty());
+    inventory.see(items[1]);
+    inventory.see(items[2]);
+
+    // the oldest item was evicted with its data
+    assert!(!inventory.is_seen(&items[0]));
+    assert!(inventory.get(&items).is_empty());
+    inventory.insert(InvData::Block(blocks[2].clone()));
+    assert_eq!(
+      inventory.get(&items),
+      vec![InvData::Block(blocks[2].clone())]
+    );
+    let compact = InvItem::CompactBlock(blocks[2].hash_digest());
+    assert_eq!(
+      inventory.get(&[compact]),
+      vec![InvData::CompactBlock(CompactBlock::new(&blocks[2]))]
+    );
+  }
+
+  #[test]
+  fn in_flight_test() {
+    let items: Vec<InvItem> = (0..3).map(|i| InvItem::Block(vec![i; 32].into())).collect();
+    let (peer1, peer2) = (PeerId::random(), PeerId::random());
+    let mut inventory = Inventory::<u32>::new(10);
+
+    assert_eq!(inventory.missing(peer1, &items[..2]), items[..2].to_vec());
+    assert_eq!(inventory.missing(peer2, &items), items[2..].to_vec());
+    let compact: Vec<InvItem> = items[..2]
+      .iter()
+      .map(|item| match item {
+        InvItem::Block(hash) => InvItem::CompactBlock(*hash),
+        item => *item,
+      })
+      .collect();
+    inventory.requested(1, &compact);
+    inventory.requested(2, &items[2..]);
+    assert!(!inventory.is_seen(&items[0]));
+
+    // only the first item was delivered, the second is asked from the other announcer
+    assert_eq!(
+      inventory.unanswered(&1, &compact[..1]),
+      items[1..2].to_vec()
+    );
+    assert!(inventory.unanswered(&1, &[]).is_empty());
+    assert_eq!(inventory.refetch(items[1], &peer1), Some(peer2));
+    assert_eq!(inventory.refetch(items[1], &peer2), None);
+    assert!(!inventory.is_in_flight(&items[1]));
+    assert_eq!(inventory.missing(peer1, &items[1..2]), items[1..2].to_vec());
+
+    // validated data is seen and no longer in flight
+    assert!(inventory.is_in_flight(&items[0]));
+    inventory.see(items[0]);
+    assert!(!inventory.is_in_flight(&items[0]));
+    assert!(inventory.missing(peer2, &items[..1]).is_empty());
+    assert_eq!(inventory.unanswered(&2, &[]), items[2..].to_vec());
+  }
+}
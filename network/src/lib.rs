@@ -1,6 +1,15 @@
+pub mod codec;
 pub mod commands;
+pub mod compact;
+pub mod discovery;
+pub mod download;
+pub mod hello;
+pub mod inventory;
 pub mod mine;
 pub mod network;
 pub mod node;
+pub mod peer_manager;
 pub mod protocol;
 pub mod swarm;
+pub mod sync;
+pub mod transport;
@@ -2,13 +2,17 @@ use crate::protocol::{BchainRequest, BchainResponse};
 use async_std::channel::{Receiver, Sender};
 use async_std::stream::interval;
 use async_std::sync::{Mutex, RwLock};
-use bchain_db::database::Db;
+use bchain_db::store::{unsupported, Store};
 use bchain_domain::block::Block;
 use bchain_domain::tx::Tx;
 use bchain_domain::tx_pool::TxPool;
+use bchain_domain::utxo::Ledger;
 use bchain_domain::wallet::Wallet;
+use bchain_util::error::AppError;
 use bchain_util::hash_digest::Hashable;
 use bchain_util::result::AppResult;
+use bchain_util::short::ShortDisplay;
+use chrono::Utc;
 use futures::{prelude::*, select};
 use log::{info, warn};
 use std::sync::Arc;
@@ -18,8 +22,8 @@ const EVERY_10_MINUTES: Duration = Duration::from_secs(60_00);
 
 pub(crate) async fn mine(
   _wallet: Arc<RwLock<Wallet>>,
-  _db: Arc<Mutex<Db>>,
-  _pool: Arc<Mutex<TxPool>>,
+  db: Arc<Mutex<Store>>,
+  pool: Arc<Mutex<TxPool>>,
   mut proposed_tx: Receiver<Tx>,
   mut proposed_blocks: Receiver<Block>,
   _bchain_request: Sender<BchainRequest>,
@@ -29,7 +33,11 @@ pub(crate) async fn mine(
   loop {
     select! {
       _tick = timer.select_next_some() => {
-        info!("mining cycle")
+        info!("mining cycle");
+        let expired = pool.lock().await.expire(Utc::now().timestamp());
+        if expired > 0 {
+          info!("{} txs expired from the pool", expired);
+        }
         // let proposed_block = {
         //   let mut pool = pool.lock().await;
         //     pool.mine(1).await?;
@@ -39,10 +47,19 @@ pub(crate) async fn mine(
       },
       tx = proposed_tx.select_next_some() => {
         let response = bchain_response.clone();
-        handle_proposed_tx(tx, response).await?;
+        let pooled = {
+          let mut pool = pool.lock().await;
+          check_tx(&*db.lock().await, &pool, &tx)
+            .and_then(|_| pool.add(tx.clone(), Utc::now().timestamp()))
+        };
+        match pooled {
+          Ok(()) => handle_proposed_tx(tx, response).await?,
+          Err(e) => warn!("Tx {} not pooled: {}", tx.hash_digest().short_display(), e),
+        }
       },
       block = proposed_blocks.select_next_some() => {
         let response = bchain_response.clone();
+        pool.lock().await.remove_block(&block);
         handle_proposed_block(block, response).await?;
       },
       complete => break,
@@ -52,6 +69,41 @@ pub(crate) async fn mine(
   Ok(())
 }
 
+/// a pooled tx has to be new and spendable together with the txs already pending
+fn check_tx(db: &Store, pool: &TxPool, tx: &Tx) -> AppResult<()> {
+  let hash = tx.hash_digest();
+  if let Some(info) = db.get_tx(&hash.to_string())? {
+    let message = format!("Tx is already in block {}", info.block_id);
+    return Err(AppError::msg(message));
+  }
+  let sender = tx.sender();
+  match db.ledger()? {
+    Ledger::Account => {
+      let available = db.balance(sender)? - pool.spent_by(sender);
+      if available < -tx.diff_for_address(sender) {
+        return Err(AppError::msg("Sender balance doesnt cover the tx"));
+      }
+    }
+    Ledger::Utxo => {
+      let index = db.index().ok_or_else(|| unsupported("Utxo ledger"))?;
+      let unspent = index.unspent(sender)?;
+      let mut spent = 0u64;
+      for input in tx.inputs() {
+        let utxo = unspent.iter().find(|utxo| &utxo.outpoint == input);
+        match utxo {
+          Some(utxo) if !pool.spends(input) => spent += utxo.output.amount,
+          Some(_) => return Err(AppError::msg(format!("Output {} is already spent", input))),
+          None => return Err(AppError::msg(format!("Output {} isnt unspent", input))),
+        }
+      }
+      if tx.inputs().is_empty() || spent != tx.total()? {
+        return Err(AppError::msg("Tx inputs dont match its outputs"));
+      }
+    }
+  }
+  Ok(())
+}
+
 async fn handle_proposed_tx(tx: Tx, response: Sender<BchainResponse>) -> AppResult<()> {
   response
     .send(BchainResponse::AcceptTx(tx.hash_digest()))
@@ -67,3 +119,34 @@ async fn handle_proposed_block(block: Block, response: Sender<BchainResponse>) -
   info!("miner received block {:?}", block.hash_digest());
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_db::memory_store::MemoryStore;
+  use bchain_domain::address::Address;
+  use bchain_domain::block::GENESIS_REWARD;
+  const RSAKEY_PEM: &str = "../pem/rsakey.pem";
+
+  #[async_std::test]
+  async fn check_tx_test() -> AppResult<()> {
+    let wallet = Wallet::from_file(RSAKEY_PEM).await?;
+    let other = Address::default();
+    let mut store: Store = Box::<MemoryStore>::default();
+    let genesis = Block::new(Some([wallet.new_coinbase_tx(GENESIS_REWARD)?]));
+    store.commit_as_genesis(&genesis)?;
+    let mut pool = TxPool::default();
+
+    let tx = Tx::new(&wallet, &other, GENESIS_REWARD - 1)?;
+    check_tx(&store, &pool, &tx)?;
+    pool.add(tx.clone(), Utc::now().timestamp())?;
+    check_tx(&store, &pool, &Tx::new(&wallet, &other, 1)?)?;
+    assert!(check_tx(&store, &pool, &Tx::new(&wallet, &other, 2)?).is_err());
+
+    let block = Block::from_previous(&genesis, Some([tx.clone()]));
+    store.commit_block(&block)?;
+    pool.remove_block(&block);
+    assert!(check_tx(&store, &pool, &tx).is_err());
+    Ok(())
+  }
+}
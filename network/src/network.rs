@@ -1,30 +1,37 @@
-use crate::protocol::BchainRequest;
+use crate::download::TargetedRequest;
+use crate::sync::{next_request_id, RequestId, SyncRequest};
 use async_std::channel::{Receiver, Sender};
-use async_std::future::timeout;
 use async_std::sync::{Mutex, RwLock};
-use bchain_db::database::Db;
+use async_std::task;
+use bchain_db::store::{unsupported, Store};
 use bchain_domain::address::Address;
-use bchain_domain::block::Block;
+use bchain_domain::block::{Block, GENESIS_REWARD};
+use bchain_domain::tx::{Tx, TxOutput};
+use bchain_domain::utxo::Ledger;
 use bchain_domain::wallet::Wallet;
-use bchain_util::group::group_default;
+use bchain_util::error::AppError;
+use bchain_util::group::{vote_by, Consensus};
 use bchain_util::hash_digest::Hashable;
 use bchain_util::result::AppResult;
+use bchain_util::short::ShortDisplay;
 use futures::prelude::*;
+use libp2p::PeerId;
 use log::info;
 use std::{sync::Arc, time::Duration};
 
 pub type NumPeersConsensus = (usize, usize);
 
-const TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(10);
 
 pub(crate) async fn bootstrap_init(
   wallet: Arc<RwLock<Wallet>>,
-  db: Arc<Mutex<Db>>,
+  db: Arc<Mutex<Store>>,
+  ledger: Ledger,
 ) -> AppResult<()> {
   let genesis = {
     let wallet = wallet.read().await;
-    let tx = wallet.new_coinbase_tx(1_000_000)?;
-    Block::new(Some([tx]))
+    let tx = wallet.new_coinbase_tx(GENESIS_REWARD)?;
+    Block::genesis(ledger, Some([tx]))
   };
   let mut db = db.lock().await;
   db.commit_as_genesis(&genesis)?;
@@ -32,43 +39,87 @@ pub(crate) async fn bootstrap_init(
   Ok(())
 }
 
+/// latest block a majority of `peers` agree on, each peer's first answer to this request counts,
+/// answers are collected until every peer answered or the request timed out
 pub(crate) async fn request_latest_block(
-  (_, majority): &NumPeersConsensus,
-  network_requests: Sender<BchainRequest>,
-  network_latest: Receiver<Block>,
+  peers: &[PeerId],
+  majority: usize,
+  sync_requests: Sender<TargetedRequest>,
+  network_latest: Receiver<(PeerId, RequestId, Option<Block>)>,
 ) -> AppResult<Option<Block>> {
-  network_requests.send(BchainRequest::AskLatest).await?;
-  let mut network_latest_block_stream = group_default(network_latest, *majority);
-  let network_latest_block = timeout(TIMEOUT, network_latest_block_stream.next());
-  Ok(network_latest_block.await?)
+  let request_id = next_request_id();
+  sync_requests
+    .send((SyncRequest::Latest(request_id), None))
+    .await?;
+  let votes = network_latest
+    .filter(move |(_, id, _)| future::ready(*id == request_id))
+    .map(|(peer, _, block)| (peer, block))
+    .take_until(task::sleep(TIMEOUT));
+  let consensus = vote_by(votes, peers, majority, |block| {
+    block.as_ref().map(Hashable::hash_digest)
+  })
+  .await;
+  info!("Latest block {}", describe_votes(&consensus));
+  consensus
+    .value
+    .ok_or_else(|| AppError::msg("No latest block agreed on"))
 }
 
-pub(crate) async fn request_specific_block(
-  id: i64,
-  (_, majority): &NumPeersConsensus,
-  network_requests: Sender<BchainRequest>,
-  network_blocks: Receiver<Block>,
-) -> AppResult<Option<Block>> {
-  network_requests.send(BchainRequest::AskBlock(id)).await?;
-  let network_block_stream =
-    group_default(network_blocks, *majority).filter(|block| future::ready(block.id == id));
-  let mut pinned_stream = Box::pin(network_block_stream);
-  let next = pinned_stream.next();
-  let network_block = timeout(TIMEOUT, next);
-  Ok(network_block.await?)
+/// who agreed, disagreed and didn't answer, for logging
+pub(crate) fn describe_votes<T>(consensus: &Consensus<PeerId, T>) -> String {
+  format!(
+    "agreed on by {}, not by {}, no answer from {}",
+    short_peers(&consensus.agreed),
+    short_peers(&consensus.disagreed),
+    short_peers(&consensus.silent)
+  )
+}
+
+/// peer ids shortened for logging
+pub(crate) fn short_peers(peers: &[PeerId]) -> String {
+  let peers: Vec<_> = peers.iter().map(ShortDisplay::short_display).collect();
+  format!("[{}]", peers.join(", "))
+}
+
+pub(crate) async fn local_balance(address: &Address, db: Arc<Mutex<Store>>) -> AppResult<i64> {
+  db.lock().await.balance(address)
 }
 
-pub(crate) async fn local_balance(address: &Address, db: Arc<Mutex<Db>>) -> AppResult<i64> {
-  let mut id = 0;
-  let mut balance = 0;
-  loop {
-    let block = db.lock().await.get_block(id)?;
-    if let Some(block) = block {
-      balance += block.diff_for_address(address);
-      id += 1;
-    } else {
+pub(crate) async fn local_utxo_balance(address: &Address, db: Arc<Mutex<Store>>) -> AppResult<i64> {
+  let db = db.lock().await;
+  let index = db.index().ok_or_else(|| unsupported("Utxo ledger"))?;
+  index.utxo_balance(address)
+}
+
+/// selects own unspent outputs to cover `outputs`, paying the change back to the wallet
+pub(crate) async fn utxo_tx(
+  wallet: &Wallet,
+  mut outputs: Vec<TxOutput>,
+  db: Arc<Mutex<Store>>,
+) -> AppResult<Tx> {
+  let total = Tx::outputs_total(&outputs)?;
+  let unspent = {
+    let db = db.lock().await;
+    let index = db.index().ok_or_else(|| unsupported("Utxo ledger"))?;
+    index.unspent(&wallet.address())?
+  };
+  let mut inputs = vec![];
+  let mut selected = 0;
+  for utxo in unspent {
+    if selected >= total {
       break;
     }
+    selected += utxo.output.amount;
+    inputs.push(utxo.outpoint);
+  }
+  if selected < total {
+    return Err(AppError::msg(format!(
+      "Insufficient funds: ¢{} available, ¢{} needed",
+      selected, total
+    )));
+  }
+  if selected > total {
+    outputs.push(TxOutput::new(&wallet.address(), selected - total));
   }
-  Ok(balance)
+  Tx::spending(wallet, inputs, outputs)
 }
@@ -1,48 +1,155 @@
+use crate::codec::{decode, encode};
+use crate::commands::watch::WatchCommand;
 use crate::commands::UserCommand;
+use crate::compact::{block_txs, CompactBlock, PartialBlock, PartialBlocks};
+use crate::discovery::{peer_id_of, Discovery};
+use crate::download::{Downloader, SyncProgress, TargetedRequest};
+use crate::hello::{Handshakes, Hello};
+use crate::inventory::{InvData, InvItem, Inventory};
 use crate::mine::mine;
 use crate::network::{
-  bootstrap_init, local_balance, request_latest_block, request_specific_block, NumPeersConsensus,
+  bootstrap_init, local_balance, local_utxo_balance, request_latest_block, utxo_tx,
+  NumPeersConsensus,
 };
+use crate::peer_manager::{Behaviour, PeerManager};
 use crate::protocol::{BchainRequest, BchainResponse, Frame};
-use crate::swarm::{create_swarm, BchainSwarm};
+use crate::swarm::{create_swarm, BchainEvent, BchainSwarm, SyncEvent};
+use crate::sync::{RequestId as SyncRequestId, SyncRequest, SyncResponse, MAX_HEADERS};
 use async_std::channel::{self, Receiver, Sender};
 use async_std::prelude::FutureExt;
+use async_std::stream::interval;
 use async_std::sync::{Mutex, RwLock};
 use async_std::{io, task};
-use bchain_db::database::{create_db, Db};
+use bchain_db::raw_ban::RawBan;
+use bchain_db::raw_peer::RawPeer;
+use bchain_db::storage_error::StorageError;
+use bchain_db::store::{create_store, unsupported, Store};
 use bchain_domain::address::Address;
-use bchain_domain::block::Block;
-use bchain_domain::tx::Tx;
+use bchain_domain::block::{Block, BlockHeader};
+use bchain_domain::message::verify_message;
+use bchain_domain::multisig::MultisigPolicy;
+use bchain_domain::public_key::PublicKey;
+use bchain_domain::signature::Signature;
+use bchain_domain::tx::{Tx, TxOutput};
+use bchain_domain::tx_file::{PartiallySignedTx, TxFile, UnsignedTx};
 use bchain_domain::tx_pool::TxPool;
+use bchain_domain::utxo::Ledger;
 use bchain_domain::{cli::Cli, wallet::Wallet};
 use bchain_util::group::peer_majority;
+use bchain_util::hash_digest::{HashDigest, Hashable};
 use bchain_util::result::AppResult;
 use bchain_util::short::ShortDisplay;
+use chrono::{NaiveDateTime, Utc};
 use futures::{prelude::*, select};
-use libp2p::gossipsub::{error::GossipsubHandlerError, GossipsubEvent, IdentTopic as Topic};
-use libp2p::{identity, swarm::SwarmEvent};
+use libp2p::gossipsub::{GossipsubEvent, IdentTopic as Topic, MessageAcceptance, MessageId};
+use libp2p::request_response::{
+  OutboundFailure, RequestId, RequestResponseMessage, ResponseChannel,
+};
+use libp2p::{identity, swarm::SwarmEvent, PeerId};
 use log::{error, info, warn};
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
 
 type Channel<T> = (Sender<T>, Receiver<T>);
 
+const BAN_EXPIRY_CHECK: Duration = Duration::from_secs(60);
+const PEER_GC: Duration = Duration::from_secs(60 * 60);
+const PEER_CHECK: Duration = Duration::from_secs(15);
+/// peers that haven't sent a hello by then are disconnected
+const HELLO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// stored peers last seen before this are removed
+fn stale_before(max_age_days: i64) -> NaiveDateTime {
+  (Utc::now() - chrono::Duration::days(max_age_days)).naive_utc()
+}
+
+/// coinbase txs are only valid inside a block, legacy txs only in old local blocks
+fn validate_tx(tx: &Tx) -> Option<Behaviour> {
+  let valid = !tx.is_coinbase() && !tx.is_legacy() && tx.verify_signature().is_ok();
+  (!valid).then_some(Behaviour::InvalidTx)
+}
+
+fn validate_block(block: &Block) -> Option<Behaviour> {
+  let valid = block
+    .verify_no_legacy_txs()
+    .and_then(|_| block.verify_txs());
+  valid.err().map(|_| Behaviour::InvalidBlock)
+}
+
+/// gossiped blocks and txs have to be correctly signed to be passed on
+fn validate_frame(frame: &Frame) -> Option<Behaviour> {
+  match frame {
+    Frame::BchainRequest(BchainRequest::SubmitTx(tx)) => validate_tx(tx),
+    Frame::BchainRequest(BchainRequest::SubmitBlock(block)) => validate_block(block),
+    _ => None,
+  }
+}
+
+fn validate_data(data: &InvData) -> Option<Behaviour> {
+  match data {
+    InvData::Tx(tx) => validate_tx(tx),
+    InvData::Block(block) => validate_block(block),
+    InvData::CompactBlock(_) => None,
+  }
+}
+
+/// rebuilt blocks go through the same checks as full ones, if the rebuild
+/// failed the full block is asked for instead
+async fn complete_block(
+  peer: PeerId,
+  partial: PartialBlock,
+  rebuilt_blocks: Sender<(PeerId, Block)>,
+  sync_requests: Sender<TargetedRequest>,
+) -> AppResult<()> {
+  match partial.block() {
+    Ok(block) => rebuilt_blocks.send((peer, block)).await?,
+    Err(e) => {
+      warn!("{}, fetching the full block", e);
+      let request = SyncRequest::GetData(vec![InvItem::Block(partial.hash())]);
+      sync_requests.send((request, Some(peer))).await?;
+    }
+  }
+  Ok(())
+}
+
 #[allow(dead_code)]
 pub struct Node {
   cli: Cli,
   topic: Topic,
-  db: Arc<Mutex<Db>>,
+  db: Arc<Mutex<Store>>,
   wallet: Arc<RwLock<Wallet>>,
   tx_pool: Arc<Mutex<TxPool>>,
   swarm: BchainSwarm,
+  serving_ranges: Arc<RwLock<HashMap<PeerId, (i64, i64)>>>,
+  /// outgoing sync requests, so responses are only taken from the peer asked
+  pending_sync: HashMap<RequestId, PeerId>,
+  sync_progress: Arc<RwLock<Option<SyncProgress>>>,
+  /// gossip and sync requests are only taken from peers that completed the handshake
+  handshakes: Handshakes,
+  peer_manager: PeerManager,
+  discovery: Discovery,
+  /// seen blocks and txs, the ones we announced are served from here
+  inventory: Inventory,
+  /// compact blocks waiting for their missing txs
+  partial_blocks: Arc<Mutex<PartialBlocks>>,
+  /// outgoing GetBlockTxs, the full block is fetched instead if one fails
+  block_txs_requests: HashMap<RequestId, HashDigest>,
 
-  network_latest: Channel<Block>,
-  network_blocks: Channel<Block>,
+  network_latest: Channel<(PeerId, SyncRequestId, Option<Block>)>,
+  network_headers: Channel<(PeerId, SyncRequestId, Vec<BlockHeader>)>,
+  network_blocks: Channel<(PeerId, Block)>,
 
   proposed_blocks: Channel<Block>,
   proposed_tx: Channel<Tx>,
 
   network_responses: Channel<BchainResponse>,
   network_requests: Channel<BchainRequest>,
+
+  sync_requests: Channel<TargetedRequest>,
+  sync_responses: Channel<(ResponseChannel<SyncResponse>, SyncResponse)>,
+  disconnects: Channel<PeerId>,
+  peer_reports: Channel<(PeerId, Behaviour)>,
+  rebuilt_blocks: Channel<(PeerId, Block)>,
+  greetings: Channel<(PeerId, Hello)>,
 }
 
 impl Node {
@@ -52,8 +159,42 @@ impl Node {
     let wallet = Wallet::from_file(&cli.wallet).await?;
     let mut rsa_pkcs8 = wallet.to_pkcs8_der()?;
     let local_peer_key = identity::Keypair::rsa_from_pkcs8(&mut rsa_pkcs8)?;
-    let db = create_db(&cli.database)?;
-    let swarm = create_swarm(&local_peer_key, &topic).await?;
+    let mut db = create_store(cli)?;
+    let bans = match db.ban_list() {
+      Some(bans) => bans.bans().unwrap_or_else(|e| {
+        warn!("{}, bans are kept in memory only", e);
+        vec![]
+      }),
+      None => {
+        warn!("Bans need the sqlite store, they are kept in memory only");
+        vec![]
+      }
+    };
+    let mut peer_manager = PeerManager::new(bans);
+    let mut discovery = Discovery::new(cli.target_peers);
+    let peers = match db.peer_book() {
+      Some(book) => book
+        .remove_stale_peers(stale_before(cli.peer_max_age))
+        .and_then(|_| book.peers())
+        .unwrap_or_else(|e| {
+          warn!("{}, peers are kept in memory only", e);
+          vec![]
+        }),
+      None => {
+        warn!("Peer address book needs the sqlite store, peers are kept in memory only");
+        vec![]
+      }
+    };
+    for peer in peers {
+      match (PeerId::from_str(&peer.peer_id), peer.address.parse()) {
+        (Ok(peer_id), Ok(address)) => {
+          discovery.add_address(peer_id, address);
+          peer_manager.restore_score(peer_id, peer.score);
+        }
+        _ => warn!("Skipping stored peer {} at {}", peer.peer_id, peer.address),
+      }
+    }
+    let swarm = create_swarm(&local_peer_key, &topic, cli).await?;
     let tx_pool = TxPool::default();
     let cli = cli.clone();
     Ok(Node {
@@ -63,19 +204,48 @@ impl Node {
       db: Arc::new(Mutex::new(db)),
       wallet: Arc::new(RwLock::new(wallet)),
       tx_pool: Arc::new(Mutex::new(tx_pool)),
+      serving_ranges: Arc::default(),
+      pending_sync: HashMap::new(),
+      sync_progress: Arc::default(),
+      handshakes: Handshakes::default(),
+      peer_manager,
+      discovery,
+      inventory: Inventory::default(),
+      partial_blocks: Arc::default(),
+      block_txs_requests: HashMap::new(),
       network_latest: channel::unbounded(),
+      network_headers: channel::unbounded(),
       network_blocks: channel::unbounded(),
       proposed_blocks: channel::unbounded(),
       proposed_tx: channel::unbounded(),
       network_responses: channel::unbounded(),
       network_requests: channel::unbounded(),
+      sync_requests: channel::unbounded(),
+      sync_responses: channel::unbounded(),
+      disconnects: channel::unbounded(),
+      peer_reports: channel::unbounded(),
+      rebuilt_blocks: channel::unbounded(),
+      greetings: channel::unbounded(),
     })
   }
 
   pub async fn run(&mut self) -> AppResult<()> {
-    self.swarm.listen_on(self.cli.listen.parse()?)?;
+    for address in &self.cli.listen {
+      self.swarm.listen_on(address.parse()?)?;
+    }
+
+    let banned: Vec<PeerId> = self
+      .peer_manager
+      .bans()
+      .map(|(peer_id, _)| *peer_id)
+      .collect();
+    for peer_id in banned {
+      self.swarm.ban_peer_id(peer_id);
+    }
 
     self.dial_peers(self.cli.peers.clone())?;
+    self.bootstrap_kademlia();
+    self.maintain_peers();
 
     let mut bootstrap = Box::pin(
       async {}
@@ -87,6 +257,15 @@ impl Node {
 
     let (_, mut network_responses) = self.network_responses.clone();
     let (_, mut network_requests) = self.network_requests.clone();
+    let (_, mut sync_requests) = self.sync_requests.clone();
+    let (_, mut sync_responses) = self.sync_responses.clone();
+    let (_, mut disconnects) = self.disconnects.clone();
+    let (_, mut peer_reports) = self.peer_reports.clone();
+    let (_, mut rebuilt_blocks) = self.rebuilt_blocks.clone();
+    let (_, mut greetings) = self.greetings.clone();
+    let mut ban_expiry = interval(BAN_EXPIRY_CHECK).fuse();
+    let mut peer_gc = interval(PEER_GC).fuse();
+    let mut peer_check = interval(PEER_CHECK).fuse();
 
     let _mining_task = {
       let wallet = self.wallet.clone();
@@ -108,7 +287,7 @@ impl Node {
         )
         .await?;
         Ok(()) as AppResult<()>
-      });
+      })
     };
 
     loop {
@@ -120,8 +299,37 @@ impl Node {
         request = network_requests.select_next_some().fuse() => {
             self.publish_request(&request)?;
         },
+        (request, peer) = sync_requests.select_next_some().fuse() => {
+            self.send_sync_request(&request, peer);
+        },
+        (channel, response) = sync_responses.select_next_some().fuse() => {
+            self.send_sync_response(channel, response);
+        },
+        peer_id = disconnects.select_next_some().fuse() => {
+            self.discovery.forget(&peer_id);
+            if self.swarm.disconnect_peer_id(peer_id).is_err() {
+              warn!("Peer {} already disconnected", peer_id.short_display());
+            }
+        },
+        (peer_id, block) = rebuilt_blocks.select_next_some().fuse() => {
+            self.handle_data(peer_id, vec![InvData::Block(block)]);
+        },
+        (peer_id, hello) = greetings.select_next_some().fuse() => {
+            if self.handshakes.completed(peer_id, hello.clone()) {
+              info!("Handshake with {}: {}", peer_id.short_display(), hello);
+            }
+        },
+        (peer_id, behaviour) = peer_reports.select_next_some().fuse() => {
+            self.report_peer(peer_id, behaviour);
+        },
+        _ = ban_expiry.select_next_some() => self.lift_expired_bans(),
+        _ = peer_gc.select_next_some() => self.remove_stale_peers(),
+        _ = peer_check.select_next_some() => {
+            self.drop_silent_peers();
+            self.maintain_peers();
+        },
         swarm_event = self.swarm.select_next_some().fuse() => {
-            self.handle_swarm_event(&swarm_event)?;
+            self.handle_swarm_event(swarm_event)?;
         },
         cmd_line = cmd_lines.select_next_some().fuse() => {
           if let Ok(line) = cmd_line {
@@ -149,35 +357,179 @@ impl Node {
     Ok(())
   }
 
+  /// --peers with a peer id are the first entries of the kademlia routing table
+  fn bootstrap_kademlia(&mut self) {
+    let kademlia = match self.swarm.behaviour_mut().kademlia.as_mut() {
+      Some(kademlia) => kademlia,
+      None => return,
+    };
+    for peer in &self.cli.peers {
+      let address = match peer.parse() {
+        Ok(address) => address,
+        Err(_) => continue,
+      };
+      if let Some(peer_id) = peer_id_of(&address) {
+        kademlia.add_address(&peer_id, address);
+      }
+    }
+    if kademlia.bootstrap().is_err() {
+      warn!("No bootstrap nodes for kademlia, pass --peers ending in /p2p/<peer id>");
+    }
+  }
+
+  /// below --target-peers kademlia is also asked for more peers
+  fn maintain_peers(&mut self) {
+    if self.discovery.outbound() >= self.discovery.target() {
+      return;
+    }
+    self.dial_candidates();
+    let local_peer_id = *self.swarm.local_peer_id();
+    if let Some(kademlia) = self.swarm.behaviour_mut().kademlia.as_mut() {
+      kademlia.get_closest_peers(local_peer_id);
+    }
+  }
+
+  /// peers we could dial are remembered across restarts
+  fn save_peer(&self, peer_id: PeerId) {
+    let address = match self.discovery.address(&peer_id) {
+      Some(address) => address.to_string(),
+      None => return,
+    };
+    let peer = RawPeer {
+      peer_id: peer_id.to_string(),
+      address,
+      last_seen: Utc::now().naive_utc(),
+      score: self.peer_manager.score(&peer_id),
+    };
+    let db = self.db.clone();
+    task::spawn(async move {
+      if let Some(Err(e)) = db
+        .lock()
+        .await
+        .peer_book()
+        .map(|book| book.save_peer(&peer))
+      {
+        warn!("{}, peer is kept in memory only", e);
+      }
+    });
+  }
+
+  fn remove_stale_peers(&self) {
+    let before = stale_before(self.cli.peer_max_age);
+    let db = self.db.clone();
+    task::spawn(async move {
+      match db
+        .lock()
+        .await
+        .peer_book()
+        .map(|book| book.remove_stale_peers(before))
+      {
+        None | Some(Ok(0)) => (),
+        Some(Ok(removed)) => info!("Removed {} stale peers", removed),
+        Some(Err(e)) => warn!("{}", e),
+      }
+    });
+  }
+
+  fn dial_candidates(&mut self) {
+    let swarm = &self.swarm;
+    let peer_manager = &self.peer_manager;
+    let candidates = self
+      .discovery
+      .candidates(|peer_id| swarm.is_connected(peer_id) || peer_manager.is_banned(peer_id));
+    for (peer_id, address) in candidates {
+      match self.swarm.dial_addr(address) {
+        Ok(_) => info!("Dialing {}", peer_id.short_display()),
+        Err(e) => warn!("Dialing {} failed: {:?}", peer_id.short_display(), e),
+      }
+    }
+  }
+
   fn handle_user_command(&mut self, cmd: &UserCommand) -> AppResult<()> {
     match cmd {
-      UserCommand::Peers => self.display_peers(),
+      UserCommand::Peers(verbose) => self.display_peers(*verbose),
+      UserCommand::Ban(peer_id, minutes) => self.ban_peer(peer_id, *minutes),
+      UserCommand::Unban(peer_id) => self.unban_peer(peer_id),
       UserCommand::Blocks => self.display_blocks(),
       UserCommand::Bootstrap => self.bootstrap()?,
+      UserCommand::Sync => self.display_sync_progress(),
       UserCommand::Dial(peers) => self.dial_peers(peers.clone())?,
       UserCommand::Msg(msg) => self.publish_user_message(msg),
       UserCommand::Balance(address) => self.print_balance(address),
-      UserCommand::Tx(address, amount) => self.submit_tx(address, *amount),
+      UserCommand::Tx(outputs) => self.submit_tx(outputs),
+      UserCommand::TxBuild(address, amount, file) => self.build_tx(address, *amount, file),
+      UserCommand::TxSubmit(file) => self.submit_tx_file(file),
+      UserCommand::Multisig(threshold, keys) => self.create_multisig(*threshold, keys),
+      UserCommand::TxBuildMultisig(policy, address, amount, file) => {
+        self.build_multisig_tx(policy, address, *amount, file)
+      }
+      UserCommand::Watch(watch) => self.watch(watch),
+      UserCommand::CheckAccounts => self.check_accounts(),
+      UserCommand::TxInfo(hash) => self.tx_info(hash),
+      UserCommand::History(address, page) => self.history(address, *page),
+      UserCommand::SignMessage(text) => self.sign_message(text),
+      UserCommand::VerifyMessage(address, signature, text) => {
+        self.verify_message(address, signature, text)
+      }
       UserCommand::Help(help_text) => info!("{}", help_text),
       UserCommand::Unrecognized => warn!("Unrecognized user input"),
     }
     Ok(())
   }
 
-  fn handle_swarm_event(
-    &mut self,
-    event: &SwarmEvent<GossipsubEvent, GossipsubHandlerError>,
-  ) -> AppResult<()> {
+  fn handle_swarm_event<E>(&mut self, event: SwarmEvent<BchainEvent, E>) -> AppResult<()> {
     match event {
-      SwarmEvent::Behaviour(GossipsubEvent::Message { message, .. }) => {
-        match serde_json::from_slice(&message.data)? {
-          Frame::BchainRequest(request) => self.handle_bchain_request(request),
-          Frame::BchainResponse(response) => self.handle_bchain_response(response),
-          _ => warn!("Unrecognized bchain event"),
+      SwarmEvent::Behaviour(BchainEvent::Gossipsub(GossipsubEvent::Message {
+        propagation_source,
+        message_id,
+        message,
+      })) => self.handle_gossip_message(propagation_source, message_id, &message.data),
+      SwarmEvent::Behaviour(BchainEvent::Sync(event)) => self.handle_sync_event(event),
+      SwarmEvent::Behaviour(BchainEvent::Mdns(event)) => {
+        let found = self.discovery.on_mdns_event(event);
+        if found {
+          self.dial_candidates();
+        }
+      }
+      SwarmEvent::Behaviour(BchainEvent::Kademlia(event)) => {
+        let found = self.discovery.on_kademlia_event(&event);
+        if found {
+          self.dial_candidates();
         }
       }
-      SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+      SwarmEvent::ConnectionEstablished {
+        peer_id,
+        endpoint,
+        num_established,
+      } => {
         info!("Peer connected: {}", peer_id.short_display());
+        self.discovery.connected(peer_id, &endpoint);
+        if self.discovery.is_outbound(&peer_id) {
+          self.save_peer(peer_id);
+        }
+        if num_established.get() == 1 {
+          self.handshakes.connected(peer_id);
+          self.send_hello(peer_id);
+        }
+      }
+      SwarmEvent::ConnectionClosed {
+        peer_id,
+        num_established: 0,
+        ..
+      } => {
+        if self.discovery.is_outbound(&peer_id) {
+          self.save_peer(peer_id);
+        }
+        self.discovery.disconnected(&peer_id);
+        self.handshakes.disconnected(&peer_id);
+        let serving_ranges = self.serving_ranges.clone();
+        task::spawn(async move {
+          serving_ranges.write().await.remove(&peer_id);
+        });
+      }
+      SwarmEvent::UnreachableAddr { address, .. }
+      | SwarmEvent::UnknownPeerUnreachableAddr { address, .. } => {
+        self.discovery.dial_failed(&address)
       }
       SwarmEvent::NewListenAddr { address, .. } => info!("Listening on {:?}", address),
       _ => (),
@@ -185,34 +537,289 @@ impl Node {
     Ok(())
   }
 
+  /// malformed messages aren't forwarded and lower the sender's gossip score,
+  /// messages from peers without a handshake are ignored
+  fn handle_gossip_message(&mut self, source: PeerId, message_id: MessageId, data: &[u8]) {
+    if !self.handshakes.is_done(&source) {
+      warn!(
+        "Ignoring gossip from {} before handshake",
+        source.short_display()
+      );
+      let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+      let ignore = MessageAcceptance::Ignore;
+      if let Err(e) = gossipsub.report_message_validation_result(&message_id, &source, ignore) {
+        error!("{:?}", e);
+      }
+      return;
+    }
+    let frame = decode::<Frame>(data);
+    let misbehaviour = match &frame {
+      Ok(frame) => validate_frame(frame),
+      Err(e) => {
+        warn!("Malformed message from {}: {}", source.short_display(), e);
+        Some(Behaviour::Malformed)
+      }
+    };
+    let acceptance = match (&frame, misbehaviour) {
+      (Ok(frame), None) => self.acceptance(frame),
+      (_, Some(behaviour)) => {
+        self.report_peer(source, behaviour);
+        MessageAcceptance::Reject
+      }
+      (Err(_), None) => MessageAcceptance::Reject,
+    };
+    let seen = matches!(acceptance, MessageAcceptance::Ignore);
+    let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+    if let Err(e) = gossipsub.report_message_validation_result(&message_id, &source, acceptance) {
+      error!("{:?}", e);
+    }
+    match (frame, misbehaviour) {
+      (Ok(_), Some(behaviour)) => warn!("Dropping {} from {}", behaviour, source.short_display()),
+      (Ok(Frame::BchainRequest(BchainRequest::Inv(items))), None) => {
+        self.handle_inv(source, &items)
+      }
+      (Ok(_), None) if seen => (),
+      (Ok(Frame::BchainRequest(request)), None) => self.handle_bchain_request(request),
+      (Ok(Frame::BchainResponse(response)), None) => self.handle_bchain_response(response),
+      (Ok(Frame::Unrecognized), None) => warn!("Unrecognized bchain event"),
+      (Err(_), _) => (),
+    }
+  }
+
+  /// announcements aren't forwarded, peers announce again once they have the data,
+  /// full blocks and txs are only forwarded the first time
+  fn acceptance(&mut self, frame: &Frame) -> MessageAcceptance {
+    let item = match frame {
+      Frame::BchainRequest(BchainRequest::Inv(_)) => return MessageAcceptance::Ignore,
+      Frame::BchainRequest(BchainRequest::SubmitTx(tx)) => InvItem::Tx(tx.hash_digest()),
+      Frame::BchainRequest(BchainRequest::SubmitBlock(block)) => {
+        InvItem::Block(block.hash_digest())
+      }
+      _ => return MessageAcceptance::Accept,
+    };
+    match self.inventory.see(item) {
+      true => MessageAcceptance::Accept,
+      false => MessageAcceptance::Ignore,
+    }
+  }
+
+  /// missing items are fetched from the peer that announced them
+  fn handle_inv(&mut self, source: PeerId, items: &[InvItem]) {
+    let missing = self.inventory.missing(source, items);
+    self.fetch(source, missing);
+  }
+
+  /// blocks are asked for in compact form
+  fn fetch(&mut self, peer: PeerId, items: Vec<InvItem>) {
+    let items: Vec<InvItem> = items
+      .into_iter()
+      .map(|item| match item {
+        InvItem::Block(hash) => InvItem::CompactBlock(hash),
+        item => item,
+      })
+      .collect();
+    if !items.is_empty() {
+      self.send_sync_request(&SyncRequest::GetData(items), Some(peer));
+    }
+  }
+
+  /// items `peer` didn't deliver are fetched from another peer that announced them
+  fn refetch(&mut self, peer: PeerId, items: Vec<InvItem>) {
+    let mut retries: HashMap<PeerId, Vec<InvItem>> = HashMap::new();
+    for item in items {
+      match self.inventory.refetch(item, &peer) {
+        Some(other) => retries.entry(other).or_default().push(item),
+        None => info!("{} wasn't delivered, no other peer announced it", item),
+      }
+    }
+    for (other, items) in retries {
+      self.fetch(other, items);
+    }
+  }
+
+  /// new blocks and txs are processed and announced to our peers in turn
+  fn handle_data(&mut self, peer: PeerId, data: Vec<InvData>) {
+    let mut announce = vec![];
+    for data in data {
+      let item = data.item().announced();
+      if self.inventory.is_seen(&item) {
+        continue;
+      }
+      if !self.inventory.is_in_flight(&item) {
+        warn!("Unsolicited {} from {}", item, peer.short_display());
+        continue;
+      }
+      if let InvData::CompactBlock(compact) = data {
+        self.rebuild_block(peer, compact);
+        continue;
+      }
+      if let Some(behaviour) = validate_data(&data) {
+        warn!("Dropping {} from {}", behaviour, peer.short_display());
+        self.report_peer(peer, behaviour);
+        self.inventory.see(item);
+        continue;
+      }
+      if !self.inventory.insert(data.clone()) {
+        continue;
+      }
+      self.report_peer(peer, Behaviour::Useful);
+      match data {
+        InvData::Tx(tx) => self.handle_proposed_tx(tx),
+        InvData::Block(block) => self.handle_proposed_block(block),
+        InvData::CompactBlock(_) => (),
+      }
+      announce.push(item);
+    }
+    if !announce.is_empty() {
+      let inv = Frame::BchainRequest(BchainRequest::Inv(announce));
+      if let Err(e) = self.publish_to_swarm(&inv) {
+        error!("{}", e);
+      }
+    }
+  }
+
+  /// txs missing from the pool are asked from the peer that sent the compact block
+  fn rebuild_block(&mut self, peer: PeerId, compact: CompactBlock) {
+    let tx_pool = self.tx_pool.clone();
+    let partial_blocks = self.partial_blocks.clone();
+    let (rebuilt_blocks, _) = self.rebuilt_blocks.clone();
+    let (sync_requests, _) = self.sync_requests.clone();
+    task::spawn(async move {
+      let partial = PartialBlock::new(compact, tx_pool.lock().await.txs());
+      let missing = partial.missing();
+      if missing.is_empty() {
+        return complete_block(peer, partial, rebuilt_blocks, sync_requests).await;
+      }
+      let hash = partial.hash();
+      info!("Compact block {} is missing {} txs", hash, missing.len());
+      for dropped in partial_blocks.lock().await.insert(partial) {
+        warn!("Gave up rebuilding compact block {}", dropped);
+      }
+      let request = SyncRequest::GetBlockTxs(hash, missing);
+      sync_requests.send((request, Some(peer))).await?;
+      AppResult::Ok(())
+    });
+  }
+
+  fn handle_block_txs(&mut self, peer: PeerId, hash: HashDigest, txs: Vec<Tx>) {
+    let partial_blocks = self.partial_blocks.clone();
+    let (rebuilt_blocks, _) = self.rebuilt_blocks.clone();
+    let (sync_requests, _) = self.sync_requests.clone();
+    task::spawn(async move {
+      let mut partial = match partial_blocks.lock().await.remove(&hash) {
+        Some(partial) => partial,
+        None => {
+          warn!("Unsolicited txs of block {}", hash);
+          return Ok(());
+        }
+      };
+      if let Err(e) = partial.fill(txs) {
+        warn!("{} for block {}", e, hash);
+      }
+      complete_block(peer, partial, rebuilt_blocks, sync_requests).await
+    });
+  }
+
+  /// a compact block whose txs couldn't be fetched is fetched in full
+  fn abandon_partial_block(&mut self, peer: PeerId, hash: HashDigest) {
+    let partial_blocks = self.partial_blocks.clone();
+    let (sync_requests, _) = self.sync_requests.clone();
+    task::spawn(async move {
+      if partial_blocks.lock().await.remove(&hash).is_some() {
+        warn!(
+          "Txs of block {} weren't delivered, fetching the full block",
+          hash
+        );
+        let request = SyncRequest::GetData(vec![InvItem::Block(hash)]);
+        sync_requests.send((request, Some(peer))).await?;
+      }
+      AppResult::Ok(())
+    });
+  }
+
+  fn report_peer(&mut self, peer_id: PeerId, behaviour: Behaviour) {
+    if let Some(ban) = self.peer_manager.report(&peer_id, behaviour) {
+      warn!("Banning {}: {}", peer_id.short_display(), ban.reason);
+      self.apply_ban(peer_id, ban);
+    }
+    let score = self.peer_manager.score(&peer_id);
+    let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+    gossipsub.set_application_score(&peer_id, score);
+  }
+
+  fn apply_ban(&mut self, peer_id: PeerId, ban: RawBan) {
+    self.swarm.ban_peer_id(peer_id);
+    let db = self.db.clone();
+    task::spawn(async move {
+      if let Some(Err(e)) = db.lock().await.ban_list().map(|bans| bans.ban(&ban)) {
+        warn!("{}, ban is kept in memory only", e);
+      }
+    });
+  }
+
+  fn ban_peer(&mut self, peer_id: &str, minutes: Option<i64>) {
+    match PeerId::from_str(peer_id) {
+      Ok(peer_id) => {
+        let ban = self.peer_manager.ban(&peer_id, minutes, "banned by user");
+        info!("Banned {}", peer_id.short_display());
+        self.apply_ban(peer_id, ban);
+      }
+      Err(_) => warn!("Invalid peer id {}", peer_id),
+    }
+  }
+
+  fn unban_peer(&mut self, peer_id: &str) {
+    let peer_id = match PeerId::from_str(peer_id) {
+      Ok(peer_id) => peer_id,
+      Err(_) => {
+        warn!("Invalid peer id {}", peer_id);
+        return;
+      }
+    };
+    if !self.peer_manager.unban(&peer_id) {
+      warn!("{} is not banned", peer_id.short_display());
+      return;
+    }
+    info!("Unbanned {}", peer_id.short_display());
+    self.lift_ban(peer_id);
+  }
+
+  fn lift_ban(&mut self, peer_id: PeerId) {
+    self.swarm.unban_peer_id(peer_id);
+    self
+      .swarm
+      .behaviour_mut()
+      .gossipsub
+      .set_application_score(&peer_id, 0.0);
+    let db = self.db.clone();
+    task::spawn(async move {
+      let peer_id = peer_id.to_string();
+      if let Some(Err(e)) = db.lock().await.ban_list().map(|bans| bans.unban(&peer_id)) {
+        warn!("{}", e);
+      }
+    });
+  }
+
+  fn lift_expired_bans(&mut self) {
+    for peer_id in self.peer_manager.expired() {
+      info!("Ban of {} expired", peer_id.short_display());
+      self.lift_ban(peer_id);
+    }
+  }
+
   fn handle_bchain_request(&mut self, request: BchainRequest) {
-    info!("Incoming request: {}", request.to_string());
+    info!("Incoming request: {}", request);
     match request {
-      BchainRequest::AskLatest => self.respond_latest_block(),
-      BchainRequest::AskBlock(id) => self.respond_block(id),
       BchainRequest::SubmitTx(tx) => self.handle_proposed_tx(tx),
       BchainRequest::SubmitBlock(block) => self.handle_proposed_block(block),
       BchainRequest::Msg(msg) => info!("{}", msg),
+      BchainRequest::Inv(_) => (),
     }
   }
 
   fn handle_bchain_response(&mut self, response: BchainResponse) {
-    info!("Incoming response: {}", response.to_string());
+    info!("Incoming response: {}", response);
     match response {
-      BchainResponse::Latest(block) => {
-        let (network_latest_sender, _) = self.network_latest.clone();
-        task::spawn(async move {
-          network_latest_sender.send(block).await?;
-          Ok(()) as AppResult<()>
-        });
-      }
-      BchainResponse::Block(block) => {
-        let (network_block_sender, _) = self.network_blocks.clone();
-        task::spawn(async move {
-          network_block_sender.send(block).await?;
-          Ok(()) as AppResult<()>
-        });
-      }
       BchainResponse::AcceptBlock(block) => {
         task::spawn(async move { block });
       }
@@ -224,22 +831,41 @@ impl Node {
   }
 
   fn publish_response(&mut self, response: &BchainResponse) -> AppResult<()> {
-    info!("Outgoing response: {}", response.to_string());
+    info!("Outgoing response: {}", response);
     self.publish_to_swarm(&Frame::BchainResponse(response.clone()))?;
     Ok(())
   }
 
+  /// blocks and txs are kept to answer GetData and only announced by hash
   fn publish_request(&mut self, request: &BchainRequest) -> AppResult<()> {
-    info!("Outgoing request: {}", request.to_string());
-    self.publish_to_swarm(&Frame::BchainRequest(request.clone()))?;
+    info!("Outgoing request: {}", request);
+    let data = match request {
+      BchainRequest::SubmitTx(tx) => {
+        // our own txs are pooled too, so compact blocks holding them rebuild
+        self.handle_proposed_tx(tx.clone());
+        Some(InvData::Tx(tx.clone()))
+      }
+      BchainRequest::SubmitBlock(block) => Some(InvData::Block(block.clone())),
+      _ => None,
+    };
+    let request = match data {
+      Some(data) => {
+        let item = data.item();
+        self.inventory.insert(data);
+        BchainRequest::Inv(vec![item])
+      }
+      None => request.clone(),
+    };
+    self.publish_to_swarm(&Frame::BchainRequest(request))?;
     Ok(())
   }
 
   fn publish_to_swarm(&mut self, frame: &Frame) -> AppResult<()> {
-    let bytes = serde_json::to_vec(frame)?;
+    let bytes = encode(frame, self.cli.compression)?;
     let publish_result = self
       .swarm
       .behaviour_mut()
+      .gossipsub
       .publish(self.topic.clone(), bytes);
 
     if let Err(e) = publish_result {
@@ -249,12 +875,56 @@ impl Node {
   }
 
   fn num_peers_consensus(&self) -> NumPeersConsensus {
-    let num_peers = self.swarm.behaviour().all_peers().count();
+    let num_peers = self.swarm.behaviour().gossipsub.all_peers().count();
     (num_peers, peer_majority(num_peers))
   }
 
-  fn display_peers(&self) {
-    info!("Peers: {}", self.num_peers_consensus().0);
+  fn peer_ids(&self) -> Vec<PeerId> {
+    let peers = self.swarm.behaviour().gossipsub.all_peers();
+    peers.map(|(peer, _)| *peer).collect()
+  }
+
+  fn display_peers(&self, verbose: bool) {
+    info!(
+      "Peers: {}, outbound {}/{}",
+      self.num_peers_consensus().0,
+      self.discovery.outbound(),
+      self.discovery.target()
+    );
+    if !verbose {
+      return;
+    }
+    let peers: Vec<(PeerId, String)> = self
+      .peer_ids()
+      .into_iter()
+      .map(|peer_id| (peer_id, self.peer_manager.describe(&peer_id)))
+      .collect();
+    for (peer_id, ban) in self.peer_manager.bans() {
+      let until = ban.until.map(|u| u.to_string());
+      let until = until.unwrap_or_else(|| "forever".into());
+      info!(
+        "Banned {} until {}: {}",
+        peer_id.short_display(),
+        until,
+        ban.reason
+      );
+    }
+    for (peer_id, description) in peers {
+      match self.handshakes.get(&peer_id) {
+        Some(hello) => info!("{} {}", description, hello),
+        None => info!("{} no handshake", description),
+      }
+    }
+  }
+
+  fn display_sync_progress(&self) {
+    let sync_progress = self.sync_progress.clone();
+    task::spawn(async move {
+      match &*sync_progress.read().await {
+        Some(progress) => info!("{}", progress),
+        None => info!("No block download running"),
+      }
+    });
   }
 
   fn display_blocks(&self) {
@@ -268,32 +938,241 @@ impl Node {
     });
   }
 
-  fn respond_latest_block(&mut self) {
-    let db = self.db.clone();
-    let (send_network_response, _) = self.network_responses.clone();
+  fn handle_sync_event(&mut self, event: SyncEvent) {
+    match event {
+      SyncEvent::Message { peer, message } => match message {
+        RequestResponseMessage::Request {
+          request, channel, ..
+        } => {
+          match &request {
+            SyncRequest::Hello(hello) => self.check_hello(peer, hello.clone()),
+            _ if !self.handshakes.is_done(&peer) => {
+              warn!(
+                "Ignoring sync request from {} before handshake",
+                peer.short_display()
+              );
+              return;
+            }
+            _ => (),
+          }
+          self.respond_sync(request, channel)
+        }
+        RequestResponseMessage::Response {
+          request_id,
+          response,
+        } => match self.pending_sync.remove(&request_id) {
+          Some(asked) if asked == peer => self.handle_sync_response(peer, request_id, response),
+          _ => warn!("Unsolicited sync response from {}", peer.short_display()),
+        },
+      },
+      SyncEvent::OutboundFailure {
+        peer,
+        request_id,
+        error,
+      } => {
+        self.pending_sync.remove(&request_id);
+        let undelivered = self.inventory.unanswered(&request_id, &[]);
+        self.refetch(peer, undelivered);
+        if let Some(hash) = self.block_txs_requests.remove(&request_id) {
+          self.abandon_partial_block(peer, hash);
+        }
+        if let OutboundFailure::Timeout = error {
+          self.report_peer(peer, Behaviour::Timeout);
+        }
+        warn!(
+          "Sync request to {} failed: {:?}",
+          peer.short_display(),
+          error
+        );
+      }
+      SyncEvent::InboundFailure { peer, error, .. } => {
+        warn!(
+          "Sync request from {} failed: {:?}",
+          peer.short_display(),
+          error
+        );
+      }
+      SyncEvent::ResponseSent { .. } => (),
+    }
+  }
+
+  fn handle_sync_response(&mut self, peer: PeerId, request_id: RequestId, response: SyncResponse) {
+    info!("Sync response from {}: {}", peer.short_display(), response);
+    match response {
+      SyncResponse::Hello(hello) => self.check_hello(peer, hello),
+      SyncResponse::Latest { id, block, serving } => {
+        if let Some((first, latest)) = serving {
+          self.record_serving_range(peer, first, latest);
+        }
+        let (network_latest_sender, _) = self.network_latest.clone();
+        task::spawn(async move {
+          network_latest_sender.send((peer, id, block)).await?;
+          Ok(()) as AppResult<()>
+        });
+      }
+      SyncResponse::Block(Some(block)) => {
+        let (network_block_sender, _) = self.network_blocks.clone();
+        task::spawn(async move {
+          network_block_sender.send((peer, block)).await?;
+          Ok(()) as AppResult<()>
+        });
+      }
+      SyncResponse::Block(None) => (),
+      SyncResponse::Headers(id, headers) => {
+        let (network_headers_sender, _) = self.network_headers.clone();
+        task::spawn(async move {
+          network_headers_sender.send((peer, id, headers)).await?;
+          Ok(()) as AppResult<()>
+        });
+      }
+      SyncResponse::Data(data) => {
+        let received: Vec<InvItem> = data.iter().map(InvData::item).collect();
+        let undelivered = self.inventory.unanswered(&request_id, &received);
+        self.handle_data(peer, data);
+        self.refetch(peer, undelivered);
+      }
+      SyncResponse::BlockTxs(hash, txs) => {
+        self.block_txs_requests.remove(&request_id);
+        self.handle_block_txs(peer, hash, txs)
+      }
+    }
+  }
+
+  fn record_serving_range(&mut self, peer_id: PeerId, first: i64, latest: i64) {
+    let serving_ranges = self.serving_ranges.clone();
     task::spawn(async move {
-      if let Ok(Some(block)) = db.lock().await.latest_block() {
-        send_network_response
-          .send(BchainResponse::Latest(block))
-          .await?;
+      serving_ranges
+        .write()
+        .await
+        .insert(peer_id, (first, latest));
+    });
+  }
+
+  /// untargeted requests go to every peer, answers are counted towards a majority
+  fn send_sync_request(&mut self, request: &SyncRequest, peer: Option<PeerId>) {
+    info!("Outgoing sync request: {}", request);
+    let peers = match peer {
+      Some(peer) => vec![peer],
+      None => self.peer_ids(),
+    };
+    for peer in peers {
+      let request_id = self
+        .swarm
+        .behaviour_mut()
+        .sync
+        .send_request(&peer, request.clone());
+      self.pending_sync.insert(request_id, peer);
+      match request {
+        SyncRequest::GetData(items) => self.inventory.requested(request_id, items),
+        SyncRequest::GetBlockTxs(hash, _) => {
+          self.block_txs_requests.insert(request_id, *hash);
+        }
+        _ => (),
       }
+    }
+  }
+
+  fn send_sync_response(&mut self, channel: ResponseChannel<SyncResponse>, response: SyncResponse) {
+    if self
+      .swarm
+      .behaviour_mut()
+      .sync
+      .send_response(channel, response)
+      .is_err()
+    {
+      warn!("Sync response dropped, connection closed");
+    }
+  }
+
+  fn send_hello(&mut self, peer_id: PeerId) {
+    let db = self.db.clone();
+    let net = self.cli.net.clone();
+    let (sync_requests, _) = self.sync_requests.clone();
+    task::spawn(async move {
+      let hello = Hello::new(&net, &*db.lock().await)?;
+      let request = SyncRequest::Hello(hello);
+      sync_requests.send((request, Some(peer_id))).await?;
       AppResult::Ok(())
     });
   }
 
-  fn respond_block(&mut self, id: i64) {
+  /// peers on another chain or protocol version are disconnected
+  fn check_hello(&mut self, peer_id: PeerId, hello: Hello) {
     let db = self.db.clone();
-    let (send_network_response, _) = self.network_responses.clone();
+    let net = self.cli.net.clone();
+    let (greetings, _) = self.greetings.clone();
+    let (disconnects, _) = self.disconnects.clone();
     task::spawn(async move {
-      if let Ok(Some(block)) = db.lock().await.get_block(id) {
-        send_network_response
-          .send(BchainResponse::Block(block))
-          .await?;
+      let local = Hello::new(&net, &*db.lock().await)?;
+      match hello.verify(&local) {
+        Ok(_) => greetings.send((peer_id, hello)).await?,
+        Err(e) => {
+          warn!("Disconnecting {}: {}", peer_id.short_display(), e);
+          disconnects.send(peer_id).await?;
+        }
       }
       AppResult::Ok(())
     });
   }
 
+  fn drop_silent_peers(&mut self) {
+    for peer_id in self.handshakes.expired(HELLO_TIMEOUT) {
+      warn!("Disconnecting {}: no handshake", peer_id.short_display());
+      self.handshakes.disconnected(&peer_id);
+      if self.swarm.disconnect_peer_id(peer_id).is_err() {
+        warn!("Peer {} already disconnected", peer_id.short_display());
+      }
+    }
+  }
+
+  fn respond_sync(&mut self, request: SyncRequest, channel: ResponseChannel<SyncResponse>) {
+    info!("Incoming sync request: {}", request);
+    let db = self.db.clone();
+    let net = self.cli.net.clone();
+    let (send_sync_response, _) = self.sync_responses.clone();
+    let data = match &request {
+      SyncRequest::GetData(items) => self.inventory.get(items),
+      _ => vec![],
+    };
+    let txs = match &request {
+      SyncRequest::GetBlockTxs(hash, indexes) => {
+        let block = self.inventory.block(hash);
+        block.map(|b| block_txs(b, indexes)).unwrap_or_default()
+      }
+      _ => vec![],
+    };
+    task::spawn(async move {
+      let db = db.lock().await;
+      let response = match request {
+        SyncRequest::Hello(_) => SyncResponse::Hello(Hello::new(&net, &db)?),
+        SyncRequest::Latest(id) => {
+          // pruned nodes tell peers which blocks they can still ask for
+          let serving = db.serving_range()?.filter(|(first, _)| *first > 0);
+          SyncResponse::Latest {
+            id,
+            block: db.latest_block()?,
+            serving,
+          }
+        }
+        SyncRequest::Block(id) => SyncResponse::Block(db.get_block(id)?),
+        SyncRequest::Headers(request_id, from, count) => {
+          let mut headers = vec![];
+          for id in from..from + count.clamp(0, MAX_HEADERS) {
+            match db.get_header(id)? {
+              Some(header) => headers.push(header),
+              None => break,
+            }
+          }
+          SyncResponse::Headers(request_id, headers)
+        }
+        SyncRequest::GetData(_) => SyncResponse::Data(data),
+        SyncRequest::GetBlockTxs(hash, _) => SyncResponse::BlockTxs(hash, txs),
+      };
+      send_sync_response.send((channel, response)).await?;
+      AppResult::Ok(())
+    });
+  }
+
   fn publish_user_message(&mut self, str: &str) {
     let (send_network_request, _) = self.network_requests.clone();
     let str = str.into();
@@ -309,16 +1188,21 @@ impl Node {
 
     let wallet = self.wallet.clone();
     let db = self.db.clone();
+    let serving_ranges = self.serving_ranges.clone();
 
-    let (network_requests, _) = self.network_requests.clone();
+    let (sync_requests, _) = self.sync_requests.clone();
     let (_, network_latest) = self.network_latest.clone();
+    let (_, network_headers) = self.network_headers.clone();
     let (_, network_blocks) = self.network_blocks.clone();
+    let sync_progress = self.sync_progress.clone();
+    let (peer_reports, _) = self.peer_reports.clone();
+    let peers = self.peer_ids();
 
     task::spawn(async move {
       info!("Bootstrapping network {}, peers {}", cli.net, num_peers);
 
       if cli.init {
-        bootstrap_init(wallet, db).await?;
+        bootstrap_init(wallet, db, cli.ledger).await?;
         return Ok(());
       }
 
@@ -327,12 +1211,15 @@ impl Node {
         return Ok(());
       }
 
-      let npc = (num_peers, consensus);
-
       loop {
         info!("Requesting latest block");
-        let network_latest_block =
-          request_latest_block(&npc, network_requests.clone(), network_latest.clone()).await?;
+        let network_latest_block = request_latest_block(
+          &peers,
+          consensus,
+          sync_requests.clone(),
+          network_latest.clone(),
+        )
+        .await?;
         let local_latest_block = db.lock().await.latest_block()?;
 
         if network_latest_block == local_latest_block {
@@ -340,22 +1227,54 @@ impl Node {
           break;
         }
 
-        if local_latest_block < network_latest_block {
-          let block = request_specific_block(
-            network_latest_block.unwrap().id,
-            &npc,
-            network_requests.clone(),
-            network_blocks.clone(),
-          )
-          .await?;
-
-          if let Some(block) = block {
-            db.lock().await.commit_block(&block)?;
+        if local_latest_block >= network_latest_block {
+          warn!("Local chain diverges from network");
+          break;
+        }
+
+        let network_latest_block = match network_latest_block {
+          Some(block) => block,
+          None => break,
+        };
+        let downloader = Downloader {
+          db: db.clone(),
+          sync_requests: sync_requests.clone(),
+          network_headers: network_headers.clone(),
+          network_blocks: network_blocks.clone(),
+          peers: peers.clone(),
+          serving_ranges: serving_ranges.read().await.clone(),
+          majority: consensus,
+          progress: sync_progress.clone(),
+          peer_reports: peer_reports.clone(),
+        };
+        let downloaded = downloader
+          .run(local_latest_block.as_ref(), &network_latest_block)
+          .await;
+        if let Err(e) = downloaded {
+          match e.downcast_ref::<StorageError>() {
+            Some(StorageError::NotContiguous { .. }) => warn!("{}, retrying", e),
+            Some(StorageError::ParentMismatch { .. }) => {
+              warn!("{}, checking the competing chain", e);
+              if let Err(e) = downloader.reorg(&network_latest_block).await {
+                warn!("Keeping the local chain: {}", e);
+                break;
+              }
+            }
+            Some(StorageError::Corrupt { id }) => {
+              error!("{}, restart to truncate the chain before block {}", e, id);
+              break;
+            }
+            Some(StorageError::Backend(_)) => return Err(e),
+            None => {
+              warn!("Sync failed: {}", e);
+              break;
+            }
           }
         }
       }
 
       info!("Bootstrap complete");
+      *sync_progress.write().await = None;
 
       Ok(()) as AppResult<()>
     });
@@ -370,20 +1289,236 @@ impl Node {
     let address = address.clone();
     task::spawn(async move {
       let address = address.unwrap_or(wallet.read().await.address());
-      let balance = local_balance(&address, db).await?;
+      let ledger = db.lock().await.ledger()?;
+      let balance = match ledger {
+        Ledger::Account => local_balance(&address, db).await?,
+        Ledger::Utxo => local_utxo_balance(&address, db).await?,
+      };
       info!("Wallet: {}", address);
       info!("Balance: ¢{}", balance);
       Ok(()) as AppResult<()>
     });
   }
 
-  pub(crate) fn submit_tx(&self, recipient: &Address, amount: u64) {
+  pub(crate) fn sign_message(&self, text: &str) {
+    let wallet = self.wallet.clone();
+    let text = text.to_owned();
+    task::spawn(async move {
+      let wallet = wallet.read().await;
+      let signature = wallet.sign_message(&text)?;
+      info!("Address: {}", wallet.address());
+      info!("Signature: {}", signature);
+      AppResult::Ok(())
+    });
+  }
+
+  pub(crate) fn verify_message(&self, address: &Address, signature: &Signature, text: &str) {
+    match verify_message(address, signature, text) {
+      Ok(_) => info!("Message was signed by {}", address.short_display()),
+      Err(_) => warn!("Message was NOT signed by {}", address.short_display()),
+    }
+  }
+
+  pub(crate) fn watch(&self, watch: &WatchCommand) {
+    let db = self.db.clone();
+    let watch = watch.clone();
+    task::spawn(async move {
+      let mut db = db.lock().await;
+      let db = db
+        .index_mut()
+        .ok_or_else(|| unsupported("Watch-only addresses"))?;
+      match watch {
+        WatchCommand::Add(address) => {
+          db.watch(&address)?;
+          info!("Watching {}", address.short_display());
+        }
+        WatchCommand::Remove(address) => match db.unwatch(&address)? {
+          true => info!("Stopped watching {}", address.short_display()),
+          false => warn!("Not watching {}", address.short_display()),
+        },
+        WatchCommand::List => {
+          let watched = db.watched()?;
+          for w in &watched {
+            info!(
+              "{} txs: {} balance: ¢{}",
+              w.address.short_display(),
+              w.txs,
+              w.balance
+            );
+          }
+          let total: i64 = watched.iter().map(|w| w.balance).sum();
+          info!("Watched: {}, total balance: ¢{}", watched.len(), total);
+        }
+        WatchCommand::History(address) => {
+          let history = db.watched_history(&address)?;
+          for entry in &history {
+            info!(
+              "block {} tx {} ¢{}",
+              entry.block_id,
+              entry.tx_hash.short_display(),
+              entry.diff
+            );
+          }
+          info!("{} txs for {}", history.len(), address.short_display());
+        }
+      }
+      AppResult::Ok(())
+    });
+  }
+
+  pub(crate) fn tx_info(&self, hash: &str) {
+    let db = self.db.clone();
+    let hash = hash.to_owned();
+    task::spawn(async move {
+      let info = match db.lock().await.get_tx(&hash)? {
+        Some(info) => info,
+        None => {
+          warn!("Tx {} not found", hash.short_display());
+          return AppResult::Ok(());
+        }
+      };
+      info!(
+        "Tx {} in block {}, {} confirmations",
+        hash.short_display(),
+        info.block_id,
+        info.confirmations
+      );
+      info!("From: {}", info.tx.sender().short_display());
+      for output in info.tx.outputs() {
+        info!("To: {} ¢{}", output.receiver.short_display(), output.amount);
+      }
+      Ok(())
+    });
+  }
+
+  #[allow(clippy::or_fun_call)]
+  pub(crate) fn history(&self, address: &Option<Address>, page: i64) {
+    let wallet = self.wallet.clone();
+    let db = self.db.clone();
+    let address = address.clone();
+    task::spawn(async move {
+      let address = address.unwrap_or(wallet.read().await.address());
+      let db = db.lock().await;
+      for entry in db.history(&address, page)? {
+        info!(
+          "block {} tx {} ¢{}",
+          entry.block_id,
+          entry.tx_hash.short_display(),
+          entry.diff
+        );
+      }
+      info!(
+        "{} txs for {}",
+        db.history_len(&address)?,
+        address.short_display()
+      );
+      AppResult::Ok(())
+    });
+  }
+
+  pub(crate) fn check_accounts(&self) {
+    let db = self.db.clone();
+    task::spawn(async move {
+      let mut db = db.lock().await;
+      let rebuilt = match db.index_mut() {
+        Some(index) => index.rebuild_accounts(),
+        None => Err(unsupported("Account index")),
+      };
+      match rebuilt {
+        Ok(0) => info!("Account index is consistent"),
+        Ok(n) => warn!("Rebuilt account index, {} accounts were out of sync", n),
+        Err(e) => warn!("{}", e),
+      }
+    });
+  }
+
+  pub(crate) fn submit_tx(&self, outputs: &[TxOutput]) {
     let (send_network_request, _) = self.network_requests.clone();
     let wallet = self.wallet.clone();
-    let recipient = recipient.clone();
+    let db = self.db.clone();
+    let outputs = outputs.to_vec();
     task::spawn(async move {
       let wallet = wallet.read().await;
-      let tx = wallet.new_tx(&recipient, amount)?;
+      let ledger = db.lock().await.ledger()?;
+      let tx = match ledger {
+        Ledger::Account => wallet.new_tx(outputs),
+        Ledger::Utxo => utxo_tx(&wallet, outputs, db).await,
+      }?;
+      send_network_request
+        .send(BchainRequest::SubmitTx(tx))
+        .await?;
+      AppResult::Ok(())
+    });
+  }
+
+  pub(crate) fn build_tx(&self, recipient: &Address, amount: u64, file: &Option<String>) {
+    let unsigned = UnsignedTx::new(recipient, amount);
+    let file = file
+      .clone()
+      .unwrap_or_else(|| format!("tx-{}.json", unsigned.timestamp));
+    task::spawn(async move {
+      TxFile::Unsigned(unsigned).write(&file).await?;
+      info!("Unsigned tx written to {}", file);
+      AppResult::Ok(())
+    });
+  }
+
+  pub(crate) fn create_multisig(&self, threshold: usize, keys: &[PublicKey]) {
+    let policy = match MultisigPolicy::new(threshold, keys.to_vec()) {
+      Ok(policy) => policy,
+      Err(e) => return error!("{}", e),
+    };
+    task::spawn(async move {
+      let address = policy.address();
+      let file = format!("multisig-{}.json", address.short_display());
+      policy.write(&file).await?;
+      info!(
+        "Multisig {} of {}: {}",
+        policy.threshold(),
+        policy.keys().len(),
+        address
+      );
+      info!("Policy written to {}", file);
+      AppResult::Ok(())
+    });
+  }
+
+  pub(crate) fn build_multisig_tx(
+    &self,
+    policy: &str,
+    recipient: &Address,
+    amount: u64,
+    file: &Option<String>,
+  ) {
+    let unsigned = UnsignedTx::new(recipient, amount);
+    let file = file
+      .clone()
+      .unwrap_or_else(|| format!("tx-{}.json", unsigned.timestamp));
+    let policy = policy.to_owned();
+    task::spawn(async move {
+      let policy = MultisigPolicy::read(&policy).await?;
+      let partial = PartiallySignedTx::new(unsigned, &policy);
+      TxFile::PartiallySigned(partial).write(&file).await?;
+      info!(
+        "Multisig tx written to {}, needs {} signatures",
+        file,
+        policy.threshold()
+      );
+      AppResult::Ok(())
+    });
+  }
+
+  pub(crate) fn submit_tx_file(&self, file: &str) {
+    let (send_network_request, _) = self.network_requests.clone();
+    let file = file.to_owned();
+    task::spawn(async move {
+      let tx = match TxFile::read(&file).await.and_then(TxFile::signed) {
+        Ok(tx) => tx,
+        Err(e) => {
+          error!("Rejected tx file {}: {}", file, e);
+          return Ok(());
+        }
+      };
       send_network_request
         .send(BchainRequest::SubmitTx(tx))
         .await?;
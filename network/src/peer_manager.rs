@@ -0,0 +1,183 @@
+use bchain_db::raw_ban::RawBan;
+use bchain_util::short::ShortDisplay;
+use chrono::{Duration, Utc};
+use libp2p::PeerId;
+use log::warn;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// peers at or below this score are banned temporarily
+pub const BAN_SCORE: f64 = -100.0;
+/// good behaviour can't build up enough credit to hide later misbehaviour
+const MAX_SCORE: f64 = 100.0;
+const AUTO_BAN_MINUTES: i64 = 60;
+
+/// what a peer did, each kind moves its score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Behaviour {
+  InvalidBlock,
+  InvalidTx,
+  Malformed,
+  Timeout,
+  Useful,
+}
+
+impl Behaviour {
+  fn score(&self) -> f64 {
+    match self {
+      Behaviour::InvalidBlock => -50.0,
+      Behaviour::InvalidTx => -20.0,
+      Behaviour::Malformed => -20.0,
+      Behaviour::Timeout => -5.0,
+      Behaviour::Useful => 1.0,
+    }
+  }
+}
+
+impl Display for Behaviour {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      Behaviour::InvalidBlock => "invalid block",
+      Behaviour::InvalidTx => "invalid tx",
+      Behaviour::Malformed => "malformed message",
+      Behaviour::Timeout => "timeout",
+      Behaviour::Useful => "useful",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// tracks peer scores and bans, the score is also handed to gossipsub
+#[derive(Debug, Default)]
+pub struct PeerManager {
+  scores: HashMap<PeerId, f64>,
+  bans: HashMap<PeerId, RawBan>,
+}
+
+impl PeerManager {
+  /// stored bans with peer ids that don't parse are skipped
+  pub fn new(bans: Vec<RawBan>) -> PeerManager {
+    let mut manager = PeerManager::default();
+    for ban in bans {
+      match PeerId::from_str(&ban.peer_id) {
+        Ok(peer_id) => {
+          manager.bans.insert(peer_id, ban);
+        }
+        Err(_) => warn!("Skipping ban of invalid peer id {}", ban.peer_id),
+      }
+    }
+    manager
+  }
+
+  pub fn score(&self, peer_id: &PeerId) -> f64 {
+    self.scores.get(peer_id).copied().unwrap_or_default()
+  }
+
+  /// score remembered from an earlier run
+  pub fn restore_score(&mut self, peer_id: PeerId, score: f64) {
+    self.scores.insert(peer_id, score.min(MAX_SCORE));
+  }
+
+  /// new ban if the peer's score fell to the ban threshold
+  pub fn report(&mut self, peer_id: &PeerId, behaviour: Behaviour) -> Option<RawBan> {
+    let entry = self.scores.entry(*peer_id).or_default();
+    *entry = (*entry + behaviour.score()).min(MAX_SCORE);
+    let score = *entry;
+    if score > BAN_SCORE || self.is_banned(peer_id) {
+      return None;
+    }
+    let reason = format!("score {} after {}", score, behaviour);
+    Some(self.ban(peer_id, Some(AUTO_BAN_MINUTES), &reason))
+  }
+
+  /// bans without `minutes` don't expire
+  pub fn ban(&mut self, peer_id: &PeerId, minutes: Option<i64>, reason: &str) -> RawBan {
+    let ban = RawBan {
+      peer_id: peer_id.to_string(),
+      until: minutes.map(|m| (Utc::now() + Duration::minutes(m)).naive_utc()),
+      reason: reason.to_owned(),
+    };
+    self.bans.insert(*peer_id, ban.clone());
+    ban
+  }
+
+  /// the score is reset so the peer doesn't get banned again right away
+  pub fn unban(&mut self, peer_id: &PeerId) -> bool {
+    self.scores.remove(peer_id);
+    self.bans.remove(peer_id).is_some()
+  }
+
+  pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+    self
+      .bans
+      .get(peer_id)
+      .map(RawBan::is_active)
+      .unwrap_or(false)
+  }
+
+  pub fn bans(&self) -> impl Iterator<Item = (&PeerId, &RawBan)> {
+    self.bans.iter()
+  }
+
+  /// temporary bans that ran out, removed from the list
+  pub fn expired(&mut self) -> Vec<PeerId> {
+    let expired: Vec<PeerId> = self
+      .bans
+      .iter()
+      .filter(|(_, ban)| !ban.is_active())
+      .map(|(peer_id, _)| *peer_id)
+      .collect();
+    for peer_id in &expired {
+      self.unban(peer_id);
+    }
+    expired
+  }
+
+  pub fn describe(&self, peer_id: &PeerId) -> String {
+    format!("{} score {}", peer_id.short_display(), self.score(peer_id))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn score_and_ban_test() {
+    let peer_id = PeerId::random();
+    let mut manager = PeerManager::default();
+    for _ in 0..150 {
+      assert_eq!(manager.report(&peer_id, Behaviour::Useful), None);
+    }
+    assert_eq!(manager.score(&peer_id), MAX_SCORE);
+
+    for _ in 0..3 {
+      assert_eq!(manager.report(&peer_id, Behaviour::InvalidBlock), None);
+    }
+    let ban = manager.report(&peer_id, Behaviour::InvalidBlock).unwrap();
+    assert!(ban.until.is_some());
+    assert!(manager.is_banned(&peer_id));
+    assert_eq!(manager.report(&peer_id, Behaviour::InvalidBlock), None);
+    assert!(manager.expired().is_empty());
+
+    assert!(manager.unban(&peer_id));
+    assert!(!manager.is_banned(&peer_id));
+    assert_eq!(manager.score(&peer_id), 0.0);
+  }
+
+  #[test]
+  fn expired_ban_test() {
+    let peer_id = PeerId::random();
+    let mut manager = PeerManager::new(vec![RawBan {
+      peer_id: "invalid".into(),
+      until: None,
+      reason: String::new(),
+    }]);
+    assert_eq!(manager.bans().count(), 0);
+    manager.ban(&peer_id, Some(-1), "test");
+    assert!(!manager.is_banned(&peer_id));
+    assert_eq!(manager.expired(), vec![peer_id]);
+    assert_eq!(manager.bans().count(), 0);
+  }
+}
@@ -1,6 +1,8 @@
+use crate::inventory::InvItem;
 use bchain_domain::{block::Block, tx::Tx};
 use bchain_util::hash_digest::{HashDigest, Hashable};
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Frame {
@@ -9,19 +11,17 @@ pub enum Frame {
   Unrecognized,
 }
 
+/// submitted blocks and txs are announced to peers as `Inv`
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum BchainRequest {
-  AskLatest,
-  AskBlock(i64),
   SubmitBlock(Block),
   SubmitTx(Tx),
   Msg(String),
+  Inv(Vec<InvItem>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum BchainResponse {
-  Latest(Block),
-  Block(Block),
   AcceptBlock(HashDigest),
   AcceptTx(HashDigest),
   Error(BchainError),
@@ -34,24 +34,23 @@ pub enum BchainError {
   Generic(String),
 }
 
-impl ToString for BchainRequest {
-  fn to_string(&self) -> String {
+impl Display for BchainRequest {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
-      BchainRequest::SubmitBlock(block) => format!("SubmitBlock({})", block.hash_digest()),
-      BchainRequest::SubmitTx(tx) => format!("SubmitTx({})", tx.hash_digest()),
-      other => format!("{:?}", other),
+      BchainRequest::SubmitBlock(block) => write!(f, "SubmitBlock({})", block.hash_digest()),
+      BchainRequest::SubmitTx(tx) => write!(f, "SubmitTx({})", tx.hash_digest()),
+      BchainRequest::Inv(items) => write!(f, "Inv({})", items.len()),
+      other => write!(f, "{:?}", other),
     }
   }
 }
 
-impl ToString for BchainResponse {
-  fn to_string(&self) -> String {
+impl Display for BchainResponse {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
-      BchainResponse::AcceptBlock(digest) => format!("AcceptBlock({})", digest),
-      BchainResponse::AcceptTx(digest) => format!("AcceptTx({})", digest),
-      BchainResponse::Block(block) => format!("Block({})", block.hash_digest()),
-      BchainResponse::Latest(block) => format!("Latest({})", block.hash_digest()),
-      BchainResponse::Error(error) => format!("Error({:?})", error),
+      BchainResponse::AcceptBlock(digest) => write!(f, "AcceptBlock({})", digest),
+      BchainResponse::AcceptTx(digest) => write!(f, "AcceptTx({})", digest),
+      BchainResponse::Error(error) => write!(f, "Error({:?})", error),
     }
   }
 }
@@ -1,20 +1,104 @@
+use crate::sync::{SyncCodec, SyncProtocol, SyncRequest, SyncResponse};
+use crate::transport::{build_transport, connection_limits};
+use bchain_domain::cli::Cli;
 use bchain_util::{error::AppError, result::AppResult};
 use libp2p::gossipsub::{
-  self, subscription_filter::AllowAllSubscriptionFilter, Gossipsub, IdentTopic as Topic,
-  IdentityTransform, MessageAuthenticity, ValidationMode,
+  self, subscription_filter::AllowAllSubscriptionFilter, Gossipsub, GossipsubEvent,
+  IdentTopic as Topic, IdentityTransform, MessageAuthenticity, PeerScoreParams,
+  PeerScoreThresholds, TopicScoreParams, ValidationMode,
 };
-use libp2p::{identity::Keypair, PeerId, Swarm};
+use libp2p::kad::{store::MemoryStore, Kademlia, KademliaConfig, KademliaEvent};
+use libp2p::mdns::{Mdns, MdnsConfig, MdnsEvent};
+use libp2p::ping::{Ping, PingConfig, PingEvent};
+use libp2p::request_response::{
+  ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+};
+use libp2p::swarm::{toggle::Toggle, SwarmBuilder};
+use libp2p::{identity::Keypair, NetworkBehaviour, PeerId, Swarm};
+use std::iter;
 use std::time::Duration;
 
-pub type BchainSwarm = Swarm<Gossipsub<IdentityTransform, AllowAllSubscriptionFilter>>;
+pub type BchainSwarm = Swarm<BchainBehaviour>;
+
+pub type SyncEvent = RequestResponseEvent<SyncRequest, SyncResponse>;
+
+/// gossip announces new blocks and txs, sync fetches blocks from a single peer,
+/// mdns and kademlia find peers and ping keeps connections alive when enabled
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "BchainEvent", event_process = false)]
+pub struct BchainBehaviour {
+  pub gossipsub: Gossipsub<IdentityTransform, AllowAllSubscriptionFilter>,
+  pub sync: RequestResponse<SyncCodec>,
+  pub mdns: Toggle<Mdns>,
+  pub kademlia: Toggle<Kademlia<MemoryStore>>,
+  pub ping: Toggle<Ping>,
+}
+
+#[derive(Debug)]
+pub enum BchainEvent {
+  Gossipsub(GossipsubEvent),
+  Sync(SyncEvent),
+  Mdns(MdnsEvent),
+  Kademlia(KademliaEvent),
+  Ping(PingEvent),
+}
 
-pub async fn create_swarm(local_peer_key: &Keypair, topic: &Topic) -> AppResult<BchainSwarm> {
+impl From<GossipsubEvent> for BchainEvent {
+  fn from(event: GossipsubEvent) -> Self {
+    BchainEvent::Gossipsub(event)
+  }
+}
+
+impl From<SyncEvent> for BchainEvent {
+  fn from(event: SyncEvent) -> Self {
+    BchainEvent::Sync(event)
+  }
+}
+
+impl From<MdnsEvent> for BchainEvent {
+  fn from(event: MdnsEvent) -> Self {
+    BchainEvent::Mdns(event)
+  }
+}
+
+impl From<KademliaEvent> for BchainEvent {
+  fn from(event: KademliaEvent) -> Self {
+    BchainEvent::Kademlia(event)
+  }
+}
+
+impl From<PingEvent> for BchainEvent {
+  fn from(event: PingEvent) -> Self {
+    BchainEvent::Ping(event)
+  }
+}
+
+pub async fn create_swarm(
+  local_peer_key: &Keypair,
+  topic: &Topic,
+  cli: &Cli,
+) -> AppResult<BchainSwarm> {
   let local_peer_id = PeerId::from(local_peer_key.public());
-  let transport = libp2p::development_transport(local_peer_key.clone()).await?;
+  let transport = build_transport(local_peer_key, cli).await?;
+  let behaviour = create_behaviour(local_peer_key, topic, cli).await?;
+  let swarm = SwarmBuilder::new(transport, behaviour, local_peer_id)
+    .connection_limits(connection_limits(cli))
+    .build();
+  Ok(swarm)
+}
 
+pub async fn create_behaviour(
+  local_peer_key: &Keypair,
+  topic: &Topic,
+  cli: &Cli,
+) -> AppResult<BchainBehaviour> {
+  let local_peer_id = PeerId::from(local_peer_key.public());
+  let idle_timeout = Duration::from_secs(cli.idle_timeout);
   let gossipsub_config = gossipsub::GossipsubConfigBuilder::default()
     .heartbeat_interval(Duration::from_secs(10))
     .validation_mode(ValidationMode::Strict)
+    .validate_messages()
+    .idle_timeout(idle_timeout)
     .build()
     .unwrap();
 
@@ -31,5 +115,54 @@ pub async fn create_swarm(local_peer_key: &Keypair, topic: &Topic) -> AppResult<
 
   gossipsub.subscribe(topic).unwrap();
 
-  Ok(libp2p::Swarm::new(transport, gossipsub, local_peer_id))
+  // quiet peers are fine, only messages that fail to decode count against a peer
+  let topic_params = TopicScoreParams {
+    mesh_message_deliveries_weight: 0.0,
+    mesh_failure_penalty_weight: 0.0,
+    invalid_message_deliveries_weight: -10.0,
+    ..Default::default()
+  };
+  // peer manager scores are handed over as they are
+  let mut score_params = PeerScoreParams {
+    app_specific_weight: 1.0,
+    ..Default::default()
+  };
+  score_params.topics.insert(topic.hash(), topic_params);
+  gossipsub
+    .with_peer_score(score_params, PeerScoreThresholds::default())
+    .map_err(AppError::msg)?;
+
+  let mut sync_config = RequestResponseConfig::default();
+  sync_config.set_request_timeout(Duration::from_secs(10));
+  sync_config.set_connection_keep_alive(idle_timeout);
+  let protocols = iter::once((SyncProtocol, ProtocolSupport::Full));
+  let codec = SyncCodec {
+    compression: cli.compression,
+  };
+  let sync = RequestResponse::new(codec, protocols, sync_config);
+
+  let mdns = match cli.mdns {
+    true => Some(Mdns::new(MdnsConfig::default()).await?),
+    false => None,
+  };
+  let kademlia = cli.kademlia.then(|| {
+    let mut config = KademliaConfig::default();
+    config.set_connection_idle_timeout(idle_timeout);
+    let store = MemoryStore::new(local_peer_id);
+    Kademlia::with_config(local_peer_id, store, config)
+  });
+  let ping = cli.keep_alive.map(|interval| {
+    let config = PingConfig::new()
+      .with_interval(Duration::from_secs(interval))
+      .with_keep_alive(true);
+    Ping::new(config)
+  });
+
+  Ok(BchainBehaviour {
+    gossipsub,
+    sync,
+    mdns: mdns.into(),
+    kademlia: kademlia.into(),
+    ping: ping.into(),
+  })
 }
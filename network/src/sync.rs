@@ -0,0 +1,192 @@
+use crate::codec::{decode, encode, HEADER_LEN, MAX_MESSAGE_SIZE};
+use crate::hello::Hello;
+use crate::inventory::{InvData, InvItem};
+use async_trait::async_trait;
+use bchain_domain::block::{Block, BlockHeader};
+use bchain_domain::cli::Compression;
+use bchain_domain::tx::Tx;
+use bchain_util::hash_digest::{HashDigest, Hashable};
+use futures::prelude::*;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName};
+use libp2p::request_response::RequestResponseCodec;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// upper bound of headers served per request
+pub const MAX_HEADERS: i64 = 2000;
+
+/// echoed in responses that are voted on, so answers to earlier requests aren't counted
+pub type RequestId = u64;
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+pub fn next_request_id() -> RequestId {
+  NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// blocks are fetched from single peers, gossip only announces new blocks and txs
+#[derive(Debug, Clone)]
+pub struct SyncProtocol;
+
+impl ProtocolName for SyncProtocol {
+  fn protocol_name(&self) -> &[u8] {
+    b"/bchain/sync/1"
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SyncRequest {
+  Hello(Hello),
+  Latest(RequestId),
+  Block(i64),
+  /// up to `count` headers starting at id `from`
+  Headers(RequestId, i64, i64),
+  /// announced items, up to `MAX_INV`
+  GetData(Vec<InvItem>),
+  /// txs of a compact block that were missing from the pool, by position
+  GetBlockTxs(HashDigest, Vec<usize>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SyncResponse {
+  Hello(Hello),
+  /// `serving` is set by pruned nodes, first and latest block they have in full
+  Latest {
+    id: RequestId,
+    block: Option<Block>,
+    serving: Option<(i64, i64)>,
+  },
+  Block(Option<Block>),
+  Headers(RequestId, Vec<BlockHeader>),
+  /// the asked items that are still known, others are left out
+  Data(Vec<InvData>),
+  BlockTxs(HashDigest, Vec<Tx>),
+}
+
+impl Display for SyncRequest {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SyncRequest::Hello(hello) => write!(f, "Hello({})", hello),
+      SyncRequest::GetData(items) => write!(f, "GetData({})", items.len()),
+      SyncRequest::GetBlockTxs(hash, indexes) => {
+        write!(f, "GetBlockTxs({}, {})", hash, indexes.len())
+      }
+      other => write!(f, "{:?}", other),
+    }
+  }
+}
+
+impl Display for SyncResponse {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SyncResponse::Hello(hello) => write!(f, "Hello({})", hello),
+      SyncResponse::Latest { id, block, serving } => match block {
+        Some(block) => write!(f, "Latest({}, {}, {:?})", id, block.hash_digest(), serving),
+        None => write!(f, "Latest({}, None)", id),
+      },
+      SyncResponse::Block(Some(block)) => write!(f, "Block({})", block.hash_digest()),
+      SyncResponse::Block(None) => write!(f, "Block(None)"),
+      SyncResponse::Headers(id, headers) => write!(f, "Headers({}, {})", id, headers.len()),
+      SyncResponse::Data(data) => write!(f, "Data({})", data.len()),
+      SyncResponse::BlockTxs(hash, txs) => write!(f, "BlockTxs({}, {})", hash, txs.len()),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncCodec {
+  pub compression: Compression,
+}
+
+#[async_trait]
+impl RequestResponseCodec for SyncCodec {
+  type Protocol = SyncProtocol;
+  type Request = SyncRequest;
+  type Response = SyncResponse;
+
+  async fn read_request<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<SyncRequest>
+  where
+    T: AsyncRead + Unpin + Send,
+  {
+    read_message(io).await
+  }
+
+  async fn read_response<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<SyncResponse>
+  where
+    T: AsyncRead + Unpin + Send,
+  {
+    read_message(io).await
+  }
+
+  async fn write_request<T>(
+    &mut self,
+    _: &SyncProtocol,
+    io: &mut T,
+    request: SyncRequest,
+  ) -> io::Result<()>
+  where
+    T: AsyncWrite + Unpin + Send,
+  {
+    write_message(io, &request, self.compression).await
+  }
+
+  async fn write_response<T>(
+    &mut self,
+    _: &SyncProtocol,
+    io: &mut T,
+    response: SyncResponse,
+  ) -> io::Result<()>
+  where
+    T: AsyncWrite + Unpin + Send,
+  {
+    write_message(io, &response, self.compression).await
+  }
+}
+
+async fn read_message<T, M>(io: &mut T) -> io::Result<M>
+where
+  T: AsyncRead + Unpin + Send,
+  M: DeserializeOwned,
+{
+  let bytes = read_length_prefixed(io, HEADER_LEN + MAX_MESSAGE_SIZE).await?;
+  decode(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_message<T, M>(io: &mut T, message: &M, compression: Compression) -> io::Result<()>
+where
+  T: AsyncWrite + Unpin + Send,
+  M: Serialize,
+{
+  let bytes = encode(message, compression).map_err(io::Error::other)?;
+  write_length_prefixed(io, bytes).await?;
+  io.close().await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bchain_util::result::AppResult;
+  use futures::io::Cursor;
+
+  #[async_std::test]
+  async fn codec_roundtrip_test() -> AppResult<()> {
+    let block = Block::from_previous(&Block::default(), None::<Vec<_>>);
+    let response = SyncResponse::Latest {
+      id: next_request_id(),
+      block: Some(block),
+      serving: Some((3, 4)),
+    };
+    let mut buffer = Cursor::new(vec![]);
+    let mut codec = SyncCodec::default();
+    codec
+      .write_response(&SyncProtocol, &mut buffer, response.clone())
+      .await?;
+    buffer.set_position(0);
+    let response1 = codec.read_response(&SyncProtocol, &mut buffer).await?;
+    assert_eq!(response, response1);
+    Ok(())
+  }
+}
@@ -0,0 +1,74 @@
+use bchain_domain::cli::Cli;
+use bchain_util::error::AppError;
+use bchain_util::result::AppResult;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::network::ConnectionLimits;
+use libp2p::core::transport::{Boxed, OptionalTransport};
+use libp2p::core::upgrade;
+use libp2p::dns::DnsConfig;
+use libp2p::noise::{Keypair as NoiseKeypair, NoiseConfig, X25519Spec};
+use libp2p::tcp::TcpConfig;
+use libp2p::websocket::WsConfig;
+use libp2p::yamux::YamuxConfig;
+use libp2p::{identity::Keypair, PeerId, Transport};
+use std::time::Duration;
+
+/// connection upgrades that take longer are dropped
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub type BchainTransport = Boxed<(PeerId, StreamMuxerBox)>;
+
+/// tcp with noise and yamux, websocket on top of it with --websocket
+pub async fn build_transport(local_peer_key: &Keypair, cli: &Cli) -> AppResult<BchainTransport> {
+  let tcp = TcpConfig::new().nodelay(true);
+  let dns_tcp = DnsConfig::system(tcp).await?;
+  let websocket = match cli.websocket {
+    true => OptionalTransport::some(WsConfig::new(dns_tcp.clone())),
+    false => OptionalTransport::none(),
+  };
+  let noise_keys = NoiseKeypair::<X25519Spec>::new()
+    .into_authentic(local_peer_key)
+    .map_err(AppError::msg)?;
+  Ok(
+    dns_tcp
+      .or_transport(websocket)
+      .upgrade(upgrade::Version::V1)
+      .authenticate(NoiseConfig::xx(noise_keys).into_authenticated())
+      .multiplex(YamuxConfig::default())
+      .timeout(UPGRADE_TIMEOUT)
+      .boxed(),
+  )
+}
+
+pub fn connection_limits(cli: &Cli) -> ConnectionLimits {
+  ConnectionLimits::default()
+    .with_max_established_incoming(Some(cli.max_incoming))
+    .with_max_established_outgoing(Some(cli.max_outgoing))
+    .with_max_established_per_peer(Some(cli.max_per_peer))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use structopt::StructOpt;
+
+  #[async_std::test]
+  async fn build_transport_test() -> AppResult<()> {
+    let key = Keypair::generate_ed25519();
+    let cli = Cli::from_iter(&["bchain", "--websocket"]);
+    let ws = build_transport(&key, &cli)
+      .await?
+      .listen_on("/ip4/127.0.0.1/tcp/0/ws".parse()?);
+    assert!(ws.is_ok());
+    let cli = Cli::from_iter(&["bchain"]);
+    let tcp = build_transport(&key, &cli)
+      .await?
+      .listen_on("/ip4/127.0.0.1/tcp/0".parse()?);
+    assert!(tcp.is_ok());
+    let ws = build_transport(&key, &cli)
+      .await?
+      .listen_on("/ip4/127.0.0.1/tcp/0/ws".parse()?);
+    assert!(ws.is_err());
+    Ok(())
+  }
+}
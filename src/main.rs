@@ -1,6 +1,12 @@
-use bchain_domain::cli::Cli;
+use bchain_db::archive::{export, import};
+use bchain_db::store::create_store;
+use bchain_domain::cli::{Cli, CliCommand};
+use bchain_domain::tx_file::{sign_tx_file, TxFile};
+use bchain_domain::wallet::Wallet;
 use bchain_network::node::Node;
+use bchain_util::hash_digest::Hashable;
 use bchain_util::result::AppResult;
+use log::info;
 use structopt::StructOpt;
 
 #[async_std::main]
@@ -8,8 +14,36 @@ async fn main() -> AppResult<()> {
   dotenv::dotenv()?;
   pretty_env_logger::init();
 
-  let mut node = Node::new(&Cli::from_args()).await?;
-  node.run().await?;
+  let cli = Cli::from_args();
+  match &cli.cmd {
+    Some(CliCommand::Sign { input, output }) => {
+      let wallet = Wallet::from_file(&cli.wallet).await?;
+      let output = output.clone().unwrap_or(format!("{}.signed", input));
+      match sign_tx_file(&wallet, input, &output).await? {
+        TxFile::PartiallySigned(partial) => info!(
+          "Signatures {} of {} written to {}",
+          partial.signatures(),
+          partial.witness.policy.threshold(),
+          output
+        ),
+        TxFile::Signed(tx) => info!("Signed tx {} written to {}", tx.hash_digest(), output),
+        TxFile::Unsigned(_) => (),
+      }
+    }
+    Some(CliCommand::Export { from, to, output }) => {
+      let store = create_store(&cli)?;
+      let header = export(store.as_ref(), &cli.net, *from, *to, output)?;
+      info!("Exported {} blocks to {}", header.count, output);
+    }
+    Some(CliCommand::Import { input }) => {
+      let mut store = create_store(&cli)?;
+      import(store.as_mut(), &cli.net, input)?;
+    }
+    None => {
+      let mut node = Node::new(&cli).await?;
+      node.run().await?;
+    }
+  }
 
   Ok(())
 }
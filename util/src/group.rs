@@ -1,6 +1,5 @@
 use async_std::prelude::*;
 use async_std::stream::Stream;
-use num::Integer;
 use std::collections::HashMap;
 use std::hash::Hash;
 
@@ -42,8 +41,63 @@ where
   group_by(stream, group_num, |g| g.hash_digest())
 }
 
+/// outcome of a vote, `value` is missing if no majority was reached,
+/// then every voter heard from disagreed, `silent` voters never voted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consensus<V, T> {
+  pub value: Option<T>,
+  pub agreed: Vec<V>,
+  pub disagreed: Vec<V>,
+  pub silent: Vec<V>,
+}
+
+/// first value `group_num` of `voters` agree on, only the first vote of a voter counts,
+/// votes are collected until every voter voted or the stream ended
+pub async fn vote_by<S, V, T, K, F>(
+  stream: S,
+  voters: &[V],
+  group_num: usize,
+  get_key: F,
+) -> Consensus<V, T>
+where
+  S: Stream<Item = (V, T)>,
+  V: PartialEq + Clone,
+  K: PartialEq,
+  F: Fn(&T) -> K,
+{
+  assert!(group_num > 0);
+  let mut stream = Box::pin(stream);
+  let mut votes: Vec<(V, K)> = vec![];
+  let mut value: Option<(usize, T)> = None;
+  while votes.len() < voters.len() {
+    let (voter, vote) = match stream.next().await {
+      Some(vote) => vote,
+      None => break,
+    };
+    if !voters.contains(&voter) || votes.iter().any(|(v, _)| *v == voter) {
+      continue;
+    }
+    let key = get_key(&vote);
+    let count = votes.iter().filter(|(_, k)| *k == key).count() + 1;
+    votes.push((voter, key));
+    if value.is_none() && count >= group_num {
+      value = Some((votes.len() - 1, vote));
+    }
+  }
+  let winner = value.as_ref().map(|(idx, _)| &votes[*idx].1);
+  let (agreed, disagreed): (Vec<_>, Vec<_>) = votes.iter().partition(|(_, k)| Some(k) == winner);
+  let voted = |voter: &&V| votes.iter().any(|(v, _)| v == *voter);
+  Consensus {
+    silent: voters.iter().filter(|v| !voted(v)).cloned().collect(),
+    value: value.map(|(_, value)| value),
+    agreed: agreed.into_iter().map(|(v, _)| v.clone()).collect(),
+    disagreed: disagreed.into_iter().map(|(v, _)| v.clone()).collect(),
+  }
+}
+
+/// more than half of `peers`, with an even count a tie isn't a majority
 pub fn peer_majority(peers: usize) -> usize {
-  peers.div_ceil(&2)
+  peers / 2 + 1
 }
 
 #[cfg(test)]
@@ -96,12 +150,52 @@ mod tests {
     Ok(())
   }
 
+  #[async_std::test]
+  async fn vote_test() -> Result<()> {
+    let voters = ["a", "b", "c", "d", "e"];
+    // "a" repeating itself, "b" voting twice and "x" not being asked don't make a majority
+    let iterable = vec![("a", 1), ("a", 1), ("b", 2), ("b", 1), ("x", 1), ("c", 3)];
+    let res = vote_by(stream::from_iter(iterable), &voters, 2, |&v| v).await;
+    let no_consensus = Consensus {
+      value: None,
+      agreed: vec![],
+      disagreed: vec!["a", "b", "c"],
+      silent: vec!["d", "e"],
+    };
+    assert_eq!(res, no_consensus);
+
+    // votes after the majority was reached are still counted
+    let iterable = vec![("a", 1), ("b", 2), ("a", 2), ("c", 1), ("d", 2)];
+    let res = vote_by(stream::from_iter(iterable), &voters, 2, |&v| v).await;
+    let consensus = Consensus {
+      value: Some(1),
+      agreed: vec!["a", "c"],
+      disagreed: vec!["b", "d"],
+      silent: vec!["e"],
+    };
+    assert_eq!(res, consensus);
+
+    // every voter voted, later votes aren't waited for
+    let votes = stream::from_iter(vec![("a", 1), ("b", 1)]).chain(stream::pending());
+    let res = vote_by(votes, &voters[..2], 2, |&v| v).await;
+    assert_eq!(res.value, Some(1));
+
+    // with an even number of voters half of them is a tie, not a majority
+    let iterable = vec![("a", 1), ("b", 1), ("c", 2), ("d", 2)];
+    let group_num = peer_majority(4);
+    let res = vote_by(stream::from_iter(iterable), &voters[..4], group_num, |&v| v).await;
+    assert_eq!(res.value, None);
+    let iterable = vec![("a", 1), ("b", 1), ("c", 2), ("d", 1)];
+    let res = vote_by(stream::from_iter(iterable), &voters[..4], group_num, |&v| v).await;
+    assert_eq!(res.value, Some(1));
+    Ok(())
+  }
+
   #[test]
   fn majority_test() -> Result<()> {
-    let peers = 3;
-    let majority = peer_majority(peers);
-
-    assert_eq!(majority, 2);
+    assert_eq!(peer_majority(1), 1);
+    assert_eq!(peer_majority(3), 2);
+    assert_eq!(peer_majority(4), 3);
     Ok(())
   }
 }
@@ -5,7 +5,7 @@ pub trait ShortDisplay: Display {
     let long = format!("{}", self);
     let last = long.len();
     let first = max(0, last as i64 - 12) as usize;
-    (&long[first..last]).to_owned()
+    long[first..last].to_owned()
   }
 }
 